@@ -0,0 +1,335 @@
+//! AF_PACKET backend for hosts and drivers without AF_XDP support.
+//!
+//! Frames are received through a TPACKET_V3 memory mapped ring and transmitted with `send(2)`
+//! on the same socket.
+use super::{Interface, PacketIo, PacketRx, PacketTx};
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
+
+// From linux/if_packet.h, not exported by libc.
+const PACKET_RX_RING: libc::c_int = 5;
+const PACKET_VERSION: libc::c_int = 10;
+const TPACKET_V3: libc::c_int = 2;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const PACKET_OUTGOING: u8 = 4;
+
+#[repr(C)]
+struct TpacketReq3 {
+    tp_block_size: u32,
+    tp_block_nr: u32,
+    tp_frame_size: u32,
+    tp_frame_nr: u32,
+    tp_retire_blk_tov: u32,
+    tp_sizeof_priv: u32,
+    tp_feature_req_word: u32,
+}
+
+#[repr(C)]
+struct TpacketBdTs {
+    ts_sec: u32,
+    ts_nsec: u32,
+}
+
+#[repr(C)]
+struct TpacketHdrV1 {
+    block_status: u32,
+    num_pkts: u32,
+    offset_to_first_pkt: u32,
+    blk_len: u32,
+    seq_num: u64,
+    ts_first_pkt: TpacketBdTs,
+    ts_last_pkt: TpacketBdTs,
+}
+
+#[repr(C)]
+struct TpacketBlockDesc {
+    version: u32,
+    offset_to_priv: u32,
+    hdr: TpacketHdrV1,
+}
+
+#[repr(C)]
+struct Tpacket3Hdr {
+    tp_next_offset: u32,
+    tp_sec: u32,
+    tp_nsec: u32,
+    tp_snaplen: u32,
+    tp_len: u32,
+    tp_status: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    tp_rxhash: u32,
+    tp_vlan_tci: u32,
+    tp_vlan_tpid: u16,
+    tp_padding: u16,
+    tp_padding2: [u8; 8],
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+struct Socket(RawFd);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// A TPACKET_V3 receive ring mapped into our address space.
+struct Ring {
+    addr: *mut u8,
+    block_size: usize,
+    block_count: usize,
+}
+
+impl Ring {
+    fn len(&self) -> usize {
+        self.block_size * self.block_count
+    }
+
+    fn block(&self, index: usize) -> *mut TpacketBlockDesc {
+        unsafe { self.addr.add(index * self.block_size) as *mut TpacketBlockDesc }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len()) };
+    }
+}
+
+/// An AF_PACKET socket bound to an interface.
+pub struct AfPacketIo {
+    socket: Socket,
+    ring: Ring,
+    ifname: String,
+}
+
+impl AfPacketIo {
+    pub fn new(ifname: &str) -> io::Result<Self> {
+        let block_size: u32 = 1 << 17;
+        let block_count: u32 = 64;
+        let frame_size: u32 = 2048;
+        let retire_blk_tov_ms: u32 = 10;
+
+        let c_ifname =
+            CString::new(ifname).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ifindex = unsafe { libc::if_nametoindex(c_ifname.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let socket = Socket(cvt(unsafe {
+            libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol as libc::c_int)
+        })?);
+
+        let version = TPACKET_V3;
+        cvt(unsafe {
+            libc::setsockopt(
+                socket.0,
+                libc::SOL_PACKET,
+                PACKET_VERSION,
+                &version as *const _ as *const libc::c_void,
+                mem::size_of_val(&version) as libc::socklen_t,
+            )
+        })?;
+
+        let req = TpacketReq3 {
+            tp_block_size: block_size,
+            tp_block_nr: block_count,
+            tp_frame_size: frame_size,
+            tp_frame_nr: block_size / frame_size * block_count,
+            tp_retire_blk_tov: retire_blk_tov_ms,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        cvt(unsafe {
+            libc::setsockopt(
+                socket.0,
+                libc::SOL_PACKET,
+                PACKET_RX_RING,
+                &req as *const _ as *const libc::c_void,
+                mem::size_of::<TpacketReq3>() as libc::socklen_t,
+            )
+        })?;
+
+        let ring_len = (block_size * block_count) as usize;
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                ring_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_LOCKED,
+                socket.0,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ring = Ring {
+            addr: addr as *mut u8,
+            block_size: block_size as usize,
+            block_count: block_count as usize,
+        };
+
+        let mut sll: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        sll.sll_family = libc::AF_PACKET as u16;
+        sll.sll_protocol = protocol;
+        sll.sll_ifindex = ifindex as i32;
+        cvt(unsafe {
+            libc::bind(
+                socket.0,
+                &sll as *const _ as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        })?;
+
+        Ok(AfPacketIo {
+            socket,
+            ring,
+            ifname: ifname.to_string(),
+        })
+    }
+}
+
+impl PacketIo for AfPacketIo {
+    type Tx = AfPacketTx;
+    type Rx = AfPacketRx;
+
+    fn split(self) -> (AfPacketTx, AfPacketRx) {
+        let socket = Arc::new(self.socket);
+        let tx = AfPacketTx {
+            socket: socket.clone(),
+            buf: vec![0; 2048],
+        };
+        let rx = AfPacketRx {
+            socket,
+            ring: self.ring,
+            current_block: 0,
+        };
+        (tx, rx)
+    }
+
+    fn interface(&self) -> Option<Interface> {
+        Some(Interface {
+            name: self.ifname.clone(),
+            queue: None,
+        })
+    }
+}
+
+/// Transmit half of an `AfPacketIo`.
+pub struct AfPacketTx {
+    socket: Arc<Socket>,
+    buf: Vec<u8>,
+}
+
+impl PacketTx for AfPacketTx {
+    fn send_batch(
+        &mut self,
+        count: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> usize,
+    ) -> io::Result<usize> {
+        let mut sent = 0;
+        while sent < count {
+            let len = fill(&mut self.buf);
+            if len == 0 {
+                break;
+            }
+            while unsafe {
+                libc::send(
+                    self.socket.0,
+                    self.buf.as_ptr() as *const libc::c_void,
+                    len,
+                    0,
+                )
+            } < 0
+            {
+                // The frame has already been taken from `fill`, so retry until the qdisc
+                // has room for it rather than dropping it.
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::ENOBUFS) | Some(libc::EAGAIN) | Some(libc::EINTR) => continue,
+                    _ => return Err(err),
+                }
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+/// Receive half of an `AfPacketIo`.
+pub struct AfPacketRx {
+    socket: Arc<Socket>,
+    ring: Ring,
+    current_block: usize,
+}
+
+// The ring is only ever accessed through the receive half that owns it.
+unsafe impl Send for AfPacketRx {}
+
+impl AfPacketRx {
+    fn block_ready(&self) -> bool {
+        let block = self.ring.block(self.current_block);
+        let status = unsafe { ptr::read_volatile(&(*block).hdr.block_status) };
+        fence(Ordering::Acquire);
+        status & TP_STATUS_USER != 0
+    }
+}
+
+impl PacketRx for AfPacketRx {
+    fn recv_batch(&mut self, timeout_ms: i32, handler: &mut dyn FnMut(&[u8])) -> io::Result<usize> {
+        if !self.block_ready() {
+            let mut pfd = libc::pollfd {
+                fd: self.socket.0,
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            cvt(unsafe { libc::poll(&mut pfd, 1, timeout_ms) })?;
+            if !self.block_ready() {
+                return Ok(0);
+            }
+        }
+
+        let block = self.ring.block(self.current_block);
+        let (num_pkts, first) =
+            unsafe { ((*block).hdr.num_pkts, (*block).hdr.offset_to_first_pkt) };
+
+        let mut frames_rcvd = 0;
+        let mut pkt = unsafe { (block as *mut u8).add(first as usize) };
+        for _ in 0..num_pkts {
+            let hdr = pkt as *const Tpacket3Hdr;
+            unsafe {
+                // The sockaddr_ll follows the header, aligned to TPACKET_ALIGNMENT.
+                let sll = pkt.add(mem::size_of::<Tpacket3Hdr>()) as *const libc::sockaddr_ll;
+                if (*sll).sll_pkttype != PACKET_OUTGOING {
+                    let data = pkt.add((*hdr).tp_mac as usize);
+                    handler(std::slice::from_raw_parts(data, (*hdr).tp_snaplen as usize));
+                    frames_rcvd += 1;
+                }
+                pkt = pkt.add((*hdr).tp_next_offset as usize);
+            }
+        }
+
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(&mut (*block).hdr.block_status, TP_STATUS_KERNEL) };
+        self.current_block = (self.current_block + 1) % self.ring.block_count;
+
+        Ok(frames_rcvd)
+    }
+}
@@ -0,0 +1,120 @@
+//! Packet I/O backends.
+//!
+//! The scanner only needs two things from the network: a way to put Ethernet frames on the
+//! wire and a way to get Ethernet frames back. `PacketIo` captures that, so the same send and
+//! receive loops run over AF_XDP, AF_PACKET, an in-memory loopback or a simulated network.
+pub mod afpacket;
+pub mod loopback;
+pub mod sim;
+pub mod umem;
+pub mod xdp;
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// The interface, and queue of it if the backend binds to one, that frames are received on.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub queue: Option<u32>,
+}
+
+impl fmt::Display for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.queue {
+            Some(queue) => write!(f, "{}/{}", self.name, queue),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl FromStr for Interface {
+    type Err = String;
+
+    /// Parses an interface as written by `Display`: `name` or `name/queue`. Interface names
+    /// can't contain slashes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, queue) = match s.split_once('/') {
+            Some((name, queue)) => {
+                let queue = queue
+                    .parse()
+                    .map_err(|_| format!("invalid interface {}", s))?;
+                (name, Some(queue))
+            }
+            None => (s, None),
+        };
+        if name.is_empty() {
+            return Err(format!("invalid interface {}", s));
+        }
+        Ok(Interface {
+            name: name.to_string(),
+            queue,
+        })
+    }
+}
+
+/// Transmit half of a packet I/O backend.
+pub trait PacketTx: Send {
+    /// Transmits up to `count` frames.
+    ///
+    /// `fill` is called once per frame with a buffer to write the frame into and returns the
+    /// length of the frame written. Returning 0 ends the batch early, and that buffer is not
+    /// sent. `fill` is only called for frames the backend has room for, so the return value,
+    /// the number of frames handed to the backend, may be less than `count`.
+    fn send_batch(
+        &mut self,
+        count: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> usize,
+    ) -> io::Result<usize>;
+
+    /// Frames handed to the kernel that it has not finished transmitting, which is always 0
+    /// for backends that transmit synchronously.
+    fn in_flight(&self) -> usize {
+        0
+    }
+}
+
+/// Drop counters of an AF_XDP socket, `struct xdp_statistics` from `linux/if_xdp.h`. The
+/// kernel counts them from when the socket was created.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct XdpStats {
+    /// Frames dropped for reasons other than invalid descriptors.
+    pub rx_dropped: u64,
+    pub rx_invalid_descs: u64,
+    pub tx_invalid_descs: u64,
+    /// Frames dropped because the RX ring was full. 0 before Linux 5.9, as are the others below.
+    pub rx_ring_full: u64,
+    /// Times the fill ring was found empty, so a frame could not be received.
+    pub rx_fill_ring_empty_descs: u64,
+    /// Times the TX ring was found empty when the driver looked for frames to send.
+    pub tx_ring_empty_descs: u64,
+}
+
+/// Receive half of a packet I/O backend.
+pub trait PacketRx: Send {
+    /// Waits up to `timeout_ms` for frames to arrive and passes each one to `handler`.
+    ///
+    /// Returns the number of frames received, which is 0 if the timeout expired.
+    fn recv_batch(&mut self, timeout_ms: i32, handler: &mut dyn FnMut(&[u8])) -> io::Result<usize>;
+
+    /// The drop counters of the AF_XDP socket frames are received on, or `None` for other
+    /// backends.
+    fn xdp_stats(&mut self) -> io::Result<Option<XdpStats>> {
+        Ok(None)
+    }
+}
+
+/// A packet I/O backend that can be split into independent transmit and receive halves, so
+/// that sending and receiving can run on separate threads.
+pub trait PacketIo {
+    type Tx: PacketTx + 'static;
+    type Rx: PacketRx + 'static;
+
+    fn split(self) -> (Self::Tx, Self::Rx);
+
+    /// The interface frames are received on, or `None` for backends without one.
+    fn interface(&self) -> Option<Interface> {
+        None
+    }
+}
@@ -0,0 +1,670 @@
+//! Simulated network backend.
+//!
+//! `SimNetwork` answers probes on behalf of a configurable set of hosts, and with ICMP time
+//! exceeded errors on behalf of the routers on the way to them, with optional loss, latency,
+//! duplicated replies and spoofed traffic. Randomness comes from a seeded generator, so a given
+//! configuration and probe order always produce the same replies.
+use super::{PacketIo, PacketRx, PacketTx};
+use crate::packet::{self, checksum_add, checksum_fold, ipv6_octets};
+use crate::rng::Rng;
+use crate::Target;
+use etherparse::PacketBuilder;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a simulated host answers a SYN, or a UDP datagram, to one of its ports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SimPort {
+    /// Answers SYNs with a SYN-ACK, and UDP datagrams not at all.
+    Open,
+    /// Answers SYNs with a RST, and UDP datagrams with an ICMP port unreachable error.
+    Closed,
+    /// Never answers.
+    Filtered,
+}
+
+/// A simulated host.
+#[derive(Clone, Debug)]
+pub struct SimHost {
+    ip: IpAddr,
+    ports: HashMap<u16, SimPort>,
+    default: SimPort,
+    open_rst_window: u16,
+    route: Vec<IpAddr>,
+}
+
+impl SimHost {
+    /// Creates a host whose ports are all closed.
+    pub fn new(ip: IpAddr) -> Self {
+        SimHost {
+            ip,
+            ports: HashMap::new(),
+            default: SimPort::Closed,
+            open_rst_window: 0,
+            route: vec![],
+        }
+    }
+
+    pub fn port(mut self, port: u16, state: SimPort) -> Self {
+        self.ports.insert(port, state);
+        self
+    }
+
+    pub fn open(self, port: u16) -> Self {
+        self.port(port, SimPort::Open)
+    }
+
+    /// Sets the state of every port not configured with `port` or `open`.
+    pub fn default_port(mut self, state: SimPort) -> Self {
+        self.default = state;
+        self
+    }
+
+    /// Sets the window of the RSTs open ports answer stray ACKs with. Stacks that set it to
+    /// anything but 0 give open ports away to window scans.
+    pub fn open_rst_window(mut self, window: u16) -> Self {
+        self.open_rst_window = window;
+        self
+    }
+
+    /// Sets the routers on the way to the host, nearest first. Probes whose TTL runs out before
+    /// reaching the host are answered with an ICMP time exceeded error by the router where it
+    /// does.
+    pub fn route(mut self, routers: Vec<IpAddr>) -> Self {
+        self.route = routers;
+        self
+    }
+
+    fn port_state(&self, port: u16) -> SimPort {
+        *self.ports.get(&port).unwrap_or(&self.default)
+    }
+}
+
+/// Network conditions applied to every probe.
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    /// Seed for every random decision the network makes.
+    pub seed: u64,
+    /// Probability that a probe or its reply is lost.
+    pub loss: f64,
+    /// Delay between a probe being sent and its reply arriving.
+    pub latency: Duration,
+    /// Maximum extra random delay added to `latency`.
+    pub jitter: Duration,
+    /// Probability that a reply is delivered twice.
+    pub duplicate: f64,
+    /// Probability, per probe, of also delivering a SYN-ACK from an address that was never
+    /// probed.
+    pub spoof: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 0,
+            loss: 0.0,
+            latency: Duration::from_millis(1),
+            jitter: Duration::from_millis(0),
+            duplicate: 0.0,
+            spoof: 0.0,
+        }
+    }
+}
+
+// A TCP connection, as host, host port and scanner port.
+type Connection = (IpAddr, u16, u16);
+
+/// Record of the frames a `SimNetwork` has seen, shared with the network after it is split.
+#[derive(Clone, Debug, Default)]
+pub struct SimLog {
+    probes: Arc<Mutex<Vec<Target>>>,
+    sources: Arc<Mutex<Vec<SocketAddr>>>,
+    resets: Arc<Mutex<Vec<Target>>>,
+    // Connections answered with a SYN-ACK and not reset yet, with the sequence number a RST
+    // must carry to tear them down.
+    half_open: Arc<Mutex<HashMap<Connection, u32>>>,
+}
+
+impl SimLog {
+    /// Every probe received, in the order it was sent.
+    pub fn probes(&self) -> Vec<Target> {
+        self.probes.lock().unwrap().clone()
+    }
+
+    /// Source address and port of every probe received, in the same order as `probes`.
+    pub fn sources(&self) -> Vec<SocketAddr> {
+        self.sources.lock().unwrap().clone()
+    }
+
+    /// Every RST received, in the order it was sent.
+    pub fn resets(&self) -> Vec<Target> {
+        self.resets.lock().unwrap().clone()
+    }
+
+    /// Connections a host answered with a SYN-ACK that have not been torn down by a RST with
+    /// the right sequence number.
+    pub fn half_open(&self) -> Vec<Target> {
+        let half_open = self.half_open.lock().unwrap();
+        half_open
+            .keys()
+            .map(|&(ip, port, _)| Target { ip, port })
+            .collect()
+    }
+}
+
+/// The hosts of a `SimNetwork`, shared with the network after it is split, so they can be
+/// changed between scans.
+#[derive(Clone, Debug, Default)]
+pub struct SimHosts(Arc<Mutex<HashMap<IpAddr, SimHost>>>);
+
+impl SimHosts {
+    /// Adds `host`, replacing any host with the same address.
+    pub fn add(&self, host: SimHost) {
+        self.0.lock().unwrap().insert(host.ip, host);
+    }
+
+    pub fn remove(&self, ip: IpAddr) {
+        self.0.lock().unwrap().remove(&ip);
+    }
+
+    fn get(&self, ip: IpAddr) -> Option<SimHost> {
+        self.0.lock().unwrap().get(&ip).cloned()
+    }
+}
+
+struct SimState {
+    hosts: SimHosts,
+    config: SimConfig,
+    log: SimLog,
+    rng: Rng,
+    // Replies waiting for their delivery time. The counter keeps delivery order stable for
+    // replies due at the same instant.
+    pending: BinaryHeap<Reverse<(Instant, u64, Vec<u8>)>>,
+    counter: u64,
+}
+
+impl SimState {
+    fn schedule(&mut self, frame: Vec<u8>) {
+        let jitter_nanos = self.config.jitter.as_nanos() as u64;
+        let jitter = if jitter_nanos > 0 {
+            Duration::from_nanos(self.rng.next_u64() % jitter_nanos)
+        } else {
+            Duration::from_nanos(0)
+        };
+        let deliver_at = Instant::now() + self.config.latency + jitter;
+        self.counter += 1;
+        self.pending
+            .push(Reverse((deliver_at, self.counter, frame)));
+    }
+
+    /// Handles a frame sent by the scanner.
+    fn handle_frame(&mut self, frame: &[u8]) -> Option<()> {
+        let value = etherparse::PacketHeaders::from_ethernet_slice(frame).ok()?;
+        let link = value.link?;
+        let (src_ip, dst_ip, ttl) = match value.ip? {
+            etherparse::IpHeader::Version4(ipv4) => (
+                IpAddr::from(ipv4.source),
+                IpAddr::from(ipv4.destination),
+                ipv4.time_to_live,
+            ),
+            etherparse::IpHeader::Version6(ipv6) => (
+                IpAddr::from(ipv6.source),
+                IpAddr::from(ipv6.destination),
+                ipv6.hop_limit,
+            ),
+        };
+        let tcp = match value.transport? {
+            etherparse::TransportHeader::Tcp(tcp) => tcp,
+            etherparse::TransportHeader::Udp(udp) => {
+                self.log_probe(src_ip, udp.source_port, dst_ip, udp.destination_port);
+                return self.handle_datagram(frame, link, src_ip, dst_ip, ttl, &udp);
+            }
+        };
+        let probe = Target {
+            ip: dst_ip,
+            port: tcp.destination_port,
+        };
+        let connection = (dst_ip, tcp.destination_port, tcp.source_port);
+
+        if tcp.rst {
+            let mut half_open = self.log.half_open.lock().unwrap();
+            if half_open.get(&connection) == Some(&tcp.sequence_number) {
+                half_open.remove(&connection);
+            }
+            self.log.resets.lock().unwrap().push(probe);
+            return Some(());
+        }
+        self.log_probe(src_ip, tcp.source_port, dst_ip, tcp.destination_port);
+
+        if self.rng.chance(self.config.spoof) {
+            let spoofed_ip = IpAddr::V4(Ipv4Addr::from(self.rng.next_u64() as u32));
+            if self.hosts.get(spoofed_ip).is_none() {
+                let spoofed = reply(
+                    link.destination,
+                    link.source,
+                    spoofed_ip,
+                    src_ip,
+                    tcp.destination_port,
+                    tcp.source_port,
+                    &tcp,
+                    Answer::SynAck,
+                );
+                self.schedule(with_vlan_tags(frame, spoofed));
+            }
+        }
+
+        if self.rng.chance(self.config.loss) {
+            return Some(());
+        }
+        let host = match self.hosts.get(dst_ip) {
+            Some(host) => host,
+            None => return Some(()),
+        };
+        if let Some(error) = time_exceeded(&host, frame, link.destination, link.source, ttl) {
+            self.deliver(frame, error);
+            return Some(());
+        }
+        let state = host.port_state(tcp.destination_port);
+
+        // What an RFC 793 stack answers a segment for a connection that does not exist with.
+        let answer = match (state, tcp.syn, tcp.ack) {
+            (SimPort::Filtered, _, _) => None,
+            (SimPort::Open, true, false) => Some(Answer::SynAck),
+            (SimPort::Closed, true, false) => Some(Answer::Rst { window: 0 }),
+            // Stray ACKs are answered with a RST whatever the port's state.
+            (SimPort::Open, false, true) => Some(Answer::Rst {
+                window: host.open_rst_window,
+            }),
+            (SimPort::Closed, false, true) => Some(Answer::Rst { window: 0 }),
+            // Other segments without SYN or ACK are dropped by open ports only.
+            (SimPort::Closed, false, false) => Some(Answer::Rst { window: 0 }),
+            _ => None,
+        };
+        let answer = match answer {
+            Some(answer) => answer,
+            None => return Some(()),
+        };
+        if answer == Answer::SynAck {
+            let seq = tcp.sequence_number.wrapping_add(1);
+            self.log.half_open.lock().unwrap().insert(connection, seq);
+        }
+
+        let reply = reply(
+            link.destination,
+            link.source,
+            dst_ip,
+            src_ip,
+            tcp.destination_port,
+            tcp.source_port,
+            &tcp,
+            answer,
+        );
+        self.deliver(frame, reply);
+
+        Some(())
+    }
+
+    fn log_probe(&mut self, src_ip: IpAddr, src_port: u16, dst_ip: IpAddr, dst_port: u16) {
+        let probe = Target {
+            ip: dst_ip,
+            port: dst_port,
+        };
+        self.log.probes.lock().unwrap().push(probe);
+        self.log
+            .sources
+            .lock()
+            .unwrap()
+            .push(SocketAddr::new(src_ip, src_port));
+    }
+
+    /// Schedules a reply to `probe`, twice if it is duplicated.
+    fn deliver(&mut self, probe: &[u8], reply: Vec<u8>) {
+        let reply = with_vlan_tags(probe, reply);
+        if self.rng.chance(self.config.duplicate) {
+            self.schedule(reply.clone());
+        }
+        self.schedule(reply);
+    }
+
+    /// Handles a UDP datagram sent by the scanner.
+    fn handle_datagram(
+        &mut self,
+        frame: &[u8],
+        link: etherparse::Ethernet2Header,
+        src_ip: IpAddr,
+        dst_ip: IpAddr,
+        ttl: u8,
+        udp: &etherparse::UdpHeader,
+    ) -> Option<()> {
+        if self.rng.chance(self.config.loss) {
+            return Some(());
+        }
+        let host = self.hosts.get(dst_ip)?;
+        let (src_mac, dst_mac) = (link.destination, link.source);
+        let error = match time_exceeded(&host, frame, src_mac, dst_mac, ttl) {
+            Some(error) => error,
+            None if host.port_state(udp.destination_port) == SimPort::Closed => icmp_error(
+                src_mac,
+                dst_mac,
+                dst_ip,
+                src_ip,
+                IcmpType::PortUnreachable,
+                frame,
+            )?,
+            None => return Some(()),
+        };
+        self.deliver(frame, error);
+        Some(())
+    }
+}
+
+/// `reply`, tagged with the VLAN tags of `probe` as hosts on its VLAN would send it.
+fn with_vlan_tags(probe: &[u8], mut reply: Vec<u8>) -> Vec<u8> {
+    if let Some((_, ip)) = packet::ethernet_payload(probe) {
+        let tags_end = probe.len() - ip.len() - 2;
+        reply.splice(12..12, probe[12..tags_end].iter().cloned());
+    }
+    reply
+}
+
+/// The ICMP time exceeded error the router on the way to `host` where the TTL of `probe` runs
+/// out answers it with, if it runs out before reaching the host.
+fn time_exceeded(
+    host: &SimHost,
+    probe: &[u8],
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    ttl: u8,
+) -> Option<Vec<u8>> {
+    let router = *host.route.get(usize::from(ttl.checked_sub(1)?))?;
+    let (_, ip) = packet::ethernet_payload(probe)?;
+    let src_ip = match ip[0] >> 4 {
+        4 => IpAddr::from([ip[12], ip[13], ip[14], ip[15]]),
+        _ => {
+            let mut src_ip = [0; 16];
+            src_ip.copy_from_slice(&ip[8..24]);
+            IpAddr::from(src_ip)
+        }
+    };
+    icmp_error(
+        src_mac,
+        dst_mac,
+        router,
+        src_ip,
+        IcmpType::TimeExceeded,
+        probe,
+    )
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum IcmpType {
+    TimeExceeded,
+    PortUnreachable,
+}
+
+/// An ICMP, or ICMPv6, error about `probe`, quoting its IP header and the 8 bytes after it as
+/// routers do.
+fn icmp_error(
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    icmp_type: IcmpType,
+    probe: &[u8],
+) -> Option<Vec<u8>> {
+    let (_, ip) = packet::ethernet_payload(probe)?;
+    let ipv6 = ip[0] >> 4 == 6;
+    let quoted_len = if ipv6 {
+        40
+    } else {
+        usize::from(ip[0] & 0x0f) * 4
+    } + 8;
+    let quoted = ip.get(..quoted_len)?;
+    let (icmp_type, code) = match (icmp_type, ipv6) {
+        (IcmpType::TimeExceeded, false) => (11, 0),
+        (IcmpType::PortUnreachable, false) => (3, 3),
+        (IcmpType::TimeExceeded, true) => (3, 0),
+        (IcmpType::PortUnreachable, true) => (1, 4),
+    };
+    let mut icmp = vec![icmp_type, code, 0, 0, 0, 0, 0, 0];
+    icmp.extend_from_slice(quoted);
+
+    let mut frame = Vec::with_capacity(14 + 40 + icmp.len());
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&src_mac);
+    if ipv6 {
+        let (src_ip, dst_ip) = (ipv6_octets(src_ip), ipv6_octets(dst_ip));
+        let icmp_len = icmp.len() as u16;
+        let sum = checksum_add(checksum_add(0, &src_ip), &dst_ip) + u32::from(icmp_len) + 58;
+        let checksum = checksum_fold(checksum_add(sum, &icmp));
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        frame.extend_from_slice(&0x86ddu16.to_be_bytes());
+        frame.extend_from_slice(&0x6000_0000u32.to_be_bytes());
+        frame.extend_from_slice(&icmp_len.to_be_bytes());
+        frame.extend_from_slice(&[58, 64]);
+        frame.extend_from_slice(&src_ip);
+        frame.extend_from_slice(&dst_ip);
+    } else {
+        let (src_ip, dst_ip) = match (src_ip, dst_ip) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => (src_ip, dst_ip),
+            _ => return None,
+        };
+        let checksum = checksum_fold(checksum_add(0, &icmp));
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut header = [0; 20];
+        header[0] = 0x45;
+        header[2..4].copy_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+        header[8] = 64;
+        header[9] = 1;
+        header[12..16].copy_from_slice(&src_ip.octets());
+        header[16..20].copy_from_slice(&dst_ip.octets());
+        let checksum = checksum_fold(checksum_add(0, &header));
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&header);
+    }
+    frame.extend_from_slice(&icmp);
+    Some(frame)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Answer {
+    SynAck,
+    Rst { window: u16 },
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reply(
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    probe: &etherparse::TcpHeader,
+    answer: Answer,
+) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(src_mac, dst_mac);
+    let builder = match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => builder.ipv4(src.octets(), dst.octets(), 64),
+        (src, dst) => {
+            let src = match src {
+                IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+                IpAddr::V6(ipv6) => ipv6,
+            };
+            let dst = match dst {
+                IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+                IpAddr::V6(ipv6) => ipv6,
+            };
+            builder.ipv6(src.octets(), dst.octets(), 64)
+        }
+    };
+    // SYNs and FINs take up a sequence number each.
+    let probe_end = probe
+        .sequence_number
+        .wrapping_add(u32::from(probe.syn) + u32::from(probe.fin));
+    let builder = match answer {
+        Answer::SynAck => builder
+            .tcp(src_port, dst_port, 0, 65535)
+            .syn()
+            .ack(probe_end),
+        // RSTs answering an ACK take their sequence number from it, others acknowledge the
+        // segment instead, as RFC 793 has it.
+        Answer::Rst { window } if probe.ack => builder
+            .tcp(src_port, dst_port, probe.acknowledgment_number, window)
+            .rst(),
+        Answer::Rst { window } => builder
+            .tcp(src_port, dst_port, 0, window)
+            .rst()
+            .ack(probe_end),
+    };
+
+    let mut result = Vec::<u8>::with_capacity(builder.size(0));
+    builder.write(&mut result, &[]).unwrap();
+    result
+}
+
+/// A simulated network of hosts, usable anywhere a `PacketIo` backend is.
+pub struct SimNetwork {
+    hosts: SimHosts,
+    config: SimConfig,
+    log: SimLog,
+}
+
+impl SimNetwork {
+    pub fn new(config: SimConfig) -> Self {
+        SimNetwork {
+            hosts: SimHosts::default(),
+            config,
+            log: SimLog::default(),
+        }
+    }
+
+    pub fn add_host(&mut self, host: SimHost) {
+        self.hosts.add(host);
+    }
+
+    /// Returns a handle to the hosts, which remains valid after the network is split.
+    pub fn hosts(&self) -> SimHosts {
+        self.hosts.clone()
+    }
+
+    /// Returns a handle to the probe log, which remains valid after the network is split.
+    pub fn log(&self) -> SimLog {
+        self.log.clone()
+    }
+}
+
+impl PacketIo for SimNetwork {
+    type Tx = SimTx;
+    type Rx = SimRx;
+
+    fn split(self) -> (SimTx, SimRx) {
+        let rng = Rng::new(self.config.seed);
+        let shared = Arc::new((
+            Mutex::new(SimState {
+                hosts: self.hosts,
+                config: self.config,
+                log: self.log,
+                rng,
+                pending: BinaryHeap::new(),
+                counter: 0,
+            }),
+            Condvar::new(),
+        ));
+
+        let tx = SimTx {
+            shared: shared.clone(),
+            buf: vec![0; 2048],
+        };
+        let rx = SimRx { shared };
+        (tx, rx)
+    }
+}
+
+/// Transmit half of a `SimNetwork`.
+pub struct SimTx {
+    shared: Arc<(Mutex<SimState>, Condvar)>,
+    buf: Vec<u8>,
+}
+
+impl PacketTx for SimTx {
+    fn send_batch(
+        &mut self,
+        count: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> usize,
+    ) -> io::Result<usize> {
+        // The frames are filled in before taking the network, so that a slow `fill` doesn't keep
+        // the receive half from the replies to earlier batches.
+        let mut frames = Vec::with_capacity(count);
+        while frames.len() < count {
+            let len = fill(&mut self.buf);
+            if len == 0 {
+                break;
+            }
+            frames.push(self.buf[..len].to_vec());
+        }
+
+        let (state, cvar) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        for frame in &frames {
+            state.handle_frame(frame);
+        }
+        cvar.notify_all();
+        Ok(frames.len())
+    }
+}
+
+/// Receive half of a `SimNetwork`.
+pub struct SimRx {
+    shared: Arc<(Mutex<SimState>, Condvar)>,
+}
+
+impl PacketRx for SimRx {
+    fn recv_batch(&mut self, timeout_ms: i32, handler: &mut dyn FnMut(&[u8])) -> io::Result<usize> {
+        let max_batch_size = 64;
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+
+        let (state, cvar) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        let mut frames = vec![];
+
+        loop {
+            let now = Instant::now();
+            while frames.len() < max_batch_size {
+                match state.pending.peek() {
+                    Some(Reverse((deliver_at, _, _))) if *deliver_at <= now => {
+                        let Reverse((_, _, frame)) = state.pending.pop().unwrap();
+                        frames.push(frame);
+                    }
+                    _ => break,
+                }
+            }
+            if !frames.is_empty() || now >= deadline {
+                break;
+            }
+
+            let wake_at = match state.pending.peek() {
+                Some(Reverse((deliver_at, _, _))) => (*deliver_at).min(deadline),
+                None => deadline,
+            };
+            state = cvar
+                .wait_timeout(state, wake_at.saturating_duration_since(now))
+                .unwrap()
+                .0;
+        }
+        drop(state);
+
+        for frame in &frames {
+            handler(frame);
+        }
+        Ok(frames.len())
+    }
+}
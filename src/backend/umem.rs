@@ -0,0 +1,112 @@
+//! Ownership of the frames of a UMEM shared by the transmit and receive halves of a socket.
+//!
+//! A UMEM is one memory mapped area, divided into frames of equal size, that the kernel reads
+//! frames to transmit from and writes received frames to. Each half of an `XdpIo` runs on its
+//! own thread and must only touch its own frames, so every frame is allocated to one owner when
+//! the socket is split and keeps it for the life of the socket. The split is static: TX gets the
+//! first half of the frames and RX the rest, and neither half ever lends a frame to the other.
+//! Frames the kernel holds on an owner's behalf, in the TX or fill ring, still belong to that
+//! owner.
+//!
+//! Each half checks the frames it writes to, reads from or gets back from the kernel against
+//! the allocator, which panics on a frame owned by anyone else rather than letting both halves
+//! use it.
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+const FREE: u8 = 0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Owner {
+    Tx = 1,
+    Rx = 2,
+}
+
+impl Owner {
+    fn from_u8(owner: u8) -> Option<Owner> {
+        match owner {
+            1 => Some(Owner::Tx),
+            2 => Some(Owner::Rx),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Owner::Tx => f.write_str("TX"),
+            Owner::Rx => f.write_str("RX"),
+        }
+    }
+}
+
+/// Tracks the owner of every frame of a UMEM. Clones share the same frames.
+#[derive(Clone, Debug)]
+pub struct FrameAllocator {
+    frame_size: usize,
+    owners: Arc<[AtomicU8]>,
+}
+
+impl FrameAllocator {
+    /// An allocator for a UMEM of `frame_count` free frames of `frame_size` bytes.
+    pub fn new(frame_count: usize, frame_size: usize) -> Self {
+        FrameAllocator {
+            frame_size,
+            owners: (0..frame_count).map(|_| AtomicU8::new(FREE)).collect(),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Index of the frame `addr` points into, which may be past any headroom at its start.
+    fn index(&self, addr: usize) -> usize {
+        let index = addr / self.frame_size;
+        assert!(
+            index < self.owners.len(),
+            "address {} is outside the UMEM",
+            addr
+        );
+        index
+    }
+
+    /// Allocates up to `count` free frames to `owner` and returns their addresses.
+    pub fn alloc(&self, owner: Owner, count: usize) -> Vec<usize> {
+        let mut addrs = Vec::with_capacity(count);
+        for (index, frame) in self.owners.iter().enumerate() {
+            if addrs.len() == count {
+                break;
+            }
+            let claimed =
+                frame.compare_exchange(FREE, owner as u8, Ordering::AcqRel, Ordering::Relaxed);
+            if claimed.is_ok() {
+                addrs.push(index * self.frame_size);
+            }
+        }
+        addrs
+    }
+
+    /// The owner of the frame `addr` points into, or `None` if it is free.
+    pub fn owner(&self, addr: usize) -> Option<Owner> {
+        Owner::from_u8(self.owners[self.index(addr)].load(Ordering::Acquire))
+    }
+
+    /// Panics unless the frame `addr` points into is owned by `owner`.
+    pub fn check(&self, addr: usize, owner: Owner) {
+        let actual = self.owner(addr);
+        assert!(
+            actual == Some(owner),
+            "{} used frame {}, which is owned by {:?}",
+            owner,
+            addr / self.frame_size,
+            actual
+        );
+    }
+}
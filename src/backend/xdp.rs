@@ -0,0 +1,517 @@
+//! AF_XDP backend built on `xsk_rs`.
+use super::umem::{FrameAllocator, Owner};
+use super::{Interface, PacketIo, PacketRx, PacketTx, XdpStats};
+use log::{debug, warn};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::num::NonZeroU32;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::slice;
+use std::str::FromStr;
+use std::sync::Arc;
+use xsk_rs::{
+    socket::{Config as SocketConfig, *},
+    umem::{Config as UmemConfig, *},
+    BindFlags, LibbpfFlags, XdpFlags,
+};
+
+fn xsk_error<E: Into<Box<dyn Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// How the XDP program is attached and how frames get between the driver and the UMEM, from
+/// fastest to slowest.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XdpMode {
+    /// Native XDP, with the driver reading and writing frames in the UMEM directly.
+    ZeroCopy,
+    /// Native XDP, with frames copied between the driver's buffers and the UMEM.
+    Copy,
+    /// Generic XDP, run by the network stack after it has allocated an skb for every frame.
+    /// Works with every driver, but is much slower.
+    Generic,
+}
+
+impl XdpMode {
+    /// The modes tried, in order, when none is forced.
+    pub const FALLBACKS: [XdpMode; 3] = [XdpMode::ZeroCopy, XdpMode::Copy, XdpMode::Generic];
+
+    fn flags(self) -> (XdpFlags, BindFlags) {
+        match self {
+            XdpMode::ZeroCopy => (
+                XdpFlags::XDP_FLAGS_DRV_MODE,
+                BindFlags::XDP_ZEROCOPY | BindFlags::XDP_USE_NEED_WAKEUP,
+            ),
+            XdpMode::Copy => (
+                XdpFlags::XDP_FLAGS_DRV_MODE,
+                BindFlags::XDP_COPY | BindFlags::XDP_USE_NEED_WAKEUP,
+            ),
+            XdpMode::Generic => (
+                XdpFlags::XDP_FLAGS_SKB_MODE,
+                BindFlags::XDP_COPY | BindFlags::XDP_USE_NEED_WAKEUP,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for XdpMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            XdpMode::ZeroCopy => "zero-copy",
+            XdpMode::Copy => "copy",
+            XdpMode::Generic => "generic",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for XdpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero-copy" => Ok(XdpMode::ZeroCopy),
+            "copy" => Ok(XdpMode::Copy),
+            "generic" | "skb" => Ok(XdpMode::Generic),
+            _ => Err(format!("invalid XDP mode {}", s)),
+        }
+    }
+}
+
+// From linux/socket.h and linux/if_xdp.h, not exported by libc.
+const SOL_XDP: libc::c_int = 283;
+const XDP_STATISTICS: libc::c_int = 7;
+
+const SIOCETHTOOL: libc::c_ulong = 0x8946;
+const ETHTOOL_GDRVINFO: u32 = 0x03;
+
+/// `struct ethtool_drvinfo` from `linux/ethtool.h`.
+#[repr(C)]
+struct EthtoolDrvInfo {
+    cmd: u32,
+    driver: [u8; 32],
+    version: [u8; 32],
+    fw_version: [u8; 32],
+    bus_info: [u8; 32],
+    erom_version: [u8; 32],
+    reserved2: [u8; 12],
+    n_priv_flags: u32,
+    n_stats: u32,
+    testinfo_len: u32,
+    eedump_len: u32,
+    regdump_len: u32,
+}
+
+/// `struct ifreq` with the `ifr_data` member of its union.
+#[repr(C)]
+struct IfReqData {
+    name: [u8; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    // Pads the union to at least its size in the kernel.
+    _padding: [u8; 16],
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// The driver of an interface, as `ethtool -i` reports it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DriverInfo {
+    pub driver: String,
+    pub version: String,
+    pub firmware_version: String,
+    /// Empty for virtual interfaces.
+    pub bus_info: String,
+}
+
+impl DriverInfo {
+    pub fn query(ifname: &str) -> io::Result<Self> {
+        if ifname.len() >= libc::IFNAMSIZ {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("interface name {} is too long", ifname),
+            ));
+        }
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut drvinfo: EthtoolDrvInfo = unsafe { mem::zeroed() };
+        drvinfo.cmd = ETHTOOL_GDRVINFO;
+        let mut ifreq = IfReqData {
+            name: [0; libc::IFNAMSIZ],
+            data: &mut drvinfo as *mut _ as *mut libc::c_void,
+            _padding: [0; 16],
+        };
+        ifreq.name[..ifname.len()].copy_from_slice(ifname.as_bytes());
+        if unsafe { libc::ioctl(fd.as_raw_fd(), SIOCETHTOOL as _, &mut ifreq) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(DriverInfo {
+            driver: c_string(&drvinfo.driver),
+            version: c_string(&drvinfo.version),
+            firmware_version: c_string(&drvinfo.fw_version),
+            bus_info: c_string(&drvinfo.bus_info),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct XdpConfig {
+    /// The mode to bind in, or `None` for the first of `XdpMode::FALLBACKS` the driver
+    /// supports.
+    pub mode: Option<XdpMode>,
+    /// Back the UMEM with huge pages, which must have been reserved, e.g. through
+    /// `/proc/sys/vm/nr_hugepages`.
+    pub huge_pages: bool,
+}
+
+/// The memory of a UMEM, which both halves of an `XdpIo` share.
+struct UmemArea {
+    addr: *mut u8,
+    frames: FrameAllocator,
+    // Unmaps the area once both halves, and so the socket, are gone.
+    _umem: Umem<'static>,
+}
+
+// Each half only touches the frames `frames` says it owns.
+unsafe impl Send for UmemArea {}
+unsafe impl Sync for UmemArea {}
+
+impl UmemArea {
+    /// The `len` bytes at `addr`, which must lie in a frame `owner` owns.
+    ///
+    /// The caller must not keep any other slice of the same frame while it holds this one.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn frame(&self, addr: usize, len: usize, owner: Owner) -> &mut [u8] {
+        self.frames.check(addr, owner);
+        let frame_size = self.frames.frame_size();
+        assert!(
+            addr % frame_size + len <= frame_size,
+            "frame at {} overruns its end",
+            addr
+        );
+        slice::from_raw_parts_mut(self.addr.add(addr), len)
+    }
+}
+
+/// An AF_XDP socket bound to a single queue of an interface.
+///
+/// Half of the frames of the UMEM are allocated to transmitting, and the other half are given
+/// to the fill queue for receiving, see `FrameAllocator`.
+pub struct XdpIo {
+    fill_q: FillQueue<'static>,
+    comp_q: CompQueue<'static>,
+    tx_q: TxQueue<'static>,
+    rx_q: RxQueue<'static>,
+    frames: Vec<FrameDesc<'static>>,
+    mode: XdpMode,
+    interface: Interface,
+    umem: Arc<UmemArea>,
+}
+
+impl XdpIo {
+    /// Binds to queue `queue_id` of `ifname` in the fastest mode the driver supports.
+    pub fn new(ifname: &str, queue_id: u32) -> io::Result<Self> {
+        XdpIo::with_config(ifname, queue_id, &XdpConfig::default())
+    }
+
+    /// Binds to queue `queue_id` of `ifname` in `config.mode`, or if there is none, in the
+    /// first of `XdpMode::FALLBACKS` the driver supports.
+    pub fn with_config(ifname: &str, queue_id: u32, config: &XdpConfig) -> io::Result<Self> {
+        if let Some(mode) = config.mode {
+            return XdpIo::bind(ifname, queue_id, mode, config.huge_pages);
+        }
+        let mut errors = vec![];
+        for &mode in &XdpMode::FALLBACKS {
+            match XdpIo::bind(ifname, queue_id, mode, config.huge_pages) {
+                Ok(io) => return Ok(io),
+                Err(e) => {
+                    warn!("{} queue {}: {} mode failed: {}", ifname, queue_id, mode, e);
+                    errors.push(format!("{} mode: {}", mode, e));
+                }
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::Other, errors.join(", ")))
+    }
+
+    /// The mode the socket is bound in.
+    pub fn mode(&self) -> XdpMode {
+        self.mode
+    }
+
+    fn bind(ifname: &str, queue_id: u32, mode: XdpMode, huge_pages: bool) -> io::Result<Self> {
+        let rx_q_size: u32 = 4096;
+        let tx_q_size: u32 = 4096;
+        let comp_q_size: u32 = 4096;
+        let fill_q_size: u32 = 4096 * 2;
+        let frame_size: u32 = 2048;
+        let frame_count = rx_q_size + tx_q_size;
+
+        let umem_config = UmemConfig::new(
+            NonZeroU32::new(frame_count).unwrap(),
+            NonZeroU32::new(frame_size).unwrap(),
+            fill_q_size,
+            comp_q_size,
+            0,
+            huge_pages,
+        )
+        .unwrap();
+
+        let (mut umem, fill_q, comp_q, frames) = Umem::builder(umem_config)
+            .create_mmap()
+            .map_err(xsk_error)?
+            .create_umem()
+            .map_err(xsk_error)?;
+
+        let (xdp_flags, bind_flags) = mode.flags();
+        let socket_config = SocketConfig::new(
+            rx_q_size,
+            tx_q_size,
+            LibbpfFlags::empty(),
+            xdp_flags,
+            bind_flags,
+        )
+        .unwrap();
+
+        let (tx_q, rx_q) =
+            Socket::new(socket_config, &mut umem, ifname, queue_id).map_err(xsk_error)?;
+
+        let len = (frame_count * frame_size) as usize;
+        let addr = unsafe { umem.umem_region_mut_checked(&0, &len) }
+            .map_err(xsk_error)?
+            .as_mut_ptr();
+        let umem = Arc::new(UmemArea {
+            addr,
+            frames: FrameAllocator::new(frame_count as usize, frame_size as usize),
+            _umem: umem,
+        });
+
+        Ok(XdpIo {
+            fill_q,
+            comp_q,
+            tx_q,
+            rx_q,
+            frames,
+            mode,
+            interface: Interface {
+                name: ifname.to_string(),
+                queue: Some(queue_id),
+            },
+            umem,
+        })
+    }
+}
+
+impl PacketIo for XdpIo {
+    type Tx = XdpTx;
+    type Rx = XdpRx;
+
+    fn split(self) -> (XdpTx, XdpRx) {
+        let allocator = &self.umem.frames;
+        allocator.alloc(Owner::Tx, allocator.frame_count() / 2);
+        allocator.alloc(Owner::Rx, allocator.frame_count());
+        let (tx_frames, rx_frames): (Vec<_>, Vec<_>) = self
+            .frames
+            .into_iter()
+            .partition(|frame| allocator.owner(frame.addr()) == Some(Owner::Tx));
+
+        debug!(
+            "tx frames[0] = {}, tx frames[-1] = {}",
+            tx_frames[0].addr(),
+            tx_frames[tx_frames.len() - 1].addr()
+        );
+        debug!(
+            "rx frames[0] = {}, rx frames[-1] = {}",
+            rx_frames[0].addr(),
+            rx_frames[rx_frames.len() - 1].addr()
+        );
+
+        let mut fill_q = self.fill_q;
+        assert_eq!(unsafe { fill_q.produce(&rx_frames[..]) }, rx_frames.len());
+
+        let tx = XdpTx {
+            tx_q: self.tx_q,
+            comp_q: self.comp_q,
+            completed: tx_frames.clone(),
+            frames: tx_frames,
+            next: 0,
+            outstanding: 0,
+            umem: self.umem.clone(),
+        };
+        let rx = XdpRx {
+            rx_q: self.rx_q,
+            fill_q,
+            frames: rx_frames,
+            umem: self.umem,
+        };
+        (tx, rx)
+    }
+
+    fn interface(&self) -> Option<Interface> {
+        Some(self.interface.clone())
+    }
+}
+
+/// Transmit half of an `XdpIo`.
+///
+/// TX frames are used round-robin, the same way the kernel's `xdpsock` sample does. Frames
+/// complete in the order they were produced, so the `outstanding` frames before `next`
+/// (wrapping around) are owned by the kernel and the rest are free.
+pub struct XdpTx {
+    tx_q: TxQueue<'static>,
+    comp_q: CompQueue<'static>,
+    frames: Vec<FrameDesc<'static>>,
+    completed: Vec<FrameDesc<'static>>,
+    next: usize,
+    outstanding: usize,
+    umem: Arc<UmemArea>,
+}
+
+impl XdpTx {
+    fn reclaim(&mut self) -> io::Result<()> {
+        if self.outstanding == 0 {
+            return Ok(());
+        }
+
+        let completed = self.comp_q.consume(&mut self.completed[..self.outstanding]);
+        for frame in &self.completed[..completed] {
+            self.umem.frames.check(frame.addr(), Owner::Tx);
+        }
+        self.outstanding -= completed;
+
+        if completed == 0 && self.tx_q.needs_wakeup() {
+            self.tx_q.wakeup()?;
+        }
+        Ok(())
+    }
+}
+
+impl PacketTx for XdpTx {
+    fn send_batch(
+        &mut self,
+        count: usize,
+        fill: &mut dyn FnMut(&mut [u8]) -> usize,
+    ) -> io::Result<usize> {
+        self.reclaim()?;
+
+        // Only hand out a contiguous run of frames so they can be produced in one call.
+        let count = count
+            .min(self.frames.len() - self.outstanding)
+            .min(self.frames.len() - self.next);
+
+        // Frames are written in place, as the kernel owns none of them until they are produced.
+        let frame_size = self.umem.frames.frame_size();
+        let mut filled = 0;
+        while filled < count {
+            let frame = &mut self.frames[self.next + filled];
+            let len = fill(unsafe { self.umem.frame(frame.addr(), frame_size, Owner::Tx) });
+            if len == 0 {
+                break;
+            }
+            frame.set_len(len);
+            filled += 1;
+        }
+
+        if filled == 0 {
+            return Ok(0);
+        }
+
+        let batch = &self.frames[self.next..self.next + filled];
+        while unsafe { self.tx_q.produce_and_wakeup(batch)? } != filled {
+            // The ring has room for every frame we own, so this only spins until the
+            // kernel catches up.
+            debug!("tx_q.produce_and_wakeup() failed to allocate");
+        }
+
+        self.outstanding += filled;
+        self.next = (self.next + filled) % self.frames.len();
+        Ok(filled)
+    }
+
+    fn in_flight(&self) -> usize {
+        self.outstanding
+    }
+}
+
+/// Receive half of an `XdpIo`.
+pub struct XdpRx {
+    rx_q: RxQueue<'static>,
+    fill_q: FillQueue<'static>,
+    frames: Vec<FrameDesc<'static>>,
+    umem: Arc<UmemArea>,
+}
+
+impl PacketRx for XdpRx {
+    fn recv_batch(&mut self, timeout_ms: i32, handler: &mut dyn FnMut(&[u8])) -> io::Result<usize> {
+        match self
+            .rx_q
+            .poll_and_consume(&mut self.frames[..], timeout_ms)?
+        {
+            0 => {
+                // No packets consumed, wake up fill queue if required
+                if self.fill_q.needs_wakeup() {
+                    self.fill_q.wakeup(self.rx_q.fd(), timeout_ms)?;
+                }
+                Ok(0)
+            }
+            frames_rcvd => {
+                for recv_frame in self.frames.iter().take(frames_rcvd) {
+                    let frame_ref = unsafe {
+                        self.umem
+                            .frame(recv_frame.addr(), recv_frame.len(), Owner::Rx)
+                    };
+                    handler(frame_ref);
+                }
+
+                // Add frames back to fill queue
+                while unsafe {
+                    self.fill_q.produce_and_wakeup(
+                        &self.frames[..frames_rcvd],
+                        self.rx_q.fd(),
+                        timeout_ms,
+                    )?
+                } != frames_rcvd
+                {
+                    // Loop until frames added to the fill ring.
+                    debug!("fill_q.produce_and_wakeup() failed to allocate");
+                }
+
+                Ok(frames_rcvd)
+            }
+        }
+    }
+
+    fn xdp_stats(&mut self) -> io::Result<Option<XdpStats>> {
+        // Kernels before 5.9 only fill in the first three counters, and say so through `len`.
+        let mut stats = [0u64; 6];
+        let mut len = mem::size_of_val(&stats) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                self.rx_q.fd().id(),
+                SOL_XDP,
+                XDP_STATISTICS,
+                stats.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(XdpStats {
+            rx_dropped: stats[0],
+            rx_invalid_descs: stats[1],
+            tx_invalid_descs: stats[2],
+            rx_ring_full: stats[3],
+            rx_fill_ring_empty_descs: stats[4],
+            tx_ring_empty_descs: stats[5],
+        }))
+    }
+}
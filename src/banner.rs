@@ -0,0 +1,257 @@
+//! Banner grabbing for open ports found by a scan.
+//!
+//! Connections go through the kernel's TCP stack, so banners must be grabbed after the scan
+//! has finished: while an AF_XDP socket is bound to a queue, the kernel never sees the replies
+//! arriving on it.
+use crate::rng::Rng;
+use crate::Target;
+use log::debug;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// What to send once a connection is established.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BannerProbe {
+    /// Send nothing and wait for the server to speak first, as SSH, SMTP or FTP servers do.
+    Passive,
+    /// Send an HTTP/1.0 `HEAD /` request.
+    HttpHead,
+    /// Send a TLS ClientHello, so the server answers with its ServerHello and certificate.
+    TlsClientHello,
+}
+
+impl BannerProbe {
+    /// Picks the probe most likely to get an answer from the usual service on `port`.
+    pub fn for_port(port: u16) -> Self {
+        match port {
+            80 | 81 | 591 | 3000 | 5000 | 8000 | 8008 | 8080 | 8888 => BannerProbe::HttpHead,
+            443 | 465 | 636 | 853 | 993 | 995 | 8443 => BannerProbe::TlsClientHello,
+            _ => BannerProbe::Passive,
+        }
+    }
+
+    fn payload(self, target: &Target) -> Vec<u8> {
+        match self {
+            BannerProbe::Passive => vec![],
+            BannerProbe::HttpHead => {
+                let host = match target.ip {
+                    IpAddr::V4(ip) => ip.to_string(),
+                    IpAddr::V6(ip) => format!("[{}]", ip),
+                };
+                format!(
+                    "HEAD / HTTP/1.0\r\nHost: {}\r\nUser-Agent: xdpscan\r\n\r\n",
+                    host
+                )
+                .into_bytes()
+            }
+            BannerProbe::TlsClientHello => {
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_nanos() as u64)
+                    .unwrap_or(0);
+                let mut rng = Rng::new(seed);
+                let mut random = [0; 32];
+                for chunk in random.chunks_mut(8) {
+                    chunk.copy_from_slice(&rng.next_u64().to_be_bytes());
+                }
+                tls_client_hello(&random)
+            }
+        }
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_extension(buf: &mut Vec<u8>, extension_type: u16, data: &[u8]) {
+    push_u16(buf, extension_type);
+    push_u16(buf, data.len() as u16);
+    buf.extend_from_slice(data);
+}
+
+/// Builds a TLS 1.2 ClientHello record offering the cipher suites and groups common servers
+/// accept. No SNI is sent, since targets are addresses rather than names.
+fn tls_client_hello(random: &[u8; 32]) -> Vec<u8> {
+    const CIPHER_SUITES: [u16; 12] = [
+        0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f,
+        0x0035,
+    ];
+    const GROUPS: [u16; 3] = [0x001d, 0x0017, 0x0018];
+    const SIGNATURE_ALGORITHMS: [u16; 8] = [
+        0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+    ];
+
+    let mut extensions = vec![];
+    let mut groups = vec![];
+    push_u16(&mut groups, (GROUPS.len() * 2) as u16);
+    GROUPS
+        .iter()
+        .for_each(|&group| push_u16(&mut groups, group));
+    push_extension(&mut extensions, 0x000a, &groups);
+    // Uncompressed points only.
+    push_extension(&mut extensions, 0x000b, &[0x01, 0x00]);
+    let mut algorithms = vec![];
+    push_u16(&mut algorithms, (SIGNATURE_ALGORITHMS.len() * 2) as u16);
+    SIGNATURE_ALGORITHMS
+        .iter()
+        .for_each(|&algorithm| push_u16(&mut algorithms, algorithm));
+    push_extension(&mut extensions, 0x000d, &algorithms);
+
+    let mut hello = vec![];
+    push_u16(&mut hello, 0x0303);
+    hello.extend_from_slice(random);
+    // Empty session id.
+    hello.push(0);
+    push_u16(&mut hello, (CIPHER_SUITES.len() * 2) as u16);
+    CIPHER_SUITES
+        .iter()
+        .for_each(|&suite| push_u16(&mut hello, suite));
+    // Null compression only.
+    hello.extend_from_slice(&[0x01, 0x00]);
+    push_u16(&mut hello, extensions.len() as u16);
+    hello.extend_from_slice(&extensions);
+
+    let mut handshake = vec![0x01];
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![0x16, 0x03, 0x01];
+    push_u16(&mut record, handshake.len() as u16);
+    record.extend_from_slice(&handshake);
+    record
+}
+
+#[derive(Clone, Debug)]
+pub struct BannerConfig {
+    /// Probe to send to every port, or `None` to pick one by port number.
+    pub probe: Option<BannerProbe>,
+    pub connect_timeout: Duration,
+    /// How long to wait for the response to start.
+    pub read_timeout: Duration,
+    /// How long to wait for more of the response once it has started.
+    pub idle_timeout: Duration,
+    /// Maximum number of response bytes to keep.
+    pub max_bytes: usize,
+    /// Number of connections to make in parallel.
+    pub workers: usize,
+}
+
+impl Default for BannerConfig {
+    fn default() -> Self {
+        BannerConfig {
+            probe: None,
+            connect_timeout: Duration::from_secs(3),
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_millis(500),
+            max_bytes: 1024,
+            workers: 32,
+        }
+    }
+}
+
+/// The first bytes an open port answered with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Banner {
+    pub target: Target,
+    pub probe: BannerProbe,
+    /// Empty if the server sent nothing before the timeout, or if the banner could not be
+    /// grabbed.
+    pub data: Vec<u8>,
+    /// Why the banner could not be grabbed, if it couldn't.
+    pub error: Option<String>,
+}
+
+/// Connects to `target`, sends `probe` and returns the first bytes of the response.
+pub fn grab_banner(
+    target: &Target,
+    probe: BannerProbe,
+    config: &BannerConfig,
+) -> io::Result<Vec<u8>> {
+    let addr = SocketAddr::new(target.ip, target.port);
+    let mut stream = TcpStream::connect_timeout(&addr, config.connect_timeout)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(config.connect_timeout))?;
+    stream.write_all(&probe.payload(target))?;
+
+    let deadline = Instant::now() + config.read_timeout;
+    let mut data = vec![0; config.max_bytes];
+    let mut len = 0;
+    while len < data.len() {
+        let timeout = if len == 0 {
+            deadline.saturating_duration_since(Instant::now())
+        } else {
+            config.idle_timeout
+        };
+        if timeout == Duration::from_secs(0) {
+            break;
+        }
+        stream.set_read_timeout(Some(timeout))?;
+
+        match stream.read(&mut data[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            // A reset after the server has said something still leaves a banner.
+            Err(_) if len > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Grabs the banners of `targets`, usually the results of a scan, in parallel.
+///
+/// Banners are returned in the order of `targets`, one for every target. Those of targets that
+/// could not be connected to or read from hold the error.
+pub fn grab_banners(targets: &[Target], config: &BannerConfig) -> Vec<Banner> {
+    let next = AtomicUsize::new(0);
+    let banners = Mutex::new(vec![]);
+
+    thread::scope(|scope| {
+        for _ in 0..config.workers.max(1).min(targets.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let target = match targets.get(i) {
+                    Some(target) => target,
+                    None => break,
+                };
+
+                let probe = config
+                    .probe
+                    .unwrap_or_else(|| BannerProbe::for_port(target.port));
+                let (data, error) = match grab_banner(target, probe, config) {
+                    Ok(data) => (data, None),
+                    Err(e) => {
+                        debug!("failed to grab banner of {:?}: {}", target, e);
+                        (vec![], Some(e.to_string()))
+                    }
+                };
+                banners.lock().unwrap().push((
+                    i,
+                    Banner {
+                        target: target.clone(),
+                        probe,
+                        data,
+                        error,
+                    },
+                ));
+            });
+        }
+    });
+
+    let mut banners = banners.into_inner().unwrap();
+    banners.sort_by_key(|&(i, _)| i);
+    banners.into_iter().map(|(_, banner)| banner).collect()
+}
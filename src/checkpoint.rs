@@ -0,0 +1,206 @@
+//! Checkpoints of long running scans.
+//!
+//! A checkpoint holds what is needed to continue an interrupted scan: the seed of the probe
+//! order, the shard being scanned, how far along the shard's part of that order every probe
+//! has been given `wait_time` to be answered, a hash of the scan's configuration and the
+//! results found so far. It is stored as a small line based text file, which is replaced
+//! atomically every time it is written.
+use crate::probe::ScanType;
+use crate::rng::{fnv1a, FNV1A_OFFSET};
+use crate::shard::Shard;
+use crate::{ScanResult, SrcConfig, Target};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const HEADER: &str = "xdpscan-checkpoint 1";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub scan_type: ScanType,
+    /// Seed of the order targets are probed in.
+    pub seed: u64,
+    /// Part of the probe order being scanned.
+    pub shard: Shard,
+    /// Number of the shard's targets, in probe order, that have been probed and given time to
+    /// reply.
+    pub position: u64,
+    /// `config_hash` of the scan the checkpoint belongs to.
+    pub config_hash: u64,
+    /// Targets that answered so far.
+    pub results: Vec<ScanResult>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_field<T: std::str::FromStr>(key: &str, value: Option<&str>) -> io::Result<T> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| invalid_data(format!("invalid checkpoint {}", key)))
+}
+
+impl Checkpoint {
+    /// Whether `contents` looks like the contents of a checkpoint file.
+    pub fn is_checkpoint(contents: &str) -> bool {
+        contents.lines().next() == Some(HEADER)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        if !Checkpoint::is_checkpoint(&contents) {
+            return Err(invalid_data(format!(
+                "{} is not an xdpscan checkpoint",
+                path.display()
+            )));
+        }
+
+        let (mut scan_type, mut seed, mut shard) = (None, None, None);
+        let (mut position, mut config_hash) = (None, None);
+        let mut results = vec![];
+        for line in contents.lines().skip(1) {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("scan") => scan_type = Some(parse_field("scan", fields.next())?),
+                Some("seed") => seed = Some(parse_field("seed", fields.next())?),
+                Some("shard") => shard = Some(parse_field("shard", fields.next())?),
+                Some("position") => position = Some(parse_field("position", fields.next())?),
+                Some("config") => {
+                    let hash = fields
+                        .next()
+                        .and_then(|hash| u64::from_str_radix(hash, 16).ok())
+                        .ok_or_else(|| invalid_data("invalid checkpoint config".into()))?;
+                    config_hash = Some(hash);
+                }
+                Some("result") => {
+                    let ip: IpAddr = parse_field("result", fields.next())?;
+                    let port = parse_field("result", fields.next())?;
+                    let state = parse_field("result", fields.next())?;
+                    // Followed by the reply, if the target sent one.
+                    let reply = fields.collect::<Vec<_>>().join(" ");
+                    let reply = if reply.is_empty() {
+                        None
+                    } else {
+                        Some(parse_field("result", Some(&reply))?)
+                    };
+                    results.push(ScanResult {
+                        target: Target { ip, port },
+                        state,
+                        reply,
+                        hop: None,
+                    });
+                }
+                Some(key) => {
+                    return Err(invalid_data(format!("unknown checkpoint field {}", key)));
+                }
+                None => {}
+            }
+        }
+
+        let missing = |key: &str| invalid_data(format!("checkpoint is missing {}", key));
+        Ok(Checkpoint {
+            scan_type: scan_type.ok_or_else(|| missing("scan"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            shard: shard.ok_or_else(|| missing("shard"))?,
+            position: position.ok_or_else(|| missing("position"))?,
+            config_hash: config_hash.ok_or_else(|| missing("config"))?,
+            results,
+        })
+    }
+
+    /// Writes the checkpoint to a temporary file next to `path`, then renames it over `path`,
+    /// so an interruption never leaves a truncated checkpoint behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "scan {}", self.scan_type)?;
+        writeln!(writer, "seed {}", self.seed)?;
+        writeln!(writer, "shard {}", self.shard)?;
+        writeln!(writer, "position {}", self.position)?;
+        writeln!(writer, "config {:016x}", self.config_hash)?;
+        for result in &self.results {
+            let target = &result.target;
+            write!(
+                writer,
+                "result {} {} {}",
+                target.ip, target.port, result.state
+            )?;
+            match &result.reply {
+                Some(reply) => writeln!(writer, " {}", reply)?,
+                None => writeln!(writer)?,
+            }
+        }
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// Hashes everything that determines which probes a scan sends, so a checkpoint is never
+/// resumed against a different scan.
+pub fn config_hash(src_config: &SrcConfig, targets: &[Target]) -> u64 {
+    let mut hash = FNV1A_OFFSET;
+    let mut write = |bytes: &[u8]| hash = fnv1a(hash, bytes);
+
+    write(&src_config.src_mac);
+    write(&src_config.dst_mac);
+    write(&(src_config.vlan_tags.len() as u64).to_be_bytes());
+    for tag in &src_config.vlan_tags {
+        write(&tag.vid.to_be_bytes());
+    }
+    write(&(src_config.src_ips.len() as u64).to_be_bytes());
+    for src_ip in &src_config.src_ips {
+        write(src_ip.to_string().as_bytes());
+    }
+    write(&src_config.src_ports.start().to_be_bytes());
+    write(&src_config.src_ports.end().to_be_bytes());
+    write(&[src_config.src_selection as u8]);
+    write(&(targets.len() as u64).to_be_bytes());
+    for target in targets {
+        write(target.ip.to_string().as_bytes());
+        write(&target.port.to_be_bytes());
+    }
+
+    hash
+}
+
+/// Tracks the position up to which every probe has been given `wait_time` to be answered, which
+/// is as far as a checkpoint written while probes are still going out can safely claim.
+pub struct SettledPosition {
+    wait_time: Duration,
+    settled: u64,
+    // Positions reached by the sender and when, oldest first.
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SettledPosition {
+    pub fn new(start: u64, wait_time: Duration) -> Self {
+        SettledPosition {
+            wait_time,
+            settled: start,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records that the sender had reached `position` at `now`, and returns the settled
+    /// position.
+    pub fn record(&mut self, now: Instant, position: u64) -> u64 {
+        self.samples.push_back((now, position));
+        while let Some(&(sent_at, position)) = self.samples.front() {
+            if sent_at + self.wait_time > now {
+                break;
+            }
+            self.settled = position;
+            self.samples.pop_front();
+        }
+        self.settled
+    }
+}
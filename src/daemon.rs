@@ -0,0 +1,169 @@
+//! Scanning the same targets again and again, on a schedule, over one socket.
+//!
+//! `scan` and the other scan functions set a backend up for a single scan and tear it down
+//! after it, which for AF_XDP means creating the socket, registering the UMEM and attaching
+//! the XDP program every time. A `Daemon` splits its backend once, runs every scan over the
+//! same halves, and compares the results of each run with those of the one before.
+//!
+//! Every run picks new cookies, so replies to an earlier run that arrive late, or that waited
+//! in the RX ring between runs, are ignored.
+use crate::backend::{Interface, PacketIo};
+use crate::{new_checkpoint, run_scan_on, Targets};
+use crate::{PortState, ScanConfig, ScanResult, SrcConfig, Target};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A target whose state differs from the previous run.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Change {
+    pub target: Target,
+    /// The state in the previous run, or `None` if the target did not answer it.
+    pub before: Option<PortState>,
+    /// The state in this run, or `None` if the target did not answer it.
+    pub after: Option<PortState>,
+}
+
+impl fmt::Display for Change {
+    /// Writes `address:port before -> after`, with `unanswered` for runs the target did not
+    /// answer.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = |state: Option<PortState>| match state {
+            Some(state) => state.to_string(),
+            None => "unanswered".to_string(),
+        };
+        write!(
+            f,
+            "{} {} -> {}",
+            SocketAddr::new(self.target.ip, self.target.port),
+            state(self.before),
+            state(self.after)
+        )
+    }
+}
+
+/// The targets whose state differs between the results `before` and `after` of scans of
+/// `targets`, in the order of `targets`.
+pub fn changes(targets: &[Target], before: &[ScanResult], after: &[ScanResult]) -> Vec<Change> {
+    let states = |results: &[ScanResult]| -> HashMap<Target, PortState> {
+        results
+            .iter()
+            .map(|result| (result.target.clone(), result.state))
+            .collect()
+    };
+    let (before, after) = (states(before), states(after));
+    targets
+        .iter()
+        .filter_map(|target| {
+            let change = Change {
+                target: target.clone(),
+                before: before.get(target).copied(),
+                after: after.get(target).copied(),
+            };
+            (change.before != change.after).then_some(change)
+        })
+        .collect()
+}
+
+/// One scan run by a `Daemon`.
+#[derive(Clone, Debug)]
+pub struct ScanRun {
+    /// Runs are numbered from 1.
+    pub number: u64,
+    /// When the run started, by the system clock, to be printed.
+    pub started_at: SystemTime,
+    /// When the run started, to time runs by, as the system clock may jump between them.
+    pub started: Instant,
+    pub duration: Duration,
+    /// The state of every target that answered, as `scan_results_with` gives them.
+    pub results: Vec<ScanResult>,
+    /// What changed since the previous run, or `None` for the first one.
+    pub changes: Option<Vec<Change>>,
+}
+
+/// Scans a fixed set of targets, with a fixed configuration, as many times as asked to, over
+/// a backend that is only set up once.
+///
+/// If `scan_config.checkpoint_path` is set, every run overwrites the checkpoint, which is only
+/// of use to resume the last run with `resume_results_with`.
+pub struct Daemon<I: PacketIo> {
+    /// Only taken while a scan runs.
+    halves: Option<(I::Tx, I::Rx)>,
+    interface: Option<Interface>,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+    previous: Option<Vec<ScanResult>>,
+    runs: u64,
+}
+
+impl<I: PacketIo> Daemon<I> {
+    pub fn new(
+        io: I,
+        src_config: SrcConfig,
+        scan_config: ScanConfig,
+        targets: Vec<Target>,
+    ) -> Self {
+        let interface = io.interface();
+        Daemon {
+            halves: Some(io.split()),
+            interface,
+            src_config,
+            scan_config,
+            targets,
+            previous: None,
+            runs: 0,
+        }
+    }
+
+    /// Scans the targets once, right away.
+    pub fn run_once(&mut self) -> ScanRun {
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let (tx, rx) = self.halves.take().expect("a previous scan panicked");
+        let checkpoint = new_checkpoint(&self.src_config, &self.scan_config, &self.targets);
+        let (results, tx, rx) = run_scan_on(
+            tx,
+            rx,
+            self.interface.clone(),
+            self.src_config.clone(),
+            self.scan_config.clone(),
+            Targets::List(self.targets.clone()),
+            checkpoint,
+        );
+        self.halves = Some((tx, rx));
+
+        self.runs += 1;
+        let changes = self
+            .previous
+            .as_ref()
+            .map(|previous| changes(&self.targets, previous, &results));
+        self.previous = Some(results.clone());
+        ScanRun {
+            number: self.runs,
+            started_at,
+            started,
+            duration: started.elapsed(),
+            results,
+            changes,
+        }
+    }
+
+    /// Scans the targets every `interval`, from the start of one run to the start of the next,
+    /// passing every run to `on_run` until it returns `false`. A run that takes longer than
+    /// `interval` is followed by the next one right away.
+    pub fn run<F: FnMut(ScanRun) -> bool>(&mut self, interval: Duration, mut on_run: F) {
+        loop {
+            let run = self.run_once();
+            let started = run.started;
+            if !on_run(run) {
+                return;
+            }
+            if let Some(remaining) = interval.checked_sub(started.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Guessing the operating system of open ports from their SYN-ACKs, the way p0f does.
+//!
+//! Signatures are read from the `[tcp:response]` sections of a database in the format of
+//! p0f 3's `p0f.fp`, so its database, or one extending it, can be used as is:
+//!
+//! ```text
+//! [tcp:response]
+//! label = s:unix:Linux:3.x
+//! sig   = *:64:0:*:mss*10,4:mss,sok,ts,nop,ws:df:0
+//! ```
+//!
+//! A `sig` holds the IP version, initial TTL, IP option length, MSS, window size and scale,
+//! TCP option layout, quirks and payload class, and belongs to the last `label` before it.
+//! Replies don't carry IP options or payloads, so the option length and payload class are not
+//! compared. Of the quirks, only `df`, `id+`, `id-`, `ecn` and `ts1-` are; the others are
+//! ignored. Other sections, and `sys` lines, are skipped.
+use crate::packet::{flags, ReplyHeaders, TcpOption};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How many hops below its initial TTL a reply's TTL may be, as in p0f.
+const MAX_HOPS: u8 = 35;
+
+/// An operating system, as p0f labels them: `s:unix:Linux:3.x`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct OsLabel {
+    /// Whether the label is generic, `g`, standing for a whole family of systems rather than
+    /// a specific one, `s`.
+    pub generic: bool,
+    pub class: String,
+    pub name: String,
+    pub flavor: String,
+}
+
+impl fmt::Display for OsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.generic { 'g' } else { 's' };
+        write!(f, "{}:{}:{}:{}", kind, self.class, self.name, self.flavor)
+    }
+}
+
+impl FromStr for OsLabel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid OS label {}", s);
+        let mut fields = s.splitn(4, ':');
+        let generic = match fields.next() {
+            Some("g") => true,
+            Some("s") => false,
+            _ => return Err(invalid()),
+        };
+        let mut next = || fields.next().map(str::to_string).ok_or_else(invalid);
+        Ok(OsLabel {
+            generic,
+            class: next()?,
+            name: next()?,
+            flavor: next()?,
+        })
+    }
+}
+
+/// The best guess at the operating system of a reply.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct OsGuess {
+    pub label: OsLabel,
+    /// Whether the reply only matched the signature once its quirks were ignored.
+    pub fuzzy: bool,
+}
+
+/// Window size of a signature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WindowSig {
+    Any,
+    Exact(u16),
+    /// A multiple of the MSS.
+    Mss(u32),
+    /// A multiple of the MTU the MSS implies.
+    Mtu(u32),
+    /// Any multiple of this.
+    Modulo(u16),
+}
+
+impl WindowSig {
+    fn matches(self, window: u16, mss: Option<u16>, ipv6: bool) -> bool {
+        let header_len = if ipv6 { 60 } else { 40 };
+        match self {
+            WindowSig::Any => true,
+            WindowSig::Exact(exact) => window == exact,
+            WindowSig::Mss(factor) => {
+                mss.map_or(false, |mss| u32::from(window) == u32::from(mss) * factor)
+            }
+            WindowSig::Mtu(factor) => mss.map_or(false, |mss| {
+                u32::from(window) == (u32::from(mss) + header_len) * factor
+            }),
+            WindowSig::Modulo(modulo) => modulo != 0 && window % modulo == 0,
+        }
+    }
+}
+
+impl FromStr for WindowSig {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            Ok(WindowSig::Any)
+        } else if let Some(factor) = s.strip_prefix("mss*") {
+            factor.parse().map(WindowSig::Mss).map_err(|_| ())
+        } else if let Some(factor) = s.strip_prefix("mtu*") {
+            factor.parse().map(WindowSig::Mtu).map_err(|_| ())
+        } else if let Some(modulo) = s.strip_prefix('%') {
+            modulo.parse().map(WindowSig::Modulo).map_err(|_| ())
+        } else {
+            s.parse().map(WindowSig::Exact).map_err(|_| ())
+        }
+    }
+}
+
+/// The quirks of a reply that signatures are compared on.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Quirks {
+    /// Don't fragment set.
+    df: bool,
+    /// Don't fragment set, with a non-zero IP ID.
+    id_plus: bool,
+    /// Don't fragment not set, with an IP ID of 0.
+    id_minus: bool,
+    /// ECE or CWR set.
+    ecn: bool,
+    /// Timestamp option with a TSval of 0.
+    zero_tsval: bool,
+}
+
+impl Quirks {
+    fn of(headers: &ReplyHeaders) -> Self {
+        let ip_id = headers.ip_id;
+        Quirks {
+            df: headers.dont_fragment,
+            id_plus: headers.dont_fragment && ip_id.map_or(false, |id| id != 0),
+            id_minus: !headers.dont_fragment && ip_id == Some(0),
+            ecn: headers.flags & (flags::ECE | flags::CWR) != 0,
+            zero_tsval: matches!(headers.timestamps, Some((0, _))),
+        }
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+        for quirk in s.split(',').filter(|quirk| !quirk.is_empty()) {
+            match quirk {
+                "df" => quirks.df = true,
+                "id+" => quirks.id_plus = true,
+                "id-" => quirks.id_minus = true,
+                "ecn" => quirks.ecn = true,
+                "ts1-" => quirks.zero_tsval = true,
+                _ => {}
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+/// Name of an option in p0f's option layouts. Padding after an end of option list, `eol+N`,
+/// is not compared.
+fn layout_name(option: &TcpOption) -> &'static str {
+    match option {
+        TcpOption::MaxSegmentSize(_) => "mss",
+        TcpOption::SackPermitted => "sok",
+        TcpOption::Timestamp => "ts",
+        TcpOption::WindowScale(_) => "ws",
+        TcpOption::Nop => "nop",
+        TcpOption::End => "eol",
+    }
+}
+
+/// One `sig` of a `[tcp:response]` section.
+#[derive(Clone, Debug)]
+struct Signature {
+    label: OsLabel,
+    /// 4, 6, or `None` for either.
+    ip_version: Option<u8>,
+    initial_ttl: u8,
+    mss: Option<u16>,
+    window: WindowSig,
+    scale: Option<u8>,
+    layout: Vec<String>,
+    quirks: Quirks,
+}
+
+/// Parses a field that is either `*`, for any value, or a value.
+fn any_or<T: FromStr>(field: &str) -> Result<Option<T>, ()> {
+    match field {
+        "*" => Ok(None),
+        _ => field.parse().map(Some).map_err(|_| ()),
+    }
+}
+
+impl Signature {
+    fn parse(s: &str, label: OsLabel) -> Result<Self, ()> {
+        let fields: Vec<&str> = s.split(':').collect();
+        if fields.len() != 8 {
+            return Err(());
+        }
+        // The IP option length and payload class, fields 2 and 7, are not compared.
+        let (version, ttl, mss, window, layout, quirks) = (
+            fields[0], fields[1], fields[3], fields[4], fields[5], fields[6],
+        );
+        let (window, scale) = window.split_once(',').ok_or(())?;
+        Ok(Signature {
+            label,
+            ip_version: match version {
+                "*" => None,
+                "4" => Some(4),
+                "6" => Some(6),
+                _ => return Err(()),
+            },
+            // A trailing `-` marks TTLs p0f could not tell the initial one of.
+            initial_ttl: ttl.trim_end_matches('-').parse().map_err(|_| ())?,
+            mss: any_or(mss)?,
+            window: window.parse()?,
+            scale: any_or(scale)?,
+            layout: layout
+                .split(',')
+                .filter(|option| !option.is_empty())
+                .map(|option| option.split('+').next().unwrap().to_string())
+                .collect(),
+            quirks: quirks.parse()?,
+        })
+    }
+
+    /// Whether `headers` match the signature, comparing their quirks only if `strict`.
+    fn matches(&self, headers: &ReplyHeaders, strict: bool) -> bool {
+        let ipv6 = headers.ip_version == 6;
+        let mss = headers.mss();
+        let layout = headers.tcp_options.iter().map(layout_name);
+        self.ip_version
+            .map_or(true, |version| version == headers.ip_version)
+            && headers.ttl <= self.initial_ttl
+            && self.initial_ttl - headers.ttl <= MAX_HOPS
+            && self.mss.map_or(true, |sig_mss| mss == Some(sig_mss))
+            && self.window.matches(headers.window, mss, ipv6)
+            && self
+                .scale
+                .map_or(true, |scale| headers.window_scale().unwrap_or(0) == scale)
+            && layout.eq(self.layout.iter().map(String::as_str))
+            && (!strict || Quirks::of(headers) == self.quirks)
+    }
+}
+
+/// The SYN-ACK signatures of a p0f style database, see the module documentation.
+#[derive(Clone, Debug, Default)]
+pub struct SignatureDb {
+    signatures: Vec<Signature>,
+}
+
+impl SignatureDb {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// Guesses the operating system that sent the SYN-ACK with `headers`, or returns `None`
+    /// if it is not a SYN-ACK or matches no signature.
+    ///
+    /// Specific signatures are preferred over generic ones, and earlier ones over later ones.
+    /// If none match, the first to match once quirks are ignored is taken as a fuzzy guess.
+    pub fn classify(&self, headers: &ReplyHeaders) -> Option<OsGuess> {
+        if headers.flags & (flags::SYN | flags::ACK) != flags::SYN | flags::ACK {
+            return None;
+        }
+        let strict = |generic: bool| {
+            self.signatures
+                .iter()
+                .find(|sig| sig.label.generic == generic && sig.matches(headers, true))
+        };
+        if let Some(sig) = strict(false).or_else(|| strict(true)) {
+            return Some(OsGuess {
+                label: sig.label.clone(),
+                fuzzy: false,
+            });
+        }
+        self.signatures
+            .iter()
+            .find(|sig| sig.matches(headers, false))
+            .map(|sig| OsGuess {
+                label: sig.label.clone(),
+                fuzzy: true,
+            })
+    }
+}
+
+impl FromStr for SignatureDb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut signatures = vec![];
+        let mut in_responses = false;
+        let mut label = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = match line.split_once(';') {
+                Some((line, _comment)) => line,
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_responses = line == "[tcp:response]";
+                label = None;
+                continue;
+            }
+            if !in_responses {
+                continue;
+            }
+            let invalid = || format!("line {}: invalid signature {}", i + 1, line);
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            match key.trim() {
+                "label" => label = Some(value.trim().parse().map_err(|_| invalid())?),
+                "sig" => {
+                    let label = label.clone().ok_or_else(invalid)?;
+                    signatures.push(Signature::parse(value.trim(), label).map_err(|_| invalid())?);
+                }
+                _ => {}
+            }
+        }
+        Ok(SignatureDb { signatures })
+    }
+}
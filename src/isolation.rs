@@ -0,0 +1,361 @@
+//! Keeping the host's own TCP stack out of a scan.
+//!
+//! When probes are sent from an address the host owns, any reply the kernel gets to see
+//! either collides with a local connection using the same port or is answered with a RST,
+//! which tears down the connection before the scanner has recorded it and makes the scan
+//! visible. The guards here each close one of those gaps and undo their change when dropped:
+//!
+//! - `PortReservation` keeps the kernel from handing the scanner's source ports out to local
+//!   connections.
+//! - `QueueSteering` steers replies to the queue the AF_XDP socket is bound to, where the XDP
+//!   program redirects them to the scanner before the kernel sees them.
+//! - `RstFilter` drops the RSTs the kernel sends for replies that reach it anyway, e.g. on
+//!   drivers without flow steering. RSTs sent by the scanner itself go out through AF_XDP and
+//!   never pass through netfilter.
+use log::warn;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Command;
+
+const RESERVED_PORTS: &str = "/proc/sys/net/ipv4/ip_local_reserved_ports";
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} {} failed: {}",
+                program,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+fn port_range(ports: &RangeInclusive<u16>) -> String {
+    if ports.start() == ports.end() {
+        ports.start().to_string()
+    } else {
+        format!("{}-{}", ports.start(), ports.end())
+    }
+}
+
+/// Binds a TCP socket to `addr` without listening on it, so the port is taken but
+/// connections to it are not accepted.
+fn bind_placeholder(addr: SocketAddr) -> io::Result<OwnedFd> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = &mut storage as *mut _ as *mut libc::sockaddr_in;
+            unsafe {
+                (*sin).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*sin).sin_port = addr.port().to_be();
+                (*sin).sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            }
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = &mut storage as *mut _ as *mut libc::sockaddr_in6;
+            unsafe {
+                (*sin6).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*sin6).sin6_port = addr.port().to_be();
+                (*sin6).sin6_addr.s6_addr = addr.ip().octets();
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    let ret = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &storage as *const _ as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Keeps the kernel from using the scanner's source ports for local connections.
+///
+/// Holds a bound, non-listening TCP socket on every port of every address, and optionally
+/// also adds the ports to `net.ipv4.ip_local_reserved_ports`, which covers every address and
+/// IPv6 too, so even connections to unrelated destinations are never given one of them. The
+/// sysctl is restored when the reservation is dropped.
+///
+/// Every socket takes a file descriptor, so no more than `MAX_PLACEHOLDERS` are held. Wider
+/// reservations only add the ports to the sysctl, and fail without it.
+///
+/// This does not stop the kernel from answering replies to those ports with a RST, see
+/// `QueueSteering` and `RstFilter`.
+pub struct PortReservation {
+    _placeholders: Vec<OwnedFd>,
+    previous_reserved_ports: Option<String>,
+}
+
+/// Placeholder sockets `PortReservation` holds at most, over all addresses.
+pub const MAX_PLACEHOLDERS: usize = 256;
+
+impl PortReservation {
+    pub fn new(
+        src_ips: &[IpAddr],
+        ports: RangeInclusive<u16>,
+        reserve_local_ports: bool,
+    ) -> io::Result<Self> {
+        let placeholder_count = src_ips.len() * (usize::from(ports.end() - ports.start()) + 1);
+        let placeholders = if placeholder_count <= MAX_PLACEHOLDERS {
+            src_ips
+                .iter()
+                .flat_map(|&src_ip| ports.clone().map(move |port| SocketAddr::new(src_ip, port)))
+                .map(bind_placeholder)
+                .collect::<io::Result<Vec<_>>>()?
+        } else if reserve_local_ports {
+            vec![]
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} ports on {} addresses need {} placeholder sockets, more than {}",
+                    port_range(&ports),
+                    src_ips.len(),
+                    placeholder_count,
+                    MAX_PLACEHOLDERS
+                ),
+            ));
+        };
+
+        let previous_reserved_ports = if reserve_local_ports {
+            let previous = fs::read_to_string(RESERVED_PORTS)?.trim().to_string();
+            let reserved = if previous.is_empty() {
+                port_range(&ports)
+            } else {
+                format!("{},{}", previous, port_range(&ports))
+            };
+            fs::write(RESERVED_PORTS, reserved)?;
+            Some(previous)
+        } else {
+            None
+        };
+
+        Ok(PortReservation {
+            _placeholders: placeholders,
+            previous_reserved_ports,
+        })
+    }
+}
+
+impl Drop for PortReservation {
+    fn drop(&mut self) {
+        if let Some(previous) = &self.previous_reserved_ports {
+            // An empty write leaves the sysctl unchanged, while a lone newline clears it.
+            if let Err(e) = fs::write(RESERVED_PORTS, format!("{}\n", previous)) {
+                warn!(
+                    "failed to restore {}: {}. May need to reset it manually to '{}'",
+                    RESERVED_PORTS, e, previous
+                );
+            }
+        }
+    }
+}
+
+/// Steers replies to the scanner's source ports to one queue of an interface with ethtool
+/// ntuple filters, so they all reach the AF_XDP socket bound to that queue.
+///
+/// Needs a driver supporting ntuple filters (`ethtool -K <ifname> ntuple on`). NICs hold few
+/// of them, so the ports are covered with masked rules, one per block of ports aligned to a
+/// power of two, and ranges needing more than `MAX_STEERING_RULES` are refused.
+pub struct QueueSteering {
+    ifname: String,
+    rule_ids: Vec<String>,
+}
+
+/// Rules `QueueSteering` adds per address at most.
+pub const MAX_STEERING_RULES: usize = 8;
+
+/// Splits `ports` into the fewest blocks of `2^n` ports starting at a multiple of `2^n`, each
+/// given as its first port and the mask of the `n` low bits for ethtool to ignore.
+fn port_blocks(ports: &RangeInclusive<u16>) -> Vec<(u16, u16)> {
+    let mut blocks = vec![];
+    let (mut start, end) = (u32::from(*ports.start()), u32::from(*ports.end()));
+    while start <= end {
+        let mut size = 1;
+        while start % (size * 2) == 0 && start + size * 2 - 1 <= end {
+            size *= 2;
+        }
+        blocks.push((start as u16, (size - 1) as u16));
+        start += size;
+    }
+    blocks
+}
+
+impl QueueSteering {
+    /// The arguments of the `ethtool` runs that add the rules, see `QueueSteering`.
+    pub fn rules(
+        ifname: &str,
+        src_ip: IpAddr,
+        ports: &RangeInclusive<u16>,
+        queue_id: u32,
+    ) -> io::Result<Vec<Vec<String>>> {
+        let blocks = port_blocks(ports);
+        if blocks.len() > MAX_STEERING_RULES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "ports {} need {} ntuple rules, more than {}. \
+                     Use a range aligned to its size, a power of two, e.g. 40960-45055",
+                    port_range(ports),
+                    blocks.len(),
+                    MAX_STEERING_RULES
+                ),
+            ));
+        }
+        let flow_type = match src_ip {
+            IpAddr::V4(_) => "tcp4",
+            IpAddr::V6(_) => "tcp6",
+        };
+        let rules = blocks
+            .into_iter()
+            .map(|(base, mask)| {
+                let mut args = vec![
+                    "-N".to_string(),
+                    ifname.to_string(),
+                    "flow-type".to_string(),
+                    flow_type.to_string(),
+                    "dst-ip".to_string(),
+                    src_ip.to_string(),
+                    "dst-port".to_string(),
+                    base.to_string(),
+                ];
+                if mask != 0 {
+                    args.extend(vec!["m".to_string(), format!("{:#x}", mask)]);
+                }
+                args.extend(vec!["action".to_string(), queue_id.to_string()]);
+                args
+            })
+            .collect();
+        Ok(rules)
+    }
+
+    pub fn new(
+        ifname: &str,
+        src_ip: IpAddr,
+        ports: RangeInclusive<u16>,
+        queue_id: u32,
+    ) -> io::Result<Self> {
+        let rules = QueueSteering::rules(ifname, src_ip, &ports, queue_id)?;
+        let mut steering = QueueSteering {
+            ifname: ifname.into(),
+            rule_ids: vec![],
+        };
+
+        for rule in rules {
+            let args: Vec<&str> = rule.iter().map(String::as_str).collect();
+            let output = run("ethtool", &args)?;
+            // ethtool reports "Added rule with ID <id>".
+            let rule_id = output
+                .split_whitespace()
+                .last()
+                .filter(|id| id.parse::<u32>().is_ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("unexpected ethtool output: {}", output.trim()),
+                    )
+                })?;
+            // Rules added so far are removed on drop if a later one fails.
+            steering.rule_ids.push(rule_id.into());
+        }
+
+        Ok(steering)
+    }
+}
+
+impl Drop for QueueSteering {
+    fn drop(&mut self) {
+        for rule_id in &self.rule_ids {
+            if let Err(e) = run("ethtool", &["-N", &self.ifname, "delete", rule_id]) {
+                warn!(
+                    "{}. May need to remove manually: 'sudo ethtool -N {} delete {}'",
+                    e, self.ifname, rule_id
+                );
+            }
+        }
+    }
+}
+
+/// Drops the RSTs the kernel sends from the scanner's source ports with an iptables rule.
+pub struct RstFilter {
+    program: &'static str,
+    rule: Vec<String>,
+}
+
+impl RstFilter {
+    pub fn new(src_ip: IpAddr, ports: RangeInclusive<u16>) -> io::Result<Self> {
+        let program = match src_ip {
+            IpAddr::V4(_) => "iptables",
+            IpAddr::V6(_) => "ip6tables",
+        };
+        let rule: Vec<String> = vec![
+            "OUTPUT".into(),
+            "-p".into(),
+            "tcp".into(),
+            "-s".into(),
+            src_ip.to_string(),
+            "--sport".into(),
+            format!("{}:{}", ports.start(), ports.end()),
+            "--tcp-flags".into(),
+            "RST".into(),
+            "RST".into(),
+            "-m".into(),
+            "comment".into(),
+            "--comment".into(),
+            "xdpscan".into(),
+            "-j".into(),
+            "DROP".into(),
+        ];
+
+        let filter = RstFilter { program, rule };
+        run(program, &filter.args("-I"))?;
+        Ok(filter)
+    }
+
+    fn args<'a>(&'a self, command: &'a str) -> Vec<&'a str> {
+        // Wait for the xtables lock rather than fail if another tool holds it.
+        let mut args = vec!["-w", command];
+        args.extend(self.rule.iter().map(String::as_str));
+        args
+    }
+}
+
+impl Drop for RstFilter {
+    fn drop(&mut self) {
+        if let Err(e) = run(self.program, &self.args("-D")) {
+            warn!(
+                "{}. May need to remove manually: 'sudo {} {}'",
+                e,
+                self.program,
+                self.args("-D").join(" ")
+            );
+        }
+    }
+}
@@ -1,113 +1,617 @@
+pub mod backend;
+pub mod banner;
+pub mod checkpoint;
+pub mod daemon;
+pub mod fingerprint;
+pub mod isolation;
+pub mod metrics;
+pub mod packet;
+mod permutation;
+pub mod probe;
 mod recv;
+pub mod reply;
+pub mod resolve;
+mod rng;
 mod send;
-use recv::recv;
-use send::send;
+pub mod shard;
+mod source;
+pub mod targets;
+pub mod traceroute;
+use backend::{xdp::XdpIo, Interface, PacketIo, PacketRx, PacketTx};
+use checkpoint::{config_hash, Checkpoint, SettledPosition};
+use fingerprint::SignatureDb;
+use log::warn;
+use metrics::Metrics;
+use packet::{ProbeOptions, TcpOption, VlanTag};
+use permutation::Permutation;
+use probe::{Cookies, ProbeModule, ScanType};
+use recv::{recv, RecvContext, Resetter};
+use reply::ReplyInfo;
+use rng::Rng;
+use send::{send, ProbeClock, SendContext, SendTimes};
+use shard::Shard;
+use source::Sources;
+use targets::TargetSource;
+use traceroute::Hop;
 
-use std::cmp;
-use std::net::{IpAddr, Ipv4Addr};
-use std::num::NonZeroU32;
-use std::sync::atomic::AtomicBool;
-use std::time;
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use std::collections::HashSet;
-use std::iter::FromIterator;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
-use etherparse::PacketBuilder;
-use xsk_rs::{
-    socket::{Config as SocketConfig, *},
-    umem::{Config as UmemConfig, *},
-    BindFlags, LibbpfFlags, XdpFlags,
-};
-
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub struct Target {
     pub ip: IpAddr,
     pub port: u16,
 }
 
+/// What a scan found out about a port, named as nmap names them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PortState {
+    Open,
+    Closed,
+    /// Probes were dropped, or only reply types that mean nothing came back.
+    Filtered,
+    /// Reachable, but whether it is open is unknown, as found by ACK scans.
+    Unfiltered,
+    /// Open, or filtered so the probes were dropped, as FIN, NULL and Xmas scans cannot tell
+    /// apart.
+    OpenFiltered,
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::Unfiltered => "unfiltered",
+            PortState::OpenFiltered => "open|filtered",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for PortState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(PortState::Open),
+            "closed" => Ok(PortState::Closed),
+            "filtered" => Ok(PortState::Filtered),
+            "unfiltered" => Ok(PortState::Unfiltered),
+            "open|filtered" => Ok(PortState::OpenFiltered),
+            _ => Err(format!("invalid port state {}", s)),
+        }
+    }
+}
+
+/// The state of a target that answered a probe.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ScanResult {
+    pub target: Target,
+    pub state: PortState,
+    /// The reply the state was read from, or `None` if its headers could not be parsed or the
+    /// result was loaded from somewhere that did not keep them.
+    pub reply: Option<ReplyInfo>,
+    /// For traceroute replies, the TTL of the probe and who answered it, see `traceroute`.
+    pub hop: Option<Hop>,
+}
+
+/// How the source address and port of each probe are picked from those in a `SrcConfig`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SrcSelection {
+    /// Cycle through the source addresses, moving on to the next source port after each full
+    /// cycle, so consecutive probes never share a source.
+    RoundRobin,
+    /// Derive the source from a hash of the target, so a target is always probed from the same
+    /// source, whatever the probe order.
+    Hashed,
+}
+
+/// Where probes are sent from.
+///
+/// Probes are sent from the addresses in `src_ips` of the same family as their target, or, if
+/// there are none, from all of them. Replies to any of `src_ips` and `src_ports` are accepted.
+#[derive(Clone, Debug)]
 pub struct SrcConfig {
     pub src_mac: [u8; 6],
     pub dst_mac: [u8; 6],
-    pub src_ip: IpAddr,
-    pub src_port: u16,
+    /// VLAN tags of every probe, outermost first. Replies must carry the same VLAN identifiers,
+    /// so the interface must not strip tags on receive: `ethtool -K <interface> rxvlan off`.
+    pub vlan_tags: Vec<VlanTag>,
+    /// Must not be empty.
+    pub src_ips: Vec<IpAddr>,
+    /// Must not be empty.
+    pub src_ports: RangeInclusive<u16>,
+    pub src_selection: SrcSelection,
 }
 
-fn validate_responders(targets: Vec<Target>, responders: Vec<Target>) -> Vec<Target> {
-    let targets: HashSet<&Target> = HashSet::from_iter(targets.iter());
+/// Timing, probe order and checkpointing of a scan.
+#[derive(Clone, Debug)]
+pub struct ScanConfig {
+    /// How long to give the receive thread to start before the first probe is sent.
+    pub startup_delay: Duration,
+    /// How long to keep listening for replies after the last probe has been sent.
+    pub wait_time: Duration,
+    /// Which probes to send and how to read the replies.
+    pub scan_type: ScanType,
+    /// Module to send probes with instead of the one of `scan_type`, for scan types defined
+    /// outside this crate. Resumed scans use it too, so it must be the one the checkpointed scan
+    /// was run with.
+    pub probe_module: Option<Arc<dyn ProbeModule>>,
+    /// Seed of the pseudo random order targets are probed in.
+    pub seed: u64,
+    /// Part of the probe order to scan, for splitting a scan between scanners.
+    pub shard: Shard,
+    /// Where to periodically write a checkpoint the scan can be resumed from.
+    pub checkpoint_path: Option<PathBuf>,
+    /// How often to write the checkpoint.
+    pub checkpoint_interval: Duration,
+    /// Whether to answer SYN-ACKs from probed targets with a RST, so they tear down the
+    /// half-open connection instead of retransmitting the SYN-ACK.
+    pub reset_open: bool,
+    /// TTL, window, IP ID and TCP options of the probes.
+    pub probe_options: ProbeOptions,
+    /// Signatures to guess the operating system of open ports from, see `ReplyInfo::os`.
+    pub fingerprints: Option<Arc<SignatureDb>>,
+    /// Where to count probes, replies and progress, see `metrics`.
+    pub metrics: Option<Arc<Metrics>>,
+}
 
-    let mut validated_responders = vec![];
-    for responder in responders {
-        if targets.contains(&responder) {
-            validated_responders.push(responder);
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            startup_delay: Duration::from_secs(1),
+            wait_time: Duration::from_secs(8),
+            scan_type: ScanType::Syn,
+            probe_module: None,
+            seed: 0,
+            shard: Shard::default(),
+            checkpoint_path: None,
+            checkpoint_interval: Duration::from_secs(60),
+            reset_open: false,
+            probe_options: ProbeOptions::default(),
+            fingerprints: None,
+            metrics: None,
         }
     }
+}
+
+/// Returns the results of the targets that responded, in the order they were given.
+fn validate_responders(targets: &[Target], responders: &[ScanResult]) -> Vec<ScanResult> {
+    // Retransmitted and duplicated replies are only reported once, with the first of them.
+    let mut firsts = HashMap::new();
+    for responder in responders {
+        firsts.entry(&responder.target).or_insert(responder);
+    }
+
+    targets
+        .iter()
+        .filter_map(|target| firsts.remove(target).cloned())
+        .collect()
+}
+
+/// Returns the results of the targets that responded, in the order they first did.
+fn unique_responders(responders: &[ScanResult]) -> Vec<ScanResult> {
+    let mut seen = HashSet::new();
+    responders
+        .iter()
+        .filter(|responder| seen.insert(&responder.target))
+        .cloned()
+        .collect()
+}
 
-    validated_responders
+fn open_targets(results: Vec<ScanResult>) -> Vec<Target> {
+    results
+        .into_iter()
+        .filter(|result| result.state == PortState::Open)
+        .map(|result| result.target)
+        .collect()
 }
 
+/// Scans `targets` from queue 0 of `ifname` over AF_XDP.
 pub fn scan(ifname: &str, src_config: SrcConfig, targets: Vec<Target>) -> Vec<Target> {
-    let rx_q_size: u32 = 4096;
-    let tx_q_size: u32 = 4096;
-    let comp_q_size: u32 = 4096;
-    let fill_q_size: u32 = 4096 * 2;
-    let frame_size: u32 = 2048;
-    let max_batch_size: usize = 64;
-    let num_frames_to_send: usize = 64;
-    let poll_ms_timeout: i32 = 100;
-    let frame_count = rx_q_size + tx_q_size;
-    let wait_time_secs = 8;
-
-    let umem_config = UmemConfig::new(
-        NonZeroU32::new(frame_count).unwrap(),
-        NonZeroU32::new(frame_size).unwrap(),
-        fill_q_size,
-        comp_q_size,
-        0,
-        false,
-    )
-    .unwrap();
-
-    let (mut umem, fq, cq, frames) = Umem::builder(umem_config)
-        .create_mmap()
-        .expect("failed to create mmap area")
-        .create_umem()
-        .expect("failed to create umem");
-
-    let socket_config = SocketConfig::new(
-        rx_q_size,
-        tx_q_size,
-        LibbpfFlags::empty(),
-        XdpFlags::empty(),
-        BindFlags::XDP_USE_NEED_WAKEUP,
-    )
-    .unwrap();
+    let queue_id = 0;
+    let io = XdpIo::new(ifname, queue_id).expect("failed to build socket");
+    scan_with(io, src_config, ScanConfig::default(), targets)
+}
 
+/// Resumes the scan of `targets` from queue 0 of `ifname` over AF_XDP, see `resume_with`.
+pub fn resume(
+    ifname: &str,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+) -> io::Result<Vec<Target>> {
     let queue_id = 0;
-    let (tx_q, rx_q) =
-        Socket::new(socket_config, &mut umem, ifname, queue_id).expect("failed to build socket");
+    let io = XdpIo::new(ifname, queue_id)?;
+    resume_with(io, src_config, scan_config, targets)
+}
 
-    let n_tx_frames = frames.len() / 2;
+/// Scans `targets` over an arbitrary packet I/O backend and returns the open ones.
+pub fn scan_with<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+) -> Vec<Target> {
+    open_targets(scan_results_with(io, src_config, scan_config, targets))
+}
 
-    let (tx_umem, rx_umem, tx_frames, rx_frames) = umem.split(frames, n_tx_frames as usize);
+/// Scans `targets` over an arbitrary packet I/O backend and returns the state of every target
+/// that answered. The others are in the scan type's `unanswered_state`.
+pub fn scan_results_with<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+) -> Vec<ScanResult> {
+    let checkpoint = new_checkpoint(&src_config, &scan_config, &targets);
+    run_scan(
+        io,
+        src_config,
+        scan_config,
+        Targets::List(targets),
+        checkpoint,
+    )
+}
+
+/// Scans the targets `source` streams over an arbitrary packet I/O backend and returns the
+/// state of every target that answered, in the order they first did.
+///
+/// Unlike lists of targets, streams are probed in the order they are read rather than
+/// shuffled, and only read as the probes go out, so they need not fit in memory. Shuffle them
+/// before streaming them if the order matters. Failing to read the stream stops the probes and
+/// returns the error once the scan has waited for the replies to those sent.
+pub fn scan_source_with<I: PacketIo, S: TargetSource + 'static>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    source: S,
+) -> io::Result<Vec<ScanResult>> {
+    let checkpoint = new_checkpoint(&src_config, &scan_config, &[]);
+    run_stream_scan(io, src_config, scan_config, source, checkpoint)
+}
+
+/// Continues a scan from the checkpoint at `scan_config.checkpoint_path` and returns the open
+/// targets.
+///
+/// Targets probed before the checkpoint was written are not probed again, and the results
+/// stored in the checkpoint are included in the returned ones. `src_config` and `targets` must
+/// be the ones the checkpointed scan was started with. The scan type, probe order and shard are
+/// taken from the checkpoint, so `scan_config.scan_type`, `scan_config.seed` and
+/// `scan_config.shard` are ignored.
+pub fn resume_with<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+) -> io::Result<Vec<Target>> {
+    resume_results_with(io, src_config, scan_config, targets).map(open_targets)
+}
+
+/// Like `resume_with`, but returns the state of every target that answered.
+pub fn resume_results_with<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+) -> io::Result<Vec<ScanResult>> {
+    let checkpoint = load_checkpoint(&scan_config, config_hash(&src_config, &targets))?;
+    Ok(run_scan(
+        io,
+        src_config,
+        scan_config,
+        Targets::List(targets),
+        checkpoint,
+    ))
+}
+
+/// Like `resume_results_with`, but for a scan started by `scan_source_with`. `source` must
+/// stream the same targets as it did for the checkpointed scan, which the checkpoint cannot
+/// check, and the ones probed before the checkpoint are read again but not probed.
+pub fn resume_source_with<I: PacketIo, S: TargetSource + 'static>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    source: S,
+) -> io::Result<Vec<ScanResult>> {
+    let checkpoint = load_checkpoint(&scan_config, config_hash(&src_config, &[]))?;
+    run_stream_scan(io, src_config, scan_config, source, checkpoint)
+}
+
+/// The checkpoint of a scan of `targets`, or of a stream if empty, that has not started yet.
+pub(crate) fn new_checkpoint(
+    src_config: &SrcConfig,
+    scan_config: &ScanConfig,
+    targets: &[Target],
+) -> Checkpoint {
+    Checkpoint {
+        scan_type: scan_config.scan_type,
+        seed: scan_config.seed,
+        shard: scan_config.shard,
+        position: 0,
+        config_hash: config_hash(src_config, targets),
+        results: vec![],
+    }
+}
+
+/// Loads the checkpoint at `scan_config.checkpoint_path`, which must have been written by a
+/// scan with `config_hash`.
+fn load_checkpoint(scan_config: &ScanConfig, config_hash: u64) -> io::Result<Checkpoint> {
+    let path = scan_config.checkpoint_path.as_ref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "resuming a scan requires a checkpoint path",
+        )
+    })?;
+    let checkpoint = Checkpoint::load(path)?;
+    if checkpoint.config_hash != config_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "checkpoint {} was written by a scan with different targets or source",
+                path.display()
+            ),
+        ));
+    }
+    Ok(checkpoint)
+}
+
+/// The targets of `source` in `shard`, from the `skip`th on, which stop at the first one that
+/// can't be read, leaving the error in `error`.
+struct StreamOrder<S> {
+    source: S,
+    shard: Shard,
+    position: u64,
+    skip: u64,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl<S: TargetSource> Iterator for StreamOrder<S> {
+    type Item = Target;
+
+    fn next(&mut self) -> Option<Target> {
+        loop {
+            let target = match self.source.next_target() {
+                Ok(target) => target?,
+                Err(e) => {
+                    *self.error.lock().unwrap() = Some(e);
+                    return None;
+                }
+            };
+            let position = self.position;
+            self.position += 1;
+            if !self.shard.contains(position) {
+                continue;
+            }
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            return Some(target);
+        }
+    }
+}
+
+fn run_stream_scan<I: PacketIo, S: TargetSource + 'static>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    source: S,
+    checkpoint: Checkpoint,
+) -> io::Result<Vec<ScanResult>> {
+    let error = Arc::new(Mutex::new(None));
+    let order = StreamOrder {
+        source,
+        shard: checkpoint.shard,
+        position: 0,
+        skip: checkpoint.position,
+        error: error.clone(),
+    };
+    let results = run_scan(
+        io,
+        src_config,
+        scan_config,
+        Targets::Stream(Box::new(order)),
+        checkpoint,
+    );
+    let error = error.lock().unwrap().take();
+    match error {
+        Some(e) => Err(e),
+        None => Ok(results),
+    }
+}
+
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) {
+    if let Err(e) = checkpoint.save(path) {
+        warn!("failed to write checkpoint {}: {}", path.display(), e);
+    }
+}
+
+/// A key for the cookies of a scan, which only needs to be hard to guess from outside.
+pub(crate) fn cookie_key() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+    Rng::new(nanos ^ u64::from(process::id())).next_u64()
+}
+
+/// The targets of a scan.
+pub(crate) enum Targets {
+    /// Probed in the pseudo random order the checkpoint's seed picks.
+    List(Vec<Target>),
+    /// Probed in the order they come, already cut down to the checkpoint's shard and position.
+    Stream(Box<dyn Iterator<Item = Target> + Send>),
+}
+
+fn run_scan<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Targets,
+    checkpoint: Checkpoint,
+) -> Vec<ScanResult> {
+    let interface = io.interface();
+    let (tx, rx) = io.split();
+    let (results, _, _) = run_scan_on(
+        tx,
+        rx,
+        interface,
+        src_config,
+        scan_config,
+        targets,
+        checkpoint,
+    );
+    results
+}
+
+/// Like `run_scan`, over the halves of a backend that was already split, which are handed back
+/// with the results so they can scan again.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_scan_on<T: PacketTx + 'static, R: PacketRx + 'static>(
+    tx: T,
+    rx: R,
+    interface: Option<Interface>,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Targets,
+    mut checkpoint: Checkpoint,
+) -> (Vec<ScanResult>, T, R) {
+    let start = checkpoint.position;
+    let shard = checkpoint.shard;
+    let (order, list, total): (Box<dyn Iterator<Item = Target> + Send>, _, _) = match targets {
+        Targets::List(targets) => {
+            let order: Vec<Target> = Permutation::new(targets.len() as u64, checkpoint.seed)
+                .enumerate()
+                .filter(|&(position, _)| shard.contains(position as u64))
+                .skip(start as usize)
+                .map(|(_, i)| targets[i as usize].clone())
+                .collect();
+            let total = start + order.len() as u64;
+            (Box::new(order.into_iter()), Some(targets), Some(total))
+        }
+        Targets::Stream(order) => (order, None, None),
+    };
+    if let Some(metrics) = &scan_config.metrics {
+        metrics.start_scan(total, start);
+    }
+    // Results of listed targets are given in their order, the others in the order they came.
+    let validate = |responders: &[ScanResult]| match &list {
+        Some(targets) => validate_responders(targets, responders),
+        None => unique_responders(responders),
+    };
 
     let tx_done = Arc::new(AtomicBool::new(false));
     let rx_done = tx_done.clone();
+    let responders = Arc::new(Mutex::new(checkpoint.results.clone()));
+    let rx_responders = responders.clone();
 
-    let recv_handle = thread::spawn(|| recv(rx_q, fq, rx_frames, rx_umem, rx_done));
-    thread::sleep(time::Duration::from_secs(1));
-    let targets_2 = targets.clone();
-    let send_handle = thread::spawn(|| {
-        send(targets_2, src_config, tx_q, cq, tx_frames, tx_umem);
+    let module = match &scan_config.probe_module {
+        Some(module) => module.clone(),
+        None => checkpoint.scan_type.module(),
+    };
+    let send_module = module.clone();
+    let cookies = Cookies::new(cookie_key());
+    let stamped = scan_config
+        .probe_options
+        .tcp_options
+        .contains(&TcpOption::Timestamp);
+    let clock = ProbeClock::new(cookie_key() as u32, stamped);
+    // Replies that come later than the scan waits for are only timed by the TSval they echo.
+    let send_times = SendTimes::new(scan_config.wait_time);
+    let recv_send_times = send_times.clone();
+    // Replies are only accepted when addressed to one of the sources probes are sent from.
+    let sources = Sources::new(&src_config);
+    let (reset_tx, reset_rx) = mpsc::channel();
+    let resetter = if scan_config.reset_open {
+        Some(Resetter {
+            targets: list
+                .as_ref()
+                .map(|targets| targets.iter().cloned().collect()),
+            resets: reset_tx,
+        })
+    } else {
+        // Lets the send thread finish as soon as the probes are out.
+        drop(reset_tx);
+        None
+    };
+
+    let fingerprints = scan_config.fingerprints.clone();
+    let recv_metrics = scan_config.metrics.clone();
+    let recv_handle = thread::spawn(move || {
+        let context = RecvContext {
+            module: &*module,
+            cookies,
+            sources,
+            responders: rx_responders,
+            resetter,
+            clock,
+            send_times: recv_send_times,
+            interface,
+            fingerprints,
+            metrics: recv_metrics,
+        };
+        recv(rx, rx_done, context)
     });
+    thread::sleep(scan_config.startup_delay);
 
-    send_handle.join().unwrap();
+    let progress = Arc::new(AtomicU64::new(0));
+    let send_progress = progress.clone();
+    let (sent_tx, sent_rx) = mpsc::channel::<()>();
+    // Keeps running until the receive thread stops requesting resets.
+    let probe_options = scan_config.probe_options.clone();
+    let ttl = probe_options.ttl;
+    let send_metrics = scan_config.metrics.clone();
+    let send_handle = thread::spawn(move || {
+        let context = SendContext {
+            src_config,
+            module: &*send_module,
+            cookies,
+            probe_options,
+            clock,
+            send_times,
+            metrics: send_metrics,
+        };
+        let probes = order.map(|target| (target, ttl));
+        send(probes, tx, context, &send_progress, sent_tx, reset_rx)
+    });
 
-    thread::sleep(time::Duration::from_secs(wait_time_secs));
+    let mut settled = SettledPosition::new(start, scan_config.wait_time);
+    while let Err(RecvTimeoutError::Timeout) = sent_rx.recv_timeout(scan_config.checkpoint_interval)
+    {
+        if let Some(path) = &scan_config.checkpoint_path {
+            let position = start + progress.load(Ordering::Relaxed);
+            checkpoint.position = settled.record(Instant::now(), position);
+            checkpoint.results = validate(&responders.lock().unwrap());
+            save_checkpoint(path, &checkpoint);
+        }
+    }
+
+    thread::sleep(scan_config.wait_time);
     tx_done.store(true, Ordering::Relaxed);
+    let rx = recv_handle.join().unwrap();
+    let tx = send_handle.join().unwrap();
 
-    let responders = recv_handle.join().unwrap();
-    validate_responders(targets, responders)
+    let results = validate(&responders.lock().unwrap());
+    if let Some(path) = &scan_config.checkpoint_path {
+        checkpoint.position = start + progress.load(Ordering::Relaxed);
+        checkpoint.results = results.clone();
+        save_checkpoint(path, &checkpoint);
+    }
+    (results, tx, rx)
 }
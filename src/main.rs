@@ -1,3 +1,715 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::ascii;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use xdpscan::backend::xdp::{DriverInfo, XdpConfig, XdpIo, XdpMode};
+use xdpscan::banner::{self, BannerConfig};
+use xdpscan::daemon::{Change, Daemon};
+use xdpscan::fingerprint::SignatureDb;
+use xdpscan::isolation::{PortReservation, QueueSteering, RstFilter};
+use xdpscan::metrics::Metrics;
+#[cfg(feature = "metrics")]
+use xdpscan::metrics::MetricsServer;
+use xdpscan::packet::{self, OsPreset, ProbeOptions, TcpOption, VlanTag};
+use xdpscan::probe::ScanType;
+use xdpscan::resolve::{AddrSelection, Hostnames, NamedResult};
+use xdpscan::shard;
+use xdpscan::targets::TargetReader;
+use xdpscan::traceroute::{self, TraceConfig};
+use xdpscan::{PortState, ScanConfig, ScanResult, SrcConfig, SrcSelection, Target};
+
+fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let octets: Vec<&str> = s.split(':').collect();
+    if octets.len() != 6 {
+        return Err(format!("invalid MAC address {}", s));
+    }
+    let mut mac = [0; 6];
+    for (byte, octet) in mac.iter_mut().zip(octets) {
+        *byte = u8::from_str_radix(octet, 16).map_err(|_| format!("invalid MAC address {}", s))?;
+    }
+    Ok(mac)
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T, String> {
+    let value = matches.value_of(name).unwrap();
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for --{}: {}", name, value))
+}
+
+fn parse_src_ips(matches: &ArgMatches) -> Result<Vec<IpAddr>, String> {
+    matches
+        .values_of("src-ip")
+        .unwrap()
+        .flat_map(|value| value.split(','))
+        .map(|ip| {
+            ip.parse()
+                .map_err(|_| format!("invalid value for --src-ip: {}", ip))
+        })
+        .collect()
+}
+
+fn parse_src_ports(matches: &ArgMatches) -> Result<RangeInclusive<u16>, String> {
+    let value = matches.value_of("src-port").unwrap();
+    let invalid = || format!("invalid value for --src-port: {}", value);
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (start, end),
+        None => (value, value),
+    };
+    let start: u16 = start.parse().map_err(|_| invalid())?;
+    let end: u16 = end.parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+/// Parses `--vlan`: one VLAN identifier for an 802.1Q tag, or an outer and an inner one for an
+/// 802.1ad and an 802.1Q tag.
+fn parse_vlan_tags(matches: &ArgMatches) -> Result<Vec<VlanTag>, String> {
+    let value = match matches.value_of("vlan") {
+        Some(value) => value,
+        None => return Ok(vec![]),
+    };
+    let invalid = || format!("invalid value for --vlan: {}", value);
+    let vids = value
+        .split(',')
+        .map(|vid| match vid.trim().parse::<u16>() {
+            Ok(vid) if vid < 4095 => Ok(vid),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<u16>, String>>()?;
+    match vids[..] {
+        [vid] => Ok(vec![VlanTag::dot1q(vid)]),
+        [outer, inner] => Ok(vec![VlanTag::dot1ad(outer), VlanTag::dot1q(inner)]),
+        _ => Err(invalid()),
+    }
+}
+
+/// Starts from the `--os` preset, if any, and overrides the fields given their own flag.
+fn parse_probe_options(matches: &ArgMatches) -> Result<ProbeOptions, String> {
+    let mut options = match matches.value_of("os") {
+        Some(_) => parse::<OsPreset>(matches, "os")?.probe_options(),
+        None => ProbeOptions::default(),
+    };
+    if matches.is_present("ttl") {
+        options.ttl = parse(matches, "ttl")?;
+    }
+    if matches.is_present("window") {
+        options.window = parse(matches, "window")?;
+    }
+    if matches.is_present("ip-id") {
+        options.ip_id = parse(matches, "ip-id")?;
+    }
+    if let Some(value) = matches.value_of("tcp-options") {
+        options.tcp_options = value
+            .split(',')
+            .filter(|option| !option.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<TcpOption>, String>>()?;
+        if packet::tcp_options_len(&options.tcp_options) > 40 {
+            return Err(format!("TCP options longer than 40 bytes: {}", value));
+        }
+    }
+    Ok(options)
+}
+
+fn parse_ports(matches: &ArgMatches) -> Result<Vec<u16>, String> {
+    match matches.value_of("ports") {
+        Some(ports) => ports
+            .split(',')
+            .map(|port| {
+                port.parse()
+                    .map_err(|_| format!("invalid value for --ports: {}", port))
+            })
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+/// Resolves the hosts that are hostnames, remembering their names in `hostnames`.
+fn parse_targets(
+    matches: &ArgMatches,
+    ports: &[u16],
+    hostnames: &mut Hostnames,
+) -> Result<Vec<Target>, String> {
+    let selection: AddrSelection = parse(matches, "resolve")?;
+    let mut ips = vec![];
+    for host in matches.values_of("hosts").into_iter().flatten() {
+        let host_ips = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => hostnames
+                .resolve(host, selection)
+                .map_err(|e| format!("failed to resolve {}: {}", host, e))?,
+        };
+        // Hostnames may share addresses, which are only scanned once.
+        for ip in host_ips {
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+
+    let mut targets = vec![];
+    for ip in ips {
+        for &port in ports {
+            targets.push(Target { ip, port });
+        }
+    }
+    Ok(targets)
+}
+
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos ^ u64::from(process::id()).rotate_left(32)
+}
+
+/// Starts serving metrics on the address given with `--metrics`, if any, until the server
+/// returned along with them is dropped.
+#[cfg(feature = "metrics")]
+fn serve_metrics(matches: &ArgMatches) -> Result<Option<(Arc<Metrics>, MetricsServer)>, String> {
+    if !matches.is_present("metrics") {
+        return Ok(None);
+    }
+    let addr: SocketAddr = parse(matches, "metrics")?;
+    let metrics = Arc::new(Metrics::new());
+    let server = MetricsServer::serve(addr, metrics.clone())
+        .map_err(|e| format!("failed to serve metrics on {}: {}", addr, e))?;
+    Ok(Some((metrics, server)))
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(matches: &ArgMatches) -> Result<Option<(Arc<Metrics>, ())>, String> {
+    if matches.is_present("metrics") {
+        return Err("--metrics needs xdpscan built with the metrics feature".to_string());
+    }
+    Ok(None)
+}
+
+fn run(matches: &ArgMatches) -> Result<(Vec<ScanResult>, Hostnames), String> {
+    let src_config = SrcConfig {
+        src_mac: parse_mac(matches.value_of("src-mac").unwrap())?,
+        dst_mac: parse_mac(matches.value_of("dst-mac").unwrap())?,
+        vlan_tags: parse_vlan_tags(matches)?,
+        src_ips: parse_src_ips(matches)?,
+        src_ports: parse_src_ports(matches)?,
+        src_selection: match matches.value_of("src-selection").unwrap() {
+            "hashed" => SrcSelection::Hashed,
+            _ => SrcSelection::RoundRobin,
+        },
+    };
+    let ports = parse_ports(matches)?;
+    let mut hostnames = Hostnames::new();
+    let targets = parse_targets(matches, &ports, &mut hostnames)?;
+    let target_reader = match matches.value_of("targets-file") {
+        Some(path) => Some(
+            TargetReader::open(path, ports)
+                .map_err(|e| format!("failed to open {}: {}", path, e))?,
+        ),
+        None => None,
+    };
+
+    let fingerprints = match matches.value_of("fingerprints") {
+        Some(path) => Some(Arc::new(
+            SignatureDb::load(Path::new(path))
+                .map_err(|e| format!("failed to load {}: {}", path, e))?,
+        )),
+        None => None,
+    };
+    // Served until the scan is over.
+    let (metrics, _metrics_server) = match serve_metrics(matches)? {
+        Some((metrics, server)) => (Some(metrics), Some(server)),
+        None => (None, None),
+    };
+
+    let scan_config = ScanConfig {
+        scan_type: parse(matches, "scan-type")?,
+        wait_time: Duration::from_secs(parse(matches, "wait")?),
+        seed: match matches.value_of("seed") {
+            Some(_) => parse(matches, "seed")?,
+            None => random_seed(),
+        },
+        shard: parse(matches, "shard")?,
+        checkpoint_path: matches.value_of("checkpoint").map(PathBuf::from),
+        checkpoint_interval: Duration::from_secs(parse(matches, "checkpoint-interval")?),
+        reset_open: matches.is_present("reset"),
+        probe_options: parse_probe_options(matches)?,
+        fingerprints,
+        metrics,
+        ..ScanConfig::default()
+    };
+
+    let ifname = matches.value_of("interface").unwrap();
+    let queue_id = parse(matches, "queue")?;
+
+    // Held until the scan is over.
+    let src_ports = &src_config.src_ports;
+    let _reservation = if matches.is_present("reserve-ports") {
+        Some(
+            PortReservation::new(&src_config.src_ips, src_ports.clone(), true)
+                .map_err(|e| format!("failed to reserve source ports: {}", e))?,
+        )
+    } else {
+        None
+    };
+    let mut steering = vec![];
+    let mut rst_filters = vec![];
+    for &src_ip in &src_config.src_ips {
+        if matches.is_present("steer-replies") {
+            steering.push(
+                QueueSteering::new(ifname, src_ip, src_ports.clone(), queue_id)
+                    .map_err(|e| format!("failed to steer replies to queue {}: {}", queue_id, e))?,
+            );
+        }
+        if matches.is_present("drop-kernel-rsts") {
+            rst_filters.push(
+                RstFilter::new(src_ip, src_ports.clone())
+                    .map_err(|e| format!("failed to add RST filter: {}", e))?,
+            );
+        }
+    }
+
+    let xdp_config = XdpConfig {
+        mode: match matches.value_of("xdp-mode").unwrap() {
+            "auto" => None,
+            _ => Some(parse::<XdpMode>(matches, "xdp-mode")?),
+        },
+        huge_pages: matches.is_present("huge-pages"),
+    };
+    let io = XdpIo::with_config(ifname, queue_id, &xdp_config)
+        .map_err(|e| format!("failed to open {} queue {}: {}", ifname, queue_id, e))?;
+    let driver = match DriverInfo::query(ifname) {
+        Ok(info) => info.driver,
+        Err(_) => "unknown".to_string(),
+    };
+    eprintln!(
+        "{} queue {}: {} driver, XDP {} mode",
+        ifname,
+        queue_id,
+        driver,
+        io.mode()
+    );
+
+    if matches.is_present("traceroute") {
+        let max_ttl: u8 = parse(matches, "max-ttl")?;
+        if max_ttl == 0 {
+            return Err("invalid value for --max-ttl: 0".to_string());
+        }
+        let trace_config = TraceConfig {
+            protocol: parse(matches, "traceroute")?,
+            max_ttl,
+            wait_time: scan_config.wait_time,
+            probe_options: scan_config.probe_options,
+            metrics: scan_config.metrics,
+            ..TraceConfig::default()
+        };
+        for trace in traceroute::trace_with(io, src_config, trace_config, targets) {
+            println!("{}", trace);
+        }
+        return Ok((vec![], hostnames));
+    }
+    if matches.is_present("every") {
+        run_daemon(matches, io, src_config, scan_config, targets, &hostnames)?;
+        return Ok((vec![], hostnames));
+    }
+
+    let scan_type = scan_config.scan_type;
+    let results = match (target_reader, matches.is_present("resume")) {
+        (Some(reader), true) => xdpscan::resume_source_with(io, src_config, scan_config, reader)
+            .map_err(|e| format!("failed to resume scan: {}", e))?,
+        (Some(reader), false) => xdpscan::scan_source_with(io, src_config, scan_config, reader)
+            .map_err(|e| format!("failed to read targets: {}", e))?,
+        (None, true) => xdpscan::resume_results_with(io, src_config, scan_config, targets)
+            .map_err(|e| format!("failed to resume scan: {}", e))?,
+        (None, false) => xdpscan::scan_results_with(io, src_config, scan_config, targets),
+    };
+
+    Ok((reported_results(scan_type, results), hostnames))
+}
+
+fn print_results(matches: &ArgMatches, results: Vec<ScanResult>, hostnames: &Hostnames) {
+    // OS guesses are printed with the reply they were made from.
+    let reply_info = matches.is_present("reply-info") || matches.is_present("fingerprints");
+    for NamedResult { result, hostnames } in hostnames.name_results(results) {
+        let addr = SocketAddr::new(result.target.ip, result.target.port);
+        let reply = result.reply.filter(|_| reply_info);
+        // The state is always given before hostnames and replies, so `merge` can read them
+        // back.
+        let mut line = addr.to_string();
+        if result.state != PortState::Open || !hostnames.is_empty() || reply.is_some() {
+            line += &format!(" {}", result.state);
+        }
+        if !hostnames.is_empty() {
+            line += &format!(" {}", hostnames.join(","));
+        }
+        if let Some(reply) = reply {
+            line += &format!(" {}", reply);
+        }
+        println!("{}", line);
+    }
+}
+
+/// Scans `targets` every `--every` seconds until killed, printing the results of every run and
+/// what changed since the previous one.
+fn run_daemon(
+    matches: &ArgMatches,
+    io: XdpIo,
+    src_config: SrcConfig,
+    scan_config: ScanConfig,
+    targets: Vec<Target>,
+    hostnames: &Hostnames,
+) -> Result<(), String> {
+    let interval = Duration::from_secs(parse(matches, "every")?);
+    let scan_type = scan_config.scan_type;
+    let mut daemon = Daemon::new(io, src_config, scan_config, targets);
+    daemon.run(interval, |run| {
+        let started_at = run
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        println!("# run {} started at {}", run.number, started_at);
+        print_results(matches, reported_results(scan_type, run.results), hostnames);
+        if let Some(changes) = run.changes {
+            // Like the results, changes between closed and filtered are left out of SYN scans.
+            let changes: Vec<Change> = changes
+                .into_iter()
+                .filter(|change| {
+                    scan_type != ScanType::Syn
+                        || change.before == Some(PortState::Open)
+                        || change.after == Some(PortState::Open)
+                })
+                .collect();
+            println!("# {} changed since run {}", changes.len(), run.number - 1);
+            for change in changes {
+                println!("{}", change);
+            }
+        }
+        true
+    });
+    Ok(())
+}
+
+/// The results worth printing of a scan of `scan_type`.
+fn reported_results(scan_type: ScanType, results: Vec<ScanResult>) -> Vec<ScanResult> {
+    // Apart from open ports, only closed ones answer SYN scans, and there are plenty of those.
+    if scan_type == ScanType::Syn {
+        results
+            .into_iter()
+            .filter(|result| result.state == PortState::Open)
+            .collect()
+    } else {
+        results
+    }
+}
+
+fn merge(matches: &ArgMatches) -> Result<(Vec<ScanResult>, Hostnames), String> {
+    let paths: Vec<&str> = matches.values_of("files").unwrap().collect();
+    shard::merge_results(&paths).map_err(|e| format!("failed to merge results: {}", e))
+}
+
 fn main() {
-    println!("Hello, world!");
+    env_logger::init();
+
+    let matches = App::new("xdpscan")
+        .about("TCP SYN scanner using AF_XDP")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .arg(
+            Arg::with_name("interface")
+                .short("i")
+                .long("interface")
+                .takes_value(true)
+                .required(true)
+                .help("Interface to scan from"),
+        )
+        .arg(
+            Arg::with_name("queue")
+                .long("queue")
+                .takes_value(true)
+                .default_value("0")
+                .help("Queue of the interface to bind to"),
+        )
+        .arg(
+            Arg::with_name("xdp-mode")
+                .long("xdp-mode")
+                .takes_value(true)
+                .possible_values(&["auto", "zero-copy", "copy", "generic"])
+                .default_value("auto")
+                .help("XDP mode, or auto for the fastest the driver supports"),
+        )
+        .arg(
+            Arg::with_name("huge-pages")
+                .long("huge-pages")
+                .help("Back the UMEM with huge pages"),
+        )
+        .arg(
+            Arg::with_name("src-mac")
+                .long("src-mac")
+                .takes_value(true)
+                .required(true)
+                .help("MAC address of the interface"),
+        )
+        .arg(
+            Arg::with_name("dst-mac")
+                .long("dst-mac")
+                .takes_value(true)
+                .required(true)
+                .help("MAC address of the gateway or target"),
+        )
+        .arg(
+            Arg::with_name("src-ip")
+                .long("src-ip")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .help("Comma separated addresses to send probes from"),
+        )
+        .arg(
+            Arg::with_name("src-port")
+                .long("src-port")
+                .takes_value(true)
+                .default_value("4321")
+                .help("Port, or range of ports a-b, to send probes from"),
+        )
+        .arg(
+            Arg::with_name("src-selection")
+                .long("src-selection")
+                .takes_value(true)
+                .possible_values(&["round-robin", "hashed"])
+                .default_value("round-robin")
+                .help("Cycle through the sources, or pick one by hashing the target"),
+        )
+        .arg(
+            Arg::with_name("vlan")
+                .long("vlan")
+                .takes_value(true)
+                .help("VLAN to tag probes with, or outer,inner VLANs for QinQ"),
+        )
+        .arg(
+            Arg::with_name("ports")
+                .short("p")
+                .long("ports")
+                .takes_value(true)
+                .required_unless("targets-file")
+                .help("Comma separated ports to probe on every host"),
+        )
+        .arg(
+            Arg::with_name("scan-type")
+                .long("scan-type")
+                .takes_value(true)
+                .possible_values(&["syn", "ack", "fin", "null", "xmas", "window"])
+                .default_value("syn")
+                .help("SYN scans print open ports, other scans every port that answered"),
+        )
+        .arg(
+            Arg::with_name("os")
+                .long("os")
+                .takes_value(true)
+                .possible_values(&["linux", "windows", "macos"])
+                .help("Make probes look like SYNs of this operating system"),
+        )
+        .arg(
+            Arg::with_name("ttl")
+                .long("ttl")
+                .takes_value(true)
+                .help("TTL or hop limit of probes [default: 20]"),
+        )
+        .arg(
+            Arg::with_name("window")
+                .long("window")
+                .takes_value(true)
+                .help("TCP window of probes [default: 4]"),
+        )
+        .arg(
+            Arg::with_name("ip-id")
+                .long("ip-id")
+                .takes_value(true)
+                .possible_values(&["zero", "random", "incrementing"])
+                .help("How to fill the IPv4 ID of probes [default: zero]"),
+        )
+        .arg(
+            Arg::with_name("tcp-options")
+                .long("tcp-options")
+                .takes_value(true)
+                .help("Comma separated TCP options of probes: mss=N, sack, ts, ws=N, nop, eol"),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .takes_value(true)
+                .default_value("8")
+                .help("Seconds to wait for replies after the last probe"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("Seed of the probe order, random by default"),
+        )
+        .arg(
+            Arg::with_name("shard")
+                .long("shard")
+                .takes_value(true)
+                .default_value("0/1")
+                .help("Part i/n of the probe order to scan, counting from 0"),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .takes_value(true)
+                .help("File to periodically write a checkpoint to"),
+        )
+        .arg(
+            Arg::with_name("checkpoint-interval")
+                .long("checkpoint-interval")
+                .takes_value(true)
+                .default_value("60")
+                .help("Seconds between checkpoints"),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .requires("checkpoint")
+                .help("Continue the scan from the checkpoint instead of starting over"),
+        )
+        .arg(
+            Arg::with_name("reset")
+                .long("reset")
+                .help("Answer SYN-ACKs with a RST instead of leaving connections half-open"),
+        )
+        .arg(
+            Arg::with_name("reserve-ports")
+                .long("reserve-ports")
+                .help("Stop the kernel using the source ports, when --src-ip is the host's own")
+                .long_help(concat!(
+                    "Stop the kernel using the source ports, when --src-ip is the host's own. ",
+                    "Holds a socket, and so a file descriptor, on every source port of every ",
+                    "address if there are 256 or fewer, and adds the ports to ",
+                    "net.ipv4.ip_local_reserved_ports"
+                )),
+        )
+        .arg(
+            Arg::with_name("steer-replies")
+                .long("steer-replies")
+                .help("Steer replies to --queue with an ethtool ntuple filter"),
+        )
+        .arg(
+            Arg::with_name("drop-kernel-rsts")
+                .long("drop-kernel-rsts")
+                .help("Drop RSTs the kernel sends from the source ports with an iptables rule"),
+        )
+        .arg(
+            Arg::with_name("banners")
+                .long("banners")
+                .help("Connect to every open port and print the first bytes it answers with"),
+        )
+        .arg(
+            Arg::with_name("targets-file")
+                .long("targets-file")
+                .takes_value(true)
+                .conflicts_with("hosts")
+                .help("File of targets to stream, one per line, or - for stdin"),
+        )
+        .arg(
+            Arg::with_name("resolve")
+                .long("resolve")
+                .takes_value(true)
+                .possible_values(&["all", "first-per-family"])
+                .default_value("all")
+                .help("Scan every address of a hostname, or its first IPv4 and IPv6 one"),
+        )
+        .arg(
+            Arg::with_name("reply-info")
+                .long("reply-info")
+                .help("Print the headers, timing and interface of every reply"),
+        )
+        .arg(
+            Arg::with_name("fingerprints")
+                .long("fingerprints")
+                .takes_value(true)
+                .help("p0f database to guess the OS of open ports with"),
+        )
+        .arg(
+            Arg::with_name("traceroute")
+                .long("traceroute")
+                .takes_value(true)
+                .possible_values(&["tcp", "udp"])
+                .conflicts_with_all(&["targets-file", "resume", "banners"])
+                .help("Print the path to every target, probing with TCP or UDP"),
+        )
+        .arg(
+            Arg::with_name("max-ttl")
+                .long("max-ttl")
+                .takes_value(true)
+                .default_value("30")
+                .help("Highest TTL of traceroute probes"),
+        )
+        .arg(
+            Arg::with_name("every")
+                .long("every")
+                .takes_value(true)
+                .conflicts_with_all(&["targets-file", "resume", "traceroute", "banners"])
+                .help("Scan again every this many seconds, printing what changed"),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .takes_value(true)
+                .help("Serve Prometheus metrics of the scan over HTTP"),
+        )
+        .arg(
+            Arg::with_name("hosts")
+                .multiple(true)
+                .required_unless("targets-file")
+                .help("Addresses or hostnames to scan"),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Combines the results or checkpoints of the shards of a scan")
+                .arg(Arg::with_name("files").multiple(true).required(true)),
+        )
+        .get_matches();
+
+    let result = match matches.subcommand_matches("merge") {
+        Some(merge_matches) => merge(merge_matches),
+        None => run(&matches),
+    };
+    match result {
+        Ok((results, _)) if matches.is_present("banners") => {
+            let open: Vec<Target> = results
+                .into_iter()
+                .filter(|result| result.state == PortState::Open)
+                .map(|result| result.target)
+                .collect();
+            for banner in banner::grab_banners(&open, &BannerConfig::default()) {
+                let data: String = banner
+                    .data
+                    .iter()
+                    .flat_map(|&byte| ascii::escape_default(byte))
+                    .map(char::from)
+                    .collect();
+                let addr = SocketAddr::new(banner.target.ip, banner.target.port);
+                match banner.error {
+                    Some(error) => println!("{} error: {}", addr, error),
+                    None => println!("{} {}", addr, data),
+                }
+            }
+        }
+        Ok((results, hostnames)) => print_results(&matches, results, &hostnames),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 }
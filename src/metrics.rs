@@ -0,0 +1,375 @@
+//! Counters of running scans, written in the Prometheus text format.
+//!
+//! Scans count into the `Metrics` set as their `ScanConfig::metrics`, and traceroutes as their
+//! `TraceConfig::metrics`, which may be shared by several of them, one after the other, to
+//! count across all of them. With the `metrics` cargo feature, `MetricsServer` serves them
+//! over HTTP for Prometheus to scrape:
+//!
+//! - `xdpscan_probes_sent_total`, `xdpscan_resets_sent_total` and
+//!   `xdpscan_frames_received_total`: frames sent and received.
+//! - `xdpscan_replies_total`: replies accepted, labelled by the `state` of the port, counting
+//!   retransmitted ones again.
+//! - `xdpscan_probe_rate`: probes sent per second, over the last second.
+//! - `xdpscan_tx_frames_in_flight`: frames in the TX and completion rings, waiting for the
+//!   kernel to send them.
+//! - `xdpscan_rx_batch_frames`: frames taken off the RX ring at once, last time it had any.
+//! - `xdpscan_xdp_*_total`: the drop counters of the AF_XDP socket, see `XdpStats`.
+//! - `xdpscan_scans_total`, `xdpscan_scan_targets` and `xdpscan_scan_position`: scans started,
+//!   and how many targets the current one has, 0 for streams, and has probed.
+use crate::backend::XdpStats;
+use crate::PortState;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "metrics")]
+pub use server::MetricsServer;
+
+/// How long probes are counted for to measure the rate.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+const STATES: [PortState; 5] = [
+    PortState::Open,
+    PortState::Closed,
+    PortState::Filtered,
+    PortState::Unfiltered,
+    PortState::OpenFiltered,
+];
+
+fn state_index(state: PortState) -> usize {
+    STATES.iter().position(|&s| s == state).unwrap()
+}
+
+/// Probes sent since `start`, to measure the rate from.
+#[derive(Debug)]
+struct RateWindow {
+    start: Instant,
+    probes_sent: u64,
+    rate: f64,
+}
+
+/// Counters of scans, see the module documentation.
+#[derive(Debug)]
+pub struct Metrics {
+    probes_sent: AtomicU64,
+    resets_sent: AtomicU64,
+    frames_received: AtomicU64,
+    replies: [AtomicU64; 5],
+    tx_in_flight: AtomicU64,
+    rx_batch_frames: AtomicU64,
+    xdp_stats: Mutex<Option<XdpStats>>,
+    rate: Mutex<RateWindow>,
+    scans: AtomicU64,
+    targets: AtomicU64,
+    position: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            probes_sent: AtomicU64::new(0),
+            resets_sent: AtomicU64::new(0),
+            frames_received: AtomicU64::new(0),
+            replies: Default::default(),
+            tx_in_flight: AtomicU64::new(0),
+            rx_batch_frames: AtomicU64::new(0),
+            xdp_stats: Mutex::new(None),
+            rate: Mutex::new(RateWindow {
+                start: Instant::now(),
+                probes_sent: 0,
+                rate: 0.0,
+            }),
+            scans: AtomicU64::new(0),
+            targets: AtomicU64::new(0),
+            position: AtomicU64::new(0),
+        }
+    }
+
+    /// Records the start of a scan of `targets`, `None` for streams, from `position` on.
+    pub(crate) fn start_scan(&self, targets: Option<u64>, position: u64) {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.targets.store(targets.unwrap_or(0), Ordering::Relaxed);
+        self.position.store(position, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_probes(&self, probes: usize) {
+        let probes = probes as u64;
+        let probes_sent = self.probes_sent.fetch_add(probes, Ordering::Relaxed) + probes;
+        self.position.fetch_add(probes, Ordering::Relaxed);
+
+        let mut window = self.rate.lock().unwrap();
+        let elapsed = window.start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            window.rate = (probes_sent - window.probes_sent) as f64 / elapsed.as_secs_f64();
+            window.start = Instant::now();
+            window.probes_sent = probes_sent;
+        }
+    }
+
+    pub(crate) fn add_resets(&self, resets: usize) {
+        self.resets_sent.fetch_add(resets as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_in_flight(&self, frames: usize) {
+        self.tx_in_flight.store(frames as u64, Ordering::Relaxed);
+    }
+
+    /// Records a batch of frames taken off the RX ring.
+    pub(crate) fn add_frames_received(&self, frames: usize) {
+        self.frames_received
+            .fetch_add(frames as u64, Ordering::Relaxed);
+        if frames > 0 {
+            self.rx_batch_frames.store(frames as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn add_reply(&self, state: PortState) {
+        self.replies[state_index(state)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_xdp_stats(&self, stats: XdpStats) {
+        *self.xdp_stats.lock().unwrap() = Some(stats);
+    }
+
+    /// Probes sent per second over the last second, or 0 if none were.
+    fn rate(&self) -> f64 {
+        let window = self.rate.lock().unwrap();
+        if window.start.elapsed() >= 2 * RATE_WINDOW {
+            0.0
+        } else {
+            window.rate
+        }
+    }
+
+    /// Writes the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            writeln!(out, "# HELP xdpscan_{} {}", name, help).unwrap();
+            writeln!(out, "# TYPE xdpscan_{} {}", name, kind).unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "xdpscan_{}{} {}", name, labels, value).unwrap();
+            }
+        };
+        let value = |value: u64| [(String::new(), value.to_string())];
+
+        metric(
+            "probes_sent_total",
+            "counter",
+            "Probes sent.",
+            &value(load(&self.probes_sent)),
+        );
+        metric(
+            "resets_sent_total",
+            "counter",
+            "RSTs sent to tear down half-open connections.",
+            &value(load(&self.resets_sent)),
+        );
+        metric(
+            "frames_received_total",
+            "counter",
+            "Frames received, replies or not.",
+            &value(load(&self.frames_received)),
+        );
+        let replies: Vec<_> = STATES
+            .iter()
+            .zip(&self.replies)
+            .map(|(state, count)| (format!("{{state=\"{}\"}}", state), load(count).to_string()))
+            .collect();
+        metric(
+            "replies_total",
+            "counter",
+            "Replies accepted, by the state of the port.",
+            &replies,
+        );
+        metric(
+            "probe_rate",
+            "gauge",
+            "Probes sent per second over the last second.",
+            &[(String::new(), self.rate().to_string())],
+        );
+        metric(
+            "tx_frames_in_flight",
+            "gauge",
+            "Frames handed to the kernel to send that it has not completed.",
+            &value(load(&self.tx_in_flight)),
+        );
+        metric(
+            "rx_batch_frames",
+            "gauge",
+            "Frames in the last batch received.",
+            &value(load(&self.rx_batch_frames)),
+        );
+        if let Some(stats) = *self.xdp_stats.lock().unwrap() {
+            let counters = [
+                ("rx_dropped", stats.rx_dropped, "Frames the socket dropped."),
+                (
+                    "rx_invalid_descs",
+                    stats.rx_invalid_descs,
+                    "Invalid descriptors in the fill ring.",
+                ),
+                (
+                    "tx_invalid_descs",
+                    stats.tx_invalid_descs,
+                    "Invalid descriptors in the TX ring.",
+                ),
+                (
+                    "rx_ring_full",
+                    stats.rx_ring_full,
+                    "Frames dropped because the RX ring was full.",
+                ),
+                (
+                    "rx_fill_ring_empty_descs",
+                    stats.rx_fill_ring_empty_descs,
+                    "Times the fill ring was empty.",
+                ),
+                (
+                    "tx_ring_empty_descs",
+                    stats.tx_ring_empty_descs,
+                    "Times the TX ring was empty.",
+                ),
+            ];
+            for (name, count, help) in &counters {
+                metric(
+                    &format!("xdp_{}_total", name),
+                    "counter",
+                    help,
+                    &value(*count),
+                );
+            }
+        }
+        metric(
+            "scans_total",
+            "counter",
+            "Scans started.",
+            &value(load(&self.scans)),
+        );
+        metric(
+            "scan_targets",
+            "gauge",
+            "Targets of the current scan, or 0 if they are streamed.",
+            &value(load(&self.targets)),
+        );
+        metric(
+            "scan_position",
+            "gauge",
+            "Targets of the current scan probed.",
+            &value(load(&self.position)),
+        );
+        out
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod server {
+    use super::Metrics;
+    use log::{debug, warn};
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    /// Bytes of a request, headers included, read before answering it.
+    const MAX_REQUEST_LEN: u64 = 8 * 1024;
+
+    /// Serves `Metrics` at `/metrics` over HTTP, from a thread of its own, until dropped.
+    pub struct MetricsServer {
+        addr: SocketAddr,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl MetricsServer {
+        pub fn serve<A: ToSocketAddrs>(addr: A, metrics: Arc<Metrics>) -> io::Result<Self> {
+            let listener = TcpListener::bind(addr)?;
+            let addr = listener.local_addr()?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread_stop = stop.clone();
+            let thread = thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if thread_stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let result = stream.and_then(|stream| respond(stream, &metrics));
+                    if let Err(e) = result {
+                        debug!("failed to serve metrics: {}", e);
+                    }
+                }
+            });
+            Ok(MetricsServer {
+                addr,
+                stop,
+                thread: Some(thread),
+            })
+        }
+
+        /// The address the server listens on, with the port picked if it was asked for 0.
+        pub fn local_addr(&self) -> SocketAddr {
+            self.addr
+        }
+    }
+
+    impl Drop for MetricsServer {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            // Wakes the thread up from waiting for a connection.
+            if let Err(e) = TcpStream::connect(self.addr) {
+                warn!("failed to stop the metrics server: {}", e);
+                return;
+            }
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+        // Requests are answered one at a time, so slow clients can't hold the others up long.
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        // Nor can they make the server buffer requests of any length.
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_LEN));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // The headers are of no interest, but must be read before answering.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+
+        let mut fields = request_line.split_whitespace();
+        let method = fields.next();
+        let path = fields
+            .next()
+            .map(|target| target.split('?').next().unwrap());
+        let (status, body) = match (method, path) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+            (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+        };
+        write!(
+            stream,
+            concat!(
+                "HTTP/1.1 {}\r\n",
+                "Content-Type: text/plain; version=0.0.4\r\n",
+                "Content-Length: {}\r\n",
+                "Connection: close\r\n",
+                "\r\n",
+                "{}"
+            ),
+            status,
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
@@ -0,0 +1,1259 @@
+//! Building the Ethernet frames carrying probes, and reading the TCP segments and ICMP errors in
+//! replies.
+//!
+//! Frames are written by hand rather than with etherparse's `PacketBuilder`, which has no way
+//! to set the IP identification or TCP options. Every field a TCP/IP stack fingerprint is made
+//! of is configurable through `ProbeOptions`, with `OsPreset` providing values that mimic the
+//! SYNs of common operating systems.
+//!
+//! Probes are written straight into the TX buffers from a `FrameTemplate` per kind of probe,
+//! with only the per-target fields patched in.
+use crate::rng::Rng;
+use log::debug;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// TCP header flags.
+pub mod flags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+    pub const URG: u8 = 0x20;
+    pub const ECE: u8 = 0x40;
+    pub const CWR: u8 = 0x80;
+}
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const TCP_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+
+/// A TCP option, in the order given it is written to the header.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TcpOption {
+    MaxSegmentSize(u16),
+    SackPermitted,
+    /// Timestamps, with the TSval taken from the frame and a TSecr of 0.
+    Timestamp,
+    WindowScale(u8),
+    Nop,
+    /// End of option list. Options are padded with it to a multiple of 4 bytes anyway.
+    End,
+}
+
+impl TcpOption {
+    fn len(self) -> usize {
+        match self {
+            TcpOption::MaxSegmentSize(_) => 4,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Timestamp => 10,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::Nop | TcpOption::End => 1,
+        }
+    }
+
+    fn write(self, buf: &mut [u8], timestamp: u32) {
+        match self {
+            TcpOption::MaxSegmentSize(mss) => {
+                buf[..2].copy_from_slice(&[2, 4]);
+                buf[2..4].copy_from_slice(&mss.to_be_bytes());
+            }
+            TcpOption::SackPermitted => buf[..2].copy_from_slice(&[4, 2]),
+            TcpOption::Timestamp => {
+                buf[..2].copy_from_slice(&[8, 10]);
+                buf[2..6].copy_from_slice(&timestamp.to_be_bytes());
+                buf[6..10].copy_from_slice(&[0; 4]);
+            }
+            TcpOption::WindowScale(shift) => buf[..3].copy_from_slice(&[3, 3, shift]),
+            TcpOption::Nop => buf[0] = 1,
+            TcpOption::End => buf[0] = 0,
+        }
+    }
+}
+
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcpOption::MaxSegmentSize(mss) => write!(f, "mss={}", mss),
+            TcpOption::SackPermitted => write!(f, "sack"),
+            TcpOption::Timestamp => write!(f, "ts"),
+            TcpOption::WindowScale(shift) => write!(f, "ws={}", shift),
+            TcpOption::Nop => write!(f, "nop"),
+            TcpOption::End => write!(f, "eol"),
+        }
+    }
+}
+
+impl FromStr for TcpOption {
+    type Err = String;
+
+    /// Parses an option as written by `Display`: `mss=N`, `sack`, `ts`, `ws=N`, `nop` or
+    /// `eol`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid TCP option {}", s);
+        let option = match s.split_once('=') {
+            Some(("mss", mss)) => TcpOption::MaxSegmentSize(mss.parse().map_err(|_| invalid())?),
+            Some(("ws", shift)) => {
+                let shift = shift.parse().map_err(|_| invalid())?;
+                // RFC 7323 caps the shift at 14.
+                if shift > 14 {
+                    return Err(invalid());
+                }
+                TcpOption::WindowScale(shift)
+            }
+            Some(_) => return Err(invalid()),
+            None => match s {
+                "sack" => TcpOption::SackPermitted,
+                "ts" => TcpOption::Timestamp,
+                "nop" => TcpOption::Nop,
+                "eol" => TcpOption::End,
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(option)
+    }
+}
+
+/// Length of `options` in a TCP header, padded to a multiple of 4 bytes.
+pub fn tcp_options_len(options: &[TcpOption]) -> usize {
+    let len: usize = options.iter().map(|option| option.len()).sum();
+    (len + 3) & !3
+}
+
+/// How the identification field of IPv4 probes is filled. IPv6 headers have none.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IpIdStrategy {
+    /// Always 0, as stacks setting the don't fragment bit often send.
+    Zero,
+    /// A new random value for every probe.
+    Random,
+    /// A counter starting at a random value, as a host sharing one counter between all
+    /// connections would send.
+    Incrementing,
+}
+
+impl FromStr for IpIdStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(IpIdStrategy::Zero),
+            "random" => Ok(IpIdStrategy::Random),
+            "incrementing" => Ok(IpIdStrategy::Incrementing),
+            _ => Err(format!("invalid IP ID strategy {}", s)),
+        }
+    }
+}
+
+/// The fields of a probe that do not depend on its source or target.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProbeOptions {
+    /// IPv4 time to live, or IPv6 hop limit.
+    pub ttl: u8,
+    pub window: u16,
+    pub ip_id: IpIdStrategy,
+    /// At most 40 bytes of options.
+    pub tcp_options: Vec<TcpOption>,
+}
+
+impl Default for ProbeOptions {
+    /// The bare SYNs xdpscan has always sent: no options, a window of 4, a TTL of 20 and an IP
+    /// ID of 0. Use an `OsPreset` for probes that blend in.
+    fn default() -> Self {
+        ProbeOptions {
+            ttl: 20,
+            window: 4,
+            ip_id: IpIdStrategy::Zero,
+            tcp_options: vec![],
+        }
+    }
+}
+
+/// Probe options copying the SYNs of a common operating system's TCP stack.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OsPreset {
+    Linux,
+    Windows,
+    MacOs,
+}
+
+impl OsPreset {
+    pub fn probe_options(self) -> ProbeOptions {
+        use TcpOption::*;
+        match self {
+            OsPreset::Linux => ProbeOptions {
+                ttl: 64,
+                window: 64240,
+                ip_id: IpIdStrategy::Random,
+                tcp_options: vec![
+                    MaxSegmentSize(1460),
+                    SackPermitted,
+                    Timestamp,
+                    Nop,
+                    WindowScale(7),
+                ],
+            },
+            OsPreset::Windows => ProbeOptions {
+                ttl: 128,
+                window: 64240,
+                ip_id: IpIdStrategy::Incrementing,
+                tcp_options: vec![
+                    MaxSegmentSize(1460),
+                    Nop,
+                    WindowScale(8),
+                    Nop,
+                    Nop,
+                    SackPermitted,
+                ],
+            },
+            OsPreset::MacOs => ProbeOptions {
+                ttl: 64,
+                window: 65535,
+                ip_id: IpIdStrategy::Zero,
+                tcp_options: vec![
+                    MaxSegmentSize(1460),
+                    Nop,
+                    WindowScale(6),
+                    Nop,
+                    Nop,
+                    Timestamp,
+                    SackPermitted,
+                    End,
+                ],
+            },
+        }
+    }
+}
+
+impl FromStr for OsPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(OsPreset::Linux),
+            "windows" => Ok(OsPreset::Windows),
+            "macos" => Ok(OsPreset::MacOs),
+            _ => Err(format!("invalid OS preset {}", s)),
+        }
+    }
+}
+
+/// Hands out IPv4 identification values according to an `IpIdStrategy`.
+#[derive(Clone, Debug)]
+pub(crate) struct IpIds {
+    strategy: IpIdStrategy,
+    rng: Rng,
+    next: u16,
+}
+
+impl IpIds {
+    pub(crate) fn new(strategy: IpIdStrategy, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let next = rng.next_u64() as u16;
+        IpIds {
+            strategy,
+            rng,
+            next,
+        }
+    }
+
+    pub(crate) fn next_id(&mut self) -> u16 {
+        match self.strategy {
+            IpIdStrategy::Zero => 0,
+            IpIdStrategy::Random => self.rng.next_u64() as u16,
+            IpIdStrategy::Incrementing => {
+                let id = self.next;
+                self.next = self.next.wrapping_add(1);
+                id
+            }
+        }
+    }
+}
+
+/// Tag protocol identifier of 802.1Q VLAN tags.
+pub const TPID_8021Q: u16 = 0x8100;
+/// Tag protocol identifier of 802.1ad service tags, the outer tags of QinQ frames.
+pub const TPID_8021AD: u16 = 0x88a8;
+const MAX_VLAN_TAGS: usize = 2;
+
+/// A VLAN tag of an Ethernet frame.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct VlanTag {
+    /// `TPID_8021Q` or `TPID_8021AD`.
+    pub tpid: u16,
+    /// Priority code point, from 0 to 7.
+    pub pcp: u8,
+    /// VLAN identifier, from 0 to 4095.
+    pub vid: u16,
+}
+
+impl VlanTag {
+    /// An 802.1Q tag of VLAN `vid`.
+    pub fn dot1q(vid: u16) -> Self {
+        VlanTag {
+            tpid: TPID_8021Q,
+            pcp: 0,
+            vid,
+        }
+    }
+
+    /// An 802.1ad tag of service VLAN `vid`.
+    pub fn dot1ad(vid: u16) -> Self {
+        VlanTag {
+            tpid: TPID_8021AD,
+            pcp: 0,
+            vid,
+        }
+    }
+
+    fn tci(self) -> u16 {
+        u16::from(self.pcp & 0x07) << 13 | self.vid & 0x0fff
+    }
+}
+
+/// A TCP segment in an IPv4 or IPv6 packet in an Ethernet frame.
+///
+/// If either address is IPv6, the packet is IPv6 and an IPv4 address is written as its
+/// IPv4-mapped IPv6 address.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    /// VLAN tags, outermost first. At most two.
+    pub vlan_tags: &'a [VlanTag],
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub ttl: u8,
+    /// Ignored for IPv6.
+    pub ip_id: u16,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    /// See `flags`.
+    pub flags: u8,
+    pub window: u16,
+    pub options: &'a [TcpOption],
+    /// TSval of a `TcpOption::Timestamp`.
+    pub timestamp: u32,
+}
+
+pub(crate) fn ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped().octets(),
+        IpAddr::V6(ipv6) => ipv6.octets(),
+    }
+}
+
+/// Adds `data` to a ones' complement sum of 16 bit words, as the Internet checksum of IP, TCP,
+/// UDP and ICMP takes it.
+pub fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u32::from(u16::from_be_bytes([word[0], word[1]]));
+    }
+    if let [last] = words.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+/// Adds `data` at `offset` of the checksummed data to a ones' complement sum. Data at odd
+/// offsets straddles words, which swaps the bytes of its sum.
+fn checksum_add_at(sum: u32, offset: usize, data: &[u8]) -> u32 {
+    if offset & 1 == 0 {
+        return checksum_add(sum, data);
+    }
+    let mut part = checksum_add(0, data);
+    while part > 0xffff {
+        part = (part & 0xffff) + (part >> 16);
+    }
+    sum + u32::from((part as u16).swap_bytes())
+}
+
+/// Folds a ones' complement sum into the 16 bit checksum field value.
+pub fn checksum_fold(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Writes the Ethernet and IP headers of a packet carrying `payload_len` bytes of `protocol` to
+/// the start of `buf`, and returns the offset of the payload and the sum of its pseudo header.
+///
+/// The packet is IPv6 if either address is, see `Frame`.
+#[allow(clippy::too_many_arguments)]
+fn write_headers(
+    buf: &mut [u8],
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    vlan_tags: &[VlanTag],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    ttl: u8,
+    ip_id: u16,
+    protocol: u8,
+    payload_len: usize,
+) -> (usize, u32) {
+    assert!(vlan_tags.len() <= MAX_VLAN_TAGS, "more than two VLAN tags");
+    buf[0..6].copy_from_slice(&dst_mac);
+    buf[6..12].copy_from_slice(&src_mac);
+    for (i, tag) in vlan_tags.iter().enumerate() {
+        let offset = 12 + 4 * i;
+        buf[offset..offset + 2].copy_from_slice(&tag.tpid.to_be_bytes());
+        buf[offset + 2..offset + 4].copy_from_slice(&tag.tci().to_be_bytes());
+    }
+    let ipv6 = src_ip.is_ipv6() || dst_ip.is_ipv6();
+    let (ether_type, ip_header_len) = if ipv6 {
+        (ETHER_TYPE_IPV6, IPV6_HEADER_LEN)
+    } else {
+        (ETHER_TYPE_IPV4, IPV4_HEADER_LEN)
+    };
+    let ip_offset = ETHERNET_HEADER_LEN + 4 * vlan_tags.len();
+    buf[ip_offset - 2..ip_offset].copy_from_slice(&ether_type.to_be_bytes());
+
+    let ip = &mut buf[ip_offset..ip_offset + ip_header_len];
+    let pseudo_header_sum = match (src_ip, dst_ip) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            ip[0] = 0x45;
+            ip[1] = 0;
+            ip[2..4].copy_from_slice(&((IPV4_HEADER_LEN + payload_len) as u16).to_be_bytes());
+            ip[4..6].copy_from_slice(&ip_id.to_be_bytes());
+            // Don't fragment, as every common stack sets it on SYNs.
+            ip[6..8].copy_from_slice(&0x4000u16.to_be_bytes());
+            ip[8] = ttl;
+            ip[9] = protocol;
+            ip[10..12].copy_from_slice(&[0, 0]);
+            ip[12..16].copy_from_slice(&src_ip.octets());
+            ip[16..20].copy_from_slice(&dst_ip.octets());
+            let ip_checksum = checksum_fold(checksum_add(0, ip));
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+            let sum = checksum_add(0, &ip[12..20]);
+            sum + u32::from(protocol) + payload_len as u32
+        }
+        (src_ip, dst_ip) => {
+            ip[0..4].copy_from_slice(&0x6000_0000u32.to_be_bytes());
+            ip[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+            ip[6] = protocol;
+            ip[7] = ttl;
+            ip[8..24].copy_from_slice(&ipv6_octets(src_ip));
+            ip[24..40].copy_from_slice(&ipv6_octets(dst_ip));
+
+            let sum = checksum_add(0, &ip[8..40]);
+            sum + u32::from(protocol) + payload_len as u32
+        }
+    };
+    (ip_offset + ip_header_len, pseudo_header_sum)
+}
+
+impl Frame<'_> {
+    pub(crate) fn is_ipv6(&self) -> bool {
+        self.src_ip.is_ipv6() || self.dst_ip.is_ipv6()
+    }
+
+    fn ethernet_header_len(&self) -> usize {
+        ETHERNET_HEADER_LEN + 4 * self.vlan_tags.len()
+    }
+
+    /// Length of the frame in bytes.
+    pub fn size(&self) -> usize {
+        let ip_header_len = if self.is_ipv6() {
+            IPV6_HEADER_LEN
+        } else {
+            IPV4_HEADER_LEN
+        };
+        self.ethernet_header_len() + ip_header_len + TCP_HEADER_LEN + tcp_options_len(self.options)
+    }
+
+    /// Writes the frame to the start of `buf` and returns its length.
+    ///
+    /// Panics if `buf` is shorter than `size()`, the options are longer than 40 bytes or there
+    /// are more than two VLAN tags.
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        let tcp_len = TCP_HEADER_LEN + tcp_options_len(self.options);
+        assert!(tcp_len <= 60, "TCP options longer than 40 bytes");
+        let len = self.size();
+        let buf = &mut buf[..len];
+
+        let (tcp_offset, pseudo_header_sum) = write_headers(
+            buf,
+            self.src_mac,
+            self.dst_mac,
+            self.vlan_tags,
+            self.src_ip,
+            self.dst_ip,
+            self.ttl,
+            self.ip_id,
+            PROTOCOL_TCP,
+            tcp_len,
+        );
+
+        let tcp = &mut buf[tcp_offset..];
+        tcp[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        tcp[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        tcp[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        tcp[8..12].copy_from_slice(&self.ack.to_be_bytes());
+        tcp[12] = ((tcp_len / 4) as u8) << 4;
+        tcp[13] = self.flags;
+        tcp[14..16].copy_from_slice(&self.window.to_be_bytes());
+        tcp[16..20].copy_from_slice(&[0, 0, 0, 0]);
+
+        let options = &mut tcp[TCP_HEADER_LEN..];
+        options.iter_mut().for_each(|byte| *byte = 0);
+        let mut offset = 0;
+        for option in self.options {
+            option.write(&mut options[offset..], self.timestamp);
+            offset += option.len();
+        }
+
+        let tcp_checksum = checksum_fold(checksum_add(pseudo_header_sum, tcp));
+        tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+        len
+    }
+}
+
+/// A UDP datagram in an IPv4 or IPv6 packet in an Ethernet frame, addressed like `Frame`.
+#[derive(Clone, Copy, Debug)]
+pub struct UdpFrame<'a> {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    /// VLAN tags, outermost first. At most two.
+    pub vlan_tags: &'a [VlanTag],
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub ttl: u8,
+    /// Ignored for IPv6.
+    pub ip_id: u16,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl UdpFrame<'_> {
+    /// Length of the frame in bytes.
+    pub fn size(&self) -> usize {
+        let ip_header_len = if self.src_ip.is_ipv6() || self.dst_ip.is_ipv6() {
+            IPV6_HEADER_LEN
+        } else {
+            IPV4_HEADER_LEN
+        };
+        ETHERNET_HEADER_LEN
+            + 4 * self.vlan_tags.len()
+            + ip_header_len
+            + UDP_HEADER_LEN
+            + self.payload.len()
+    }
+
+    /// Writes the frame to the start of `buf` and returns its length.
+    ///
+    /// Panics if `buf` is shorter than `size()` or there are more than two VLAN tags.
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        let len = self.size();
+        let buf = &mut buf[..len];
+        let udp_len = UDP_HEADER_LEN + self.payload.len();
+        let (udp_offset, pseudo_header_sum) = write_headers(
+            buf,
+            self.src_mac,
+            self.dst_mac,
+            self.vlan_tags,
+            self.src_ip,
+            self.dst_ip,
+            self.ttl,
+            self.ip_id,
+            PROTOCOL_UDP,
+            udp_len,
+        );
+
+        let udp = &mut buf[udp_offset..];
+        udp[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        udp[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[6..8].copy_from_slice(&[0, 0]);
+        udp[UDP_HEADER_LEN..].copy_from_slice(self.payload);
+        // A checksum of 0 means there is none, so one that sums to 0 is sent as 0xffff.
+        let checksum = match checksum_fold(checksum_add(pseudo_header_sum, udp)) {
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+        len
+    }
+
+    /// Writes the frame like `write`, but with the first two bytes of the payload replaced by
+    /// whatever makes the UDP checksum come out as `checksum`, which must not be 0 or 0xffff.
+    ///
+    /// Load balancers that hash the checksum into their choice of path then send datagrams
+    /// with different payloads the same way, as paris-traceroute relies on.
+    pub fn write_with_checksum(&self, buf: &mut [u8], checksum: u16) -> usize {
+        assert!(
+            self.payload.len() >= 2,
+            "no room in the payload to fix the checksum"
+        );
+        assert!(
+            checksum != 0 && checksum != 0xffff,
+            "checksum {:#x} cannot be fixed",
+            checksum
+        );
+        let len = self.write(buf);
+        let udp_offset = len - UDP_HEADER_LEN - self.payload.len();
+        let udp = &mut buf[udp_offset..len];
+        let (written, replaced) = (be_u16(udp, 6), be_u16(udp, 8));
+        // The checksum is the complement of the sum, so the sum has to go from `!written` to
+        // `!checksum`: the replaced bytes make way for `replaced + written - checksum`, which
+        // is the complement of `!replaced + !written + checksum` in ones' complement.
+        let filler =
+            checksum_fold(u32::from(!replaced) + u32::from(!written) + u32::from(checksum));
+        udp[8..10].copy_from_slice(&filler.to_be_bytes());
+        udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+        len
+    }
+}
+
+const MAX_FRAME_LEN: usize = ETHERNET_HEADER_LEN + 4 * MAX_VLAN_TAGS + IPV6_HEADER_LEN + 60;
+
+/// A frame written once, into which the fields that differ between probes are patched.
+///
+/// The addresses, ports, sequence and acknowledgment numbers, IP ID and timestamps are left out
+/// of the checksums of the template, and only their own sums are added when they are patched
+/// in, so probes cost a copy and a few additions rather than a full header build.
+#[derive(Clone, Debug)]
+pub struct FrameTemplate {
+    frame: [u8; MAX_FRAME_LEN],
+    len: usize,
+    ip_offset: usize,
+    ipv6: bool,
+    /// Offsets of TSvals in the TCP header.
+    timestamp_offsets: Vec<usize>,
+    /// Sum of the IPv4 header without the patched fields.
+    ip_sum: u32,
+    /// Sum of the pseudo header and TCP segment without the patched fields.
+    tcp_sum: u32,
+}
+
+impl FrameTemplate {
+    /// A template for frames like `frame`, whose patched fields are ignored.
+    pub fn new(frame: &Frame) -> Self {
+        let ipv6 = frame.is_ipv6();
+        let unspecified = if ipv6 {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        };
+        let blank = Frame {
+            src_ip: unspecified,
+            dst_ip: unspecified,
+            ip_id: 0,
+            src_port: 0,
+            dst_port: 0,
+            seq: 0,
+            ack: 0,
+            timestamp: 0,
+            ..*frame
+        };
+        let mut template = [0; MAX_FRAME_LEN];
+        let len = blank.write(&mut template);
+
+        let ip_header_len = if ipv6 {
+            IPV6_HEADER_LEN
+        } else {
+            IPV4_HEADER_LEN
+        };
+        let ip_offset = frame.ethernet_header_len();
+        let (ip, tcp) = template[ip_offset..len].split_at_mut(ip_header_len);
+        let ip_sum = if ipv6 {
+            0
+        } else {
+            ip[10..12].copy_from_slice(&[0, 0]);
+            checksum_add(0, ip)
+        };
+        tcp[16..18].copy_from_slice(&[0, 0]);
+        let tcp_sum = checksum_add(6 + tcp.len() as u32, tcp);
+
+        let mut timestamp_offsets = vec![];
+        let mut offset = TCP_HEADER_LEN;
+        for option in frame.options {
+            if *option == TcpOption::Timestamp {
+                timestamp_offsets.push(offset + 2);
+            }
+            offset += option.len();
+        }
+
+        FrameTemplate {
+            frame: template,
+            len,
+            ip_offset,
+            ipv6,
+            timestamp_offsets,
+            ip_sum,
+            tcp_sum,
+        }
+    }
+
+    /// Writes `frame` to the start of `buf` from the template and returns its length. Only the
+    /// patched fields are taken from `frame`, the others must be those the template was made
+    /// with.
+    ///
+    /// Panics if `buf` is too short for the frame.
+    pub fn write(&self, buf: &mut [u8], frame: &Frame) -> usize {
+        debug_assert_eq!(frame.is_ipv6(), self.ipv6);
+        let buf = &mut buf[..self.len];
+        buf.copy_from_slice(&self.frame[..self.len]);
+
+        let (ip, tcp) = if self.ipv6 {
+            buf[self.ip_offset..].split_at_mut(IPV6_HEADER_LEN)
+        } else {
+            buf[self.ip_offset..].split_at_mut(IPV4_HEADER_LEN)
+        };
+        let address_sum = match (frame.src_ip, frame.dst_ip) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) if !self.ipv6 => {
+                ip[4..6].copy_from_slice(&frame.ip_id.to_be_bytes());
+                ip[12..16].copy_from_slice(&src_ip.octets());
+                ip[16..20].copy_from_slice(&dst_ip.octets());
+                let address_sum = checksum_add(0, &ip[12..20]);
+                let ip_sum = checksum_add(self.ip_sum + address_sum, &ip[4..6]);
+                ip[10..12].copy_from_slice(&checksum_fold(ip_sum).to_be_bytes());
+                address_sum
+            }
+            (src_ip, dst_ip) => {
+                ip[8..24].copy_from_slice(&ipv6_octets(src_ip));
+                ip[24..40].copy_from_slice(&ipv6_octets(dst_ip));
+                checksum_add(0, &ip[8..40])
+            }
+        };
+
+        tcp[0..2].copy_from_slice(&frame.src_port.to_be_bytes());
+        tcp[2..4].copy_from_slice(&frame.dst_port.to_be_bytes());
+        tcp[4..8].copy_from_slice(&frame.seq.to_be_bytes());
+        tcp[8..12].copy_from_slice(&frame.ack.to_be_bytes());
+        let mut tcp_sum = checksum_add(self.tcp_sum + address_sum, &tcp[0..12]);
+        let timestamp = frame.timestamp.to_be_bytes();
+        for &offset in &self.timestamp_offsets {
+            tcp[offset..offset + 4].copy_from_slice(&timestamp);
+            tcp_sum = checksum_add_at(tcp_sum, offset, &timestamp);
+        }
+        tcp[16..18].copy_from_slice(&checksum_fold(tcp_sum).to_be_bytes());
+
+        self.len
+    }
+}
+
+/// The header fields of a received TCP segment that probe modules read replies by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TcpSegment {
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    /// See `flags`.
+    pub flags: u8,
+    pub window: u16,
+}
+
+/// What the fast path made of a frame.
+enum Parsed<'a> {
+    /// The IP header, up to the end of its options, and the TCP segment.
+    Tcp { ip: &'a [u8], tcp: &'a [u8] },
+    /// Not a TCP segment, or malformed.
+    Other,
+    /// Headers the fast path does not walk, left to etherparse.
+    Unsupported,
+}
+
+/// 802.1Q, 802.1ad and the pre-standard QinQ tag protocol identifiers.
+const VLAN_TPIDS: [u16; 3] = [TPID_8021Q, TPID_8021AD, 0x9100];
+/// IPv6 extension headers that may come before a TCP header.
+const IPV6_EXTENSION_HEADERS: [u8; 8] = [0, 43, 44, 51, 60, 135, 139, 140];
+const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+fn be_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Whether `frame` is tagged with the VLAN identifiers of `tags` and no others, outermost
+/// first. The tag protocol identifiers and priorities are not compared, as switches rewrite
+/// them.
+pub fn has_vlan_tags(frame: &[u8], tags: &[VlanTag]) -> bool {
+    let mut offset = 12;
+    for tag in tags {
+        match frame.get(offset..offset + 4) {
+            Some(header) if VLAN_TPIDS.contains(&be_u16(header, 0)) => {
+                if be_u16(header, 2) & 0x0fff != tag.vid {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+        offset += 4;
+    }
+    match frame.get(offset..offset + 2) {
+        Some(ether_type) => !VLAN_TPIDS.contains(&be_u16(ether_type, 0)),
+        None => false,
+    }
+}
+
+/// Skips the Ethernet header and VLAN tags of a frame, and returns its EtherType and payload.
+pub(crate) fn ethernet_payload(frame: &[u8]) -> Option<(u16, &[u8])> {
+    if frame.len() < ETHERNET_HEADER_LEN {
+        return None;
+    }
+    let mut ether_type = be_u16(frame, 12);
+    let mut offset = ETHERNET_HEADER_LEN;
+    while VLAN_TPIDS.contains(&ether_type) {
+        if frame.len() < offset + 4 {
+            return None;
+        }
+        ether_type = be_u16(frame, offset + 2);
+        offset += 4;
+    }
+    Some((ether_type, &frame[offset..]))
+}
+
+/// Finds the IP header and TCP segment in an Ethernet frame in place, checking every length
+/// against the frame before reading past it.
+fn parse_fast(frame: &[u8]) -> Parsed<'_> {
+    let (ether_type, ip) = match ethernet_payload(frame) {
+        Some(payload) => payload,
+        None => return Parsed::Other,
+    };
+
+    let (ip, tcp) = match ether_type {
+        ETHER_TYPE_IPV4 => {
+            let (header_len, total_len) = match ipv4_lens(ip) {
+                Some(lens) => lens,
+                None => return Parsed::Other,
+            };
+            // Only the first fragment carries the TCP header.
+            if ip[9] != PROTOCOL_TCP || be_u16(ip, 6) & 0x1fff != 0 {
+                return Parsed::Other;
+            }
+            (&ip[..header_len], &ip[header_len..total_len])
+        }
+        ETHER_TYPE_IPV6 => {
+            let total_len = match ipv6_lens(ip) {
+                Some((_, total_len)) => total_len,
+                None => return Parsed::Other,
+            };
+            match ip[6] {
+                PROTOCOL_TCP => {}
+                next_header if IPV6_EXTENSION_HEADERS.contains(&next_header) => {
+                    return Parsed::Unsupported
+                }
+                _ => return Parsed::Other,
+            }
+            (&ip[..IPV6_HEADER_LEN], &ip[IPV6_HEADER_LEN..total_len])
+        }
+        _ => return Parsed::Other,
+    };
+
+    let header_len = usize::from(tcp.get(12).copied().unwrap_or(0) >> 4) * 4;
+    if tcp.len() < TCP_HEADER_LEN || header_len < TCP_HEADER_LEN || tcp.len() < header_len {
+        return Parsed::Other;
+    }
+    Parsed::Tcp { ip, tcp }
+}
+
+/// The source and destination address of an IP header at least 20 bytes long, or 40 for IPv6.
+fn ip_addrs(ip: &[u8]) -> (IpAddr, IpAddr) {
+    if ip[0] >> 4 == 4 {
+        (
+            IpAddr::V4(Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15])),
+            IpAddr::V4(Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19])),
+        )
+    } else {
+        let mut src_ip = [0; 16];
+        src_ip.copy_from_slice(&ip[8..24]);
+        let mut dst_ip = [0; 16];
+        dst_ip.copy_from_slice(&ip[24..40]);
+        (IpAddr::from(src_ip), IpAddr::from(dst_ip))
+    }
+}
+
+impl TcpSegment {
+    /// Parses the TCP segment in an Ethernet frame, or returns `None` if the frame does not
+    /// carry one.
+    ///
+    /// The fields are read in place, and only frames with IPv6 extension headers are decoded by
+    /// etherparse.
+    pub fn parse(frame: &[u8]) -> Option<TcpSegment> {
+        match parse_fast(frame) {
+            Parsed::Tcp { ip, tcp } => Some(TcpSegment::read(ip, tcp)),
+            Parsed::Other => None,
+            Parsed::Unsupported => TcpSegment::parse_slow(frame),
+        }
+    }
+
+    /// Parses the TCP segment in an Ethernet frame along with its `ReplyHeaders`, walking the
+    /// frame once, or returns `None` if the frame does not carry one.
+    pub fn parse_with_headers(frame: &[u8]) -> Option<(TcpSegment, ReplyHeaders)> {
+        match parse_fast(frame) {
+            Parsed::Tcp { ip, tcp } => {
+                Some((TcpSegment::read(ip, tcp), ReplyHeaders::read(ip, tcp)))
+            }
+            Parsed::Other => None,
+            // Rare enough for etherparse to decode them twice.
+            Parsed::Unsupported => Some((
+                TcpSegment::parse_slow(frame)?,
+                ReplyHeaders::parse_slow(frame)?,
+            )),
+        }
+    }
+
+    /// Reads the segment from the IP header, up to the end of its options, and TCP header the
+    /// fast path found.
+    fn read(ip: &[u8], tcp: &[u8]) -> TcpSegment {
+        let (src_ip, dst_ip) = ip_addrs(ip);
+        TcpSegment {
+            src_ip,
+            dst_ip,
+            src_port: be_u16(tcp, 0),
+            dst_port: be_u16(tcp, 2),
+            seq: be_u32(tcp, 4),
+            ack: be_u32(tcp, 8),
+            flags: tcp[13] & 0x3f,
+            window: be_u16(tcp, 14),
+        }
+    }
+
+    fn parse_slow(frame: &[u8]) -> Option<TcpSegment> {
+        let headers = match etherparse::PacketHeaders::from_ethernet_slice(frame) {
+            Ok(headers) => headers,
+            Err(e) => {
+                debug!("failed to parse frame: {:?}", e);
+                return None;
+            }
+        };
+        let (src_ip, dst_ip) = match headers.ip? {
+            etherparse::IpHeader::Version4(ipv4) => {
+                (IpAddr::from(ipv4.source), IpAddr::from(ipv4.destination))
+            }
+            etherparse::IpHeader::Version6(ipv6) => {
+                (IpAddr::from(ipv6.source), IpAddr::from(ipv6.destination))
+            }
+        };
+        let tcp = headers.transport?.tcp()?;
+        let flags = [
+            (tcp.fin, flags::FIN),
+            (tcp.syn, flags::SYN),
+            (tcp.rst, flags::RST),
+            (tcp.psh, flags::PSH),
+            (tcp.ack, flags::ACK),
+            (tcp.urg, flags::URG),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag);
+
+        Some(TcpSegment {
+            src_ip,
+            dst_ip,
+            src_port: tcp.source_port,
+            dst_port: tcp.destination_port,
+            seq: tcp.sequence_number,
+            ack: tcp.acknowledgment_number,
+            flags,
+            window: tcp.window_size,
+        })
+    }
+
+    /// Whether all of `flags` are set.
+    pub fn has(&self, flags: u8) -> bool {
+        self.flags & flags == flags
+    }
+}
+
+/// What an ICMP error says went wrong with a packet.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IcmpErrorKind {
+    /// The packet's TTL, or hop limit, ran out in transit.
+    TimeExceeded,
+    /// The destination could not be reached. Holds the ICMP code, whose meanings differ
+    /// between ICMP and ICMPv6.
+    Unreachable(u8),
+}
+
+/// The start of the packet an ICMP error was sent about, as quoted in the error.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct QuotedPacket {
+    /// `PROTOCOL_TCP` or `PROTOCOL_UDP`.
+    pub protocol: u8,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// The 4 bytes after the ports: the sequence number of a TCP segment, or the length and
+    /// checksum of a UDP datagram.
+    pub word: u32,
+}
+
+/// An ICMP or ICMPv6 time exceeded or destination unreachable message about a TCP segment or
+/// UDP datagram.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IcmpError {
+    /// The router or host that sent the error.
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub kind: IcmpErrorKind,
+    pub quoted: QuotedPacket,
+}
+
+impl IcmpError {
+    /// Parses the ICMP error in an Ethernet frame, or returns `None` if the frame does not carry
+    /// one about a TCP segment or UDP datagram. IPv6 packets with extension headers, whether
+    /// the error or the quoted packet, are not read.
+    pub fn parse(frame: &[u8]) -> Option<IcmpError> {
+        let (ether_type, ip) = ethernet_payload(frame)?;
+        let (ip_header_len, total_len) = match ether_type {
+            ETHER_TYPE_IPV4 => ipv4_lens(ip)?,
+            ETHER_TYPE_IPV6 => ipv6_lens(ip)?,
+            _ => return None,
+        };
+        // Only the first fragment carries the ICMP header.
+        if ether_type == ETHER_TYPE_IPV4 && be_u16(ip, 6) & 0x1fff != 0 {
+            return None;
+        }
+        let (src_ip, dst_ip) = ip_addrs(ip);
+        let icmp = &ip[ip_header_len..total_len];
+        if icmp.len() < 8 {
+            return None;
+        }
+        let kind = match (ip[0] >> 4, ip_protocol(ip), icmp[0], icmp[1]) {
+            (4, PROTOCOL_ICMP, 11, 0) | (6, PROTOCOL_ICMPV6, 3, 0) => IcmpErrorKind::TimeExceeded,
+            (4, PROTOCOL_ICMP, 3, code) | (6, PROTOCOL_ICMPV6, 1, code) => {
+                IcmpErrorKind::Unreachable(code)
+            }
+            _ => return None,
+        };
+
+        // Errors quote as much of the packet as fits, and at least the first 8 bytes after the
+        // IP header.
+        let quoted_ip = &icmp[8..];
+        let quoted_header_len = match quoted_ip.first()? >> 4 {
+            4 if quoted_ip.len() >= IPV4_HEADER_LEN => usize::from(quoted_ip[0] & 0x0f) * 4,
+            6 if quoted_ip.len() >= IPV6_HEADER_LEN => IPV6_HEADER_LEN,
+            _ => return None,
+        };
+        let protocol = ip_protocol(quoted_ip);
+        let transport = quoted_ip.get(quoted_header_len..quoted_header_len + 8)?;
+        if protocol != PROTOCOL_TCP && protocol != PROTOCOL_UDP {
+            return None;
+        }
+        let (quoted_src_ip, quoted_dst_ip) = ip_addrs(quoted_ip);
+        Some(IcmpError {
+            src_ip,
+            dst_ip,
+            kind,
+            quoted: QuotedPacket {
+                protocol,
+                src_ip: quoted_src_ip,
+                dst_ip: quoted_dst_ip,
+                src_port: be_u16(transport, 0),
+                dst_port: be_u16(transport, 2),
+                word: be_u32(transport, 4),
+            },
+        })
+    }
+}
+
+/// Header and total length of an IPv4 packet, checked against `ip`.
+fn ipv4_lens(ip: &[u8]) -> Option<(usize, usize)> {
+    if ip.len() < IPV4_HEADER_LEN || ip[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = usize::from(ip[0] & 0x0f) * 4;
+    // Frames may be padded past the end of the packet.
+    let total_len = usize::from(be_u16(ip, 2));
+    if header_len < IPV4_HEADER_LEN || total_len < header_len || ip.len() < total_len {
+        return None;
+    }
+    Some((header_len, total_len))
+}
+
+/// Header and total length of an IPv6 packet, checked against `ip`.
+fn ipv6_lens(ip: &[u8]) -> Option<(usize, usize)> {
+    if ip.len() < IPV6_HEADER_LEN || ip[0] >> 4 != 6 {
+        return None;
+    }
+    let total_len = IPV6_HEADER_LEN + usize::from(be_u16(ip, 4));
+    if ip.len() < total_len {
+        return None;
+    }
+    Some((IPV6_HEADER_LEN, total_len))
+}
+
+/// The protocol, or IPv6 next header, of an IP header at least 20 bytes long.
+fn ip_protocol(ip: &[u8]) -> u8 {
+    if ip[0] >> 4 == 4 {
+        ip[9]
+    } else {
+        ip[6]
+    }
+}
+
+/// The fields of a reply's IP and TCP headers that tell about the host that sent it, rather than
+/// about the probe it answers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ReplyHeaders {
+    /// 4 or 6.
+    pub ip_version: u8,
+    /// TTL, or hop limit for IPv6.
+    pub ttl: u8,
+    /// Identification, which IPv6 headers have none of.
+    pub ip_id: Option<u16>,
+    /// The don't fragment bit, which IPv6 headers have none of either.
+    pub dont_fragment: bool,
+    pub window: u16,
+    /// All eight flags, see `flags`, ECN ones included.
+    pub flags: u8,
+    /// The TCP options, in the order they came. Options of unknown kinds are left out.
+    pub tcp_options: Vec<TcpOption>,
+    /// TSval and TSecr of the timestamp option.
+    pub timestamps: Option<(u32, u32)>,
+}
+
+/// Reads the options of a TCP header, and the values of its timestamp option.
+fn parse_tcp_options(options: &[u8]) -> (Vec<TcpOption>, Option<(u32, u32)>) {
+    let mut parsed = vec![];
+    let mut timestamps = None;
+    let mut offset = 0;
+    while let Some(&kind) = options.get(offset) {
+        match kind {
+            0 => {
+                // Only padding follows.
+                parsed.push(TcpOption::End);
+                break;
+            }
+            1 => {
+                parsed.push(TcpOption::Nop);
+                offset += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let len = match options.get(offset + 1) {
+            Some(&len) if len >= 2 && offset + usize::from(len) <= options.len() => len,
+            _ => break,
+        };
+        let data = &options[offset + 2..offset + usize::from(len)];
+        match (kind, data.len()) {
+            (2, 2) => parsed.push(TcpOption::MaxSegmentSize(be_u16(data, 0))),
+            // RFC 7323 has larger shifts read as 14.
+            (3, 1) => parsed.push(TcpOption::WindowScale(data[0].min(14))),
+            (4, 0) => parsed.push(TcpOption::SackPermitted),
+            (8, 8) => {
+                parsed.push(TcpOption::Timestamp);
+                timestamps = Some((be_u32(data, 0), be_u32(data, 4)));
+            }
+            _ => {}
+        }
+        offset += usize::from(len);
+    }
+    (parsed, timestamps)
+}
+
+impl ReplyHeaders {
+    /// Parses the headers of the TCP segment in an Ethernet frame, or returns `None` if the
+    /// frame does not carry one.
+    pub fn parse(frame: &[u8]) -> Option<ReplyHeaders> {
+        match parse_fast(frame) {
+            Parsed::Tcp { ip, tcp } => Some(ReplyHeaders::read(ip, tcp)),
+            Parsed::Other => None,
+            Parsed::Unsupported => ReplyHeaders::parse_slow(frame),
+        }
+    }
+
+    /// See `TcpSegment::read`.
+    fn read(ip: &[u8], tcp: &[u8]) -> ReplyHeaders {
+        let ipv4 = ip[0] >> 4 == 4;
+        let header_len = usize::from(tcp[12] >> 4) * 4;
+        let (tcp_options, timestamps) = parse_tcp_options(&tcp[TCP_HEADER_LEN..header_len]);
+        ReplyHeaders {
+            ip_version: if ipv4 { 4 } else { 6 },
+            ttl: if ipv4 { ip[8] } else { ip[7] },
+            ip_id: if ipv4 { Some(be_u16(ip, 4)) } else { None },
+            dont_fragment: ipv4 && ip[6] & 0x40 != 0,
+            window: be_u16(tcp, 14),
+            flags: tcp[13],
+            tcp_options,
+            timestamps,
+        }
+    }
+
+    fn parse_slow(frame: &[u8]) -> Option<ReplyHeaders> {
+        let headers = etherparse::PacketHeaders::from_ethernet_slice(frame).ok()?;
+        let (ip_version, ttl, ip_id, dont_fragment) = match headers.ip? {
+            etherparse::IpHeader::Version4(ipv4) => (
+                4,
+                ipv4.time_to_live,
+                Some(ipv4.identification),
+                ipv4.dont_fragment,
+            ),
+            etherparse::IpHeader::Version6(ipv6) => (6, ipv6.hop_limit, None, false),
+        };
+        let tcp = headers.transport?.tcp()?;
+        let flags = [
+            (tcp.fin, flags::FIN),
+            (tcp.syn, flags::SYN),
+            (tcp.rst, flags::RST),
+            (tcp.psh, flags::PSH),
+            (tcp.ack, flags::ACK),
+            (tcp.urg, flags::URG),
+            (tcp.ece, flags::ECE),
+            (tcp.cwr, flags::CWR),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag);
+        let (tcp_options, timestamps) = parse_tcp_options(tcp.options());
+
+        Some(ReplyHeaders {
+            ip_version,
+            ttl,
+            ip_id,
+            dont_fragment,
+            window: tcp.window_size,
+            flags,
+            tcp_options,
+            timestamps,
+        })
+    }
+
+    /// The maximum segment size the reply announced.
+    pub fn mss(&self) -> Option<u16> {
+        self.tcp_options.iter().find_map(|option| match option {
+            TcpOption::MaxSegmentSize(mss) => Some(*mss),
+            _ => None,
+        })
+    }
+
+    /// The window scale the reply announced.
+    pub fn window_scale(&self) -> Option<u8> {
+        self.tcp_options.iter().find_map(|option| match option {
+            TcpOption::WindowScale(shift) => Some(*shift),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use crate::rng::Rng;
+
+/// Iterates over `0..len` in a pseudo random order fixed by a seed.
+///
+/// Probing targets in this order spreads consecutive probes over unrelated hosts, and since
+/// the order can be regenerated from the seed, a scan can be resumed from any position in it
+/// without storing the order itself.
+///
+/// Walks the full period linear congruential generator `x -> a * x + c (mod m)`, where `m`
+/// is the smallest power of two of at least `len`, and skips the values outside `0..len`.
+#[derive(Clone, Debug)]
+pub(crate) struct Permutation {
+    len: u64,
+    mask: u64,
+    a: u64,
+    c: u64,
+    x: u64,
+    remaining: u64,
+}
+
+impl Permutation {
+    pub(crate) fn new(len: u64, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let mask = len.next_power_of_two() - 1;
+        // With a power of two modulus the generator visits every value exactly once per
+        // period when `c` is odd and `a - 1` is a multiple of 4 (Hull-Dobell).
+        let a = ((rng.next_u64() << 2) | 1) & mask;
+        let c = (rng.next_u64() | 1) & mask;
+        let x = rng.next_u64() & mask;
+        Permutation {
+            len,
+            mask,
+            a,
+            c,
+            x,
+            remaining: len,
+        }
+    }
+}
+
+impl Iterator for Permutation {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            let value = self.x;
+            self.x = self.a.wrapping_mul(self.x).wrapping_add(self.c) & self.mask;
+            if value < self.len {
+                self.remaining -= 1;
+                return Some(value);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
@@ -0,0 +1,385 @@
+//! Scan types: which TCP segment is sent as a probe, and what the replies to it say about
+//! the port.
+//!
+//! - SYN scans send a SYN, and find open ports by their SYN-ACK.
+//! - ACK scans send a bare ACK, which any port answers with a RST unless a firewall drops it,
+//!   so they map firewall rules rather than open ports.
+//! - FIN, NULL and Xmas scans send segments no connection was opened for. RFC 793 stacks
+//!   answer them with a RST on closed ports and drop them on open ones, so ports that stay
+//!   silent are open or filtered. Windows and many network devices send a RST either way.
+//! - Window scans send an ACK like ACK scans, but tell open from closed ports by the window of
+//!   the RST, which some stacks set on open ports only.
+//!
+//! Each scan type is a `ProbeModule`, which builds the probe frames and reads the replies to
+//! them. Scan types defined outside this crate plug in through `ScanConfig::probe_module`.
+use crate::packet::{
+    flags, ipv6_octets, Frame, FrameTemplate, ProbeOptions, TcpSegment, UdpFrame, VlanTag,
+};
+use crate::rng::{fnv1a, Rng, FNV1A_OFFSET};
+use crate::traceroute::Hop;
+use crate::{PortState, Target};
+use std::cell::RefCell;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ScanType {
+    #[default]
+    Syn,
+    Ack,
+    Fin,
+    Null,
+    Xmas,
+    Window,
+}
+
+impl ScanType {
+    /// State of the ports that never answer a probe.
+    pub fn unanswered_state(self) -> PortState {
+        match self {
+            ScanType::Fin | ScanType::Null | ScanType::Xmas => PortState::OpenFiltered,
+            ScanType::Syn | ScanType::Ack | ScanType::Window => PortState::Filtered,
+        }
+    }
+
+    /// The module that sends this scan type's probes.
+    pub fn module(self) -> Arc<dyn ProbeModule> {
+        match self {
+            ScanType::Syn => Arc::new(SynScan),
+            ScanType::Ack => Arc::new(AckScan),
+            ScanType::Fin => Arc::new(StealthScan { flags: flags::FIN }),
+            ScanType::Null => Arc::new(StealthScan { flags: 0 }),
+            ScanType::Xmas => Arc::new(StealthScan {
+                flags: flags::FIN | flags::PSH | flags::URG,
+            }),
+            ScanType::Window => Arc::new(WindowScan),
+        }
+    }
+}
+
+impl fmt::Display for ScanType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ScanType::Syn => "syn",
+            ScanType::Ack => "ack",
+            ScanType::Fin => "fin",
+            ScanType::Null => "null",
+            ScanType::Xmas => "xmas",
+            ScanType::Window => "window",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ScanType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syn" => Ok(ScanType::Syn),
+            "ack" => Ok(ScanType::Ack),
+            "fin" => Ok(ScanType::Fin),
+            "null" => Ok(ScanType::Null),
+            "xmas" => Ok(ScanType::Xmas),
+            "window" => Ok(ScanType::Window),
+            _ => Err(format!("invalid scan type {}", s)),
+        }
+    }
+}
+
+/// Values derived from the source and target of each probe with a key only the scanner knows,
+/// which the replies must echo to be accepted, as SYN cookies do. Replies that don't answer no
+/// probe of this scan: they are spoofed, or answer an earlier one.
+#[derive(Clone, Copy, Debug)]
+pub struct Cookies {
+    key: u64,
+}
+
+impl Cookies {
+    pub fn new(key: u64) -> Self {
+        Cookies { key }
+    }
+
+    /// The cookie of probes from `src_ip` and `src_port` to `target`.
+    pub fn cookie(&self, src_ip: IpAddr, src_port: u16, target: &Target) -> u32 {
+        // IPv4 sources probe IPv6 targets from their IPv4-mapped address.
+        let hash = fnv1a(FNV1A_OFFSET, &self.key.to_be_bytes());
+        let hash = fnv1a(hash, &ipv6_octets(src_ip));
+        let hash = fnv1a(hash, &src_port.to_be_bytes());
+        let hash = fnv1a(hash, &ipv6_octets(target.ip));
+        let hash = fnv1a(hash, &target.port.to_be_bytes());
+        Rng::new(hash).next_u64() as u32
+    }
+
+    /// The cookie of the probe `reply` would answer.
+    pub fn reply_cookie(&self, reply: &TcpSegment) -> u32 {
+        let responder = Target {
+            ip: reply.src_ip,
+            port: reply.src_port,
+        };
+        self.cookie(reply.dst_ip, reply.dst_port, &responder)
+    }
+}
+
+/// A probe for a `ProbeModule` to build: where it goes, where it is sent from, and the header
+/// fields the scan picked for it.
+#[derive(Clone, Copy, Debug)]
+pub struct Probe<'a> {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    pub vlan_tags: &'a [VlanTag],
+    pub src_ip: IpAddr,
+    pub src_port: u16,
+    pub target: &'a Target,
+    /// The value replies must echo, see `Cookies`.
+    pub cookie: u32,
+    pub ip_id: u16,
+    /// The TTL of `options`, unless the probe is sent with its own, as traceroutes send them.
+    pub ttl: u8,
+    /// TSval of a `TcpOption::Timestamp`.
+    pub timestamp: u32,
+    pub options: &'a ProbeOptions,
+    pub templates: &'a Templates,
+}
+
+impl<'a> Probe<'a> {
+    /// A TCP segment to the target with the window and TCP options in `options`.
+    pub fn tcp_frame(&self, flags: u8, seq: u32, ack: u32) -> Frame<'a> {
+        Frame {
+            src_mac: self.src_mac,
+            dst_mac: self.dst_mac,
+            vlan_tags: self.vlan_tags,
+            src_ip: self.src_ip,
+            dst_ip: self.target.ip,
+            ttl: self.ttl,
+            ip_id: self.ip_id,
+            src_port: self.src_port,
+            dst_port: self.target.port,
+            seq,
+            ack,
+            flags,
+            window: self.options.window,
+            options: &self.options.tcp_options,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Writes the frame `tcp_frame` returns to the start of `buf` and returns its length,
+    /// patching it into a template rather than building it from scratch.
+    pub fn write_tcp(&self, buf: &mut [u8], flags: u8, seq: u32, ack: u32) -> usize {
+        self.templates.write(buf, &self.tcp_frame(flags, seq, ack))
+    }
+
+    /// A UDP datagram to the target carrying `payload`.
+    pub fn udp_frame(&self, payload: &'a [u8]) -> UdpFrame<'a> {
+        UdpFrame {
+            src_mac: self.src_mac,
+            dst_mac: self.dst_mac,
+            vlan_tags: self.vlan_tags,
+            src_ip: self.src_ip,
+            dst_ip: self.target.ip,
+            ttl: self.ttl,
+            ip_id: self.ip_id,
+            src_port: self.src_port,
+            dst_port: self.target.port,
+            payload,
+        }
+    }
+}
+
+/// The frame templates of the probes of one scan, made as each kind of probe is first sent.
+///
+/// Templates are told apart by TCP flags, IP version and TTL only, as the other fields they are
+/// made from are the same for every probe of a scan.
+#[derive(Debug, Default)]
+pub struct Templates {
+    templates: RefCell<Vec<(TemplateKey, FrameTemplate)>>,
+}
+
+/// TCP flags, whether the frame is IPv6, and TTL.
+type TemplateKey = (u8, bool, u8);
+
+impl Templates {
+    pub fn new() -> Self {
+        Templates::default()
+    }
+
+    fn write(&self, buf: &mut [u8], frame: &Frame) -> usize {
+        let key = (frame.flags, frame.is_ipv6(), frame.ttl);
+        let mut templates = self.templates.borrow_mut();
+        let index = match templates.iter().position(|(k, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                templates.push((key, FrameTemplate::new(frame)));
+                templates.len() - 1
+            }
+        };
+        templates[index].1.write(buf, frame)
+    }
+}
+
+/// A reply a `ProbeModule` accepted as the answer to one of its probes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProbeReply {
+    pub responder: Target,
+    /// Where the reply was sent, which must be one of the scan's sources for it to count.
+    pub dst_ip: IpAddr,
+    pub dst_port: u16,
+    pub state: PortState,
+    /// For replies that half open a connection, like SYN-ACKs, the sequence number of the RST
+    /// that tears it down.
+    pub reset_seq: Option<u32>,
+    /// For replies to traceroute probes, the TTL of the probe and who answered it.
+    pub hop: Option<Hop>,
+}
+
+impl ProbeReply {
+    /// A reply carried by `segment`.
+    pub fn new(segment: &TcpSegment, state: PortState, reset_seq: Option<u32>) -> Self {
+        ProbeReply {
+            responder: Target {
+                ip: segment.src_ip,
+                port: segment.src_port,
+            },
+            dst_ip: segment.dst_ip,
+            dst_port: segment.dst_port,
+            state,
+            reset_seq,
+            hop: None,
+        }
+    }
+}
+
+/// Builds the probes of a scan type and reads the replies to them, so the send and receive
+/// loops need not know which scan they run.
+pub trait ProbeModule: fmt::Debug + Send + Sync {
+    /// Writes the frame of `probe` to the start of `buf` and returns its length.
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize;
+
+    /// Returns what `frame` says about the port it came from, or `None` if it answers none of
+    /// this module's probes, according to `cookies`, or says nothing about the port.
+    ///
+    /// `segment` is the TCP segment the frame carries, if any, which the receive loop parses
+    /// once for all modules.
+    fn classify(
+        &self,
+        frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply>;
+}
+
+#[derive(Debug)]
+struct SynScan;
+
+impl ProbeModule for SynScan {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        probe.write_tcp(buf, flags::SYN, probe.cookie, 0)
+    }
+
+    fn classify(
+        &self,
+        _frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        let segment = segment?;
+        // SYN-ACKs and RSTs alike acknowledge the SYN.
+        let ack = cookies.reply_cookie(segment).wrapping_add(1);
+        if !segment.has(flags::ACK) || segment.ack != ack {
+            return None;
+        }
+        if segment.has(flags::SYN) {
+            Some(ProbeReply::new(segment, PortState::Open, Some(segment.ack)))
+        } else if segment.has(flags::RST) {
+            Some(ProbeReply::new(segment, PortState::Closed, None))
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `segment` is the RST a stack answers an ACK probe with, which takes its sequence
+/// number from the probe's acknowledgment number.
+fn resets_ack_probe(segment: &TcpSegment, cookies: &Cookies) -> bool {
+    segment.has(flags::RST) && segment.seq == cookies.reply_cookie(segment)
+}
+
+#[derive(Debug)]
+struct AckScan;
+
+impl ProbeModule for AckScan {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        probe.write_tcp(buf, flags::ACK, 0, probe.cookie)
+    }
+
+    fn classify(
+        &self,
+        _frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        let segment = segment?;
+        if resets_ack_probe(segment, cookies) {
+            Some(ProbeReply::new(segment, PortState::Unfiltered, None))
+        } else {
+            None
+        }
+    }
+}
+
+/// FIN, NULL and Xmas scans, which only differ in the flags they send.
+#[derive(Debug)]
+struct StealthScan {
+    flags: u8,
+}
+
+impl ProbeModule for StealthScan {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        probe.write_tcp(buf, self.flags, probe.cookie, 0)
+    }
+
+    fn classify(
+        &self,
+        _frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        let segment = segment?;
+        // The RST acknowledges the probe, whose FIN takes up a sequence number.
+        let len = u32::from(self.flags & flags::FIN != 0);
+        let ack = cookies.reply_cookie(segment).wrapping_add(len);
+        if segment.has(flags::RST | flags::ACK) && segment.ack == ack {
+            Some(ProbeReply::new(segment, PortState::Closed, None))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WindowScan;
+
+impl ProbeModule for WindowScan {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        probe.write_tcp(buf, flags::ACK, 0, probe.cookie)
+    }
+
+    fn classify(
+        &self,
+        _frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        let segment = segment?;
+        if !resets_ack_probe(segment, cookies) {
+            None
+        } else if segment.window > 0 {
+            Some(ProbeReply::new(segment, PortState::Open, None))
+        } else {
+            Some(ProbeReply::new(segment, PortState::Closed, None))
+        }
+    }
+}
@@ -1,112 +1,172 @@
-use crate::Target;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use crate::backend::{Interface, PacketRx};
+use crate::fingerprint::SignatureDb;
+use crate::metrics::Metrics;
+use crate::packet::TcpSegment;
+use crate::probe::{Cookies, ProbeModule};
+use crate::reply::ReplyInfo;
+use crate::send::{ProbeClock, Reset, SendTimes};
+use crate::source::Sources;
+use crate::{ScanResult, Target};
+use log::{debug, log_enabled, Level};
+use std::collections::HashSet;
 use std::sync::atomic::Ordering;
-use std::sync::{atomic::AtomicBool, Arc};
-use xsk_rs::{FillQueue, FrameDesc, RxQueue, Umem};
+use std::sync::mpsc::Sender;
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-fn parse_response(value: &etherparse::PacketHeaders) -> Option<Target> {
-    let ip_hdr = value.ip.as_ref()?;
-    eprintln!("parse: ip_hdr = {:?}", ip_hdr);
-    let src_ip = match ip_hdr {
-        etherparse::IpHeader::Version4(ipv4_hdr) => IpAddr::V4(ipv4_hdr.source.into()),
-        etherparse::IpHeader::Version6(ipv6_hdr) => IpAddr::V6(ipv6_hdr.destination.into()),
-    };
+/// Resets the half-open connections of SYN-ACKs from probed targets.
+pub struct Resetter {
+    /// Targets that may be reset, or `None` for any responder whose reply passed the cookie
+    /// check, for scans too large to list their targets.
+    pub targets: Option<HashSet<Target>>,
+    pub resets: Sender<Reset>,
+}
 
-    let transport_hdr = value.transport.as_ref()?;
-    eprintln!("parse: transport_hdr = {:?}", transport_hdr);
-    let src_port = match transport_hdr {
-        etherparse::TransportHeader::Udp(udp_hdr) => return None,
-        etherparse::TransportHeader::Tcp(tcp_hdr) => tcp_hdr.source_port,
+/// When the probe a reply answers was sent: when the sender `recorded` sending it, checked
+/// against the time of the TSval the reply `echoed`, if any, or that time if the sender no
+/// longer remembers.
+fn probe_sent_at(recorded: Option<SystemTime>, echoed: Option<SystemTime>) -> Option<SystemTime> {
+    let (recorded, echoed) = match (recorded, echoed) {
+        (Some(recorded), Some(echoed)) => (recorded, echoed),
+        (recorded, echoed) => return recorded.or(echoed),
     };
-
-    Some(Target {
-        ip: src_ip,
-        port: src_port.into(),
-    })
+    // TSvals count whole milliseconds, and are taken just before the send time is.
+    let skew = recorded.duration_since(echoed);
+    if skew.map_or(true, |skew| skew > Duration::from_millis(2)) {
+        debug!(
+            "reply echoes a TSval of {:?} for a probe sent at {:?}",
+            echoed, recorded
+        );
+    }
+    Some(recorded)
 }
 
-pub fn recv(
-    mut rx_q: RxQueue,
-    mut fill_q: FillQueue,
-    mut frames: Vec<FrameDesc>,
-    umem: Umem,
-    done: Arc<AtomicBool>,
-) -> Vec<Target> {
-    eprintln!("--------------------------");
-    eprintln!(
-        "rx frames[0] = {}, rx frames[-1] = {}",
-        frames[0].addr(),
-        frames[frames.len() - 1].addr()
-    );
-    eprintln!("--------------------------");
-    assert_eq!(unsafe { fill_q.produce(&frames[..]) }, frames.len());
+/// What the receive loop makes of replies, and where it records them, see `recv`.
+pub struct RecvContext<'a> {
+    /// Classifies replies, according to `cookies`.
+    pub module: &'a dyn ProbeModule,
+    pub cookies: Cookies,
+    /// Replies must be sent to one of them, on their VLANs.
+    pub sources: Sources,
+    pub responders: Arc<Mutex<Vec<ScanResult>>>,
+    /// Asked to reset the connections replies from probed targets half opened.
+    pub resetter: Option<Resetter>,
+    /// Times the replies, and reads the TSvals they echo.
+    pub clock: ProbeClock,
+    pub send_times: SendTimes,
+    /// The interface the replies are received on.
+    pub interface: Option<Interface>,
+    /// Guesses the operating system replies come from.
+    pub fingerprints: Option<Arc<SignatureDb>>,
+    pub metrics: Option<Arc<Metrics>>,
+}
 
+/// Receives replies until `done` is set, then hands `rx` back.
+///
+/// Every reply the module of `context` accepts is added to its responders as soon as it is
+/// seen. It comes with the state the module classifies it as, its headers and when it was
+/// received. When its probe was sent is taken from the send times, or from the TSval it
+/// echoes. Frames, replies and the socket's drop counters are counted in the metrics.
+pub fn recv<R: PacketRx>(mut rx: R, done: Arc<AtomicBool>, context: RecvContext) -> R {
+    let RecvContext {
+        module,
+        cookies,
+        sources,
+        responders,
+        resetter,
+        clock,
+        send_times,
+        interface,
+        fingerprints,
+        metrics,
+    } = context;
     let poll_ms_timeout: i32 = 100;
+    let stats_interval = Duration::from_secs(1);
+    let mut stats_read_at: Option<Instant> = None;
     let mut total_frames_rcvd = 0;
 
-    let mut responders = vec![];
-
     while !(done.load(Ordering::Relaxed)) {
-        eprintln!("starting rx loop");
-        match rx_q
-            .poll_and_consume(&mut frames[..], poll_ms_timeout)
-            .unwrap()
-        {
-            0 => {
-                eprintln!("rx_q.poll_and_consume() consumed 0 frames");
-                // No packets consumed, wake up fill queue if required
-                if fill_q.needs_wakeup() {
-                    eprintln!("(waking up fill_q");
-                    fill_q.wakeup(rx_q.fd(), poll_ms_timeout).unwrap();
-                }
-            }
-            frames_rcvd => {
-                eprintln!("rx_q.poll_and_consume() consumed {} frames", frames_rcvd);
-
-                for recv_frame in frames.iter().take(frames_rcvd) {
-                    eprintln!(
-                        "recv frame addr = {}, len = {}",
-                        recv_frame.addr(),
-                        recv_frame.len()
+        let frames_rcvd = rx
+            .recv_batch(poll_ms_timeout, &mut |frame| {
+                let received_at = clock.now();
+                // Fully decoding every frame is too slow to do unless asked to.
+                if log_enabled!(Level::Debug) {
+                    debug!(
+                        "received frame in xdpscan rx loop: {:?}",
+                        etherparse::PacketHeaders::from_ethernet_slice(frame)
                     );
-                    let frame_ref = unsafe {
-                        umem.read_from_umem_checked(&recv_frame.addr(), &recv_frame.len())
-                            .unwrap()
+                }
+                if !sources.has_vlan_tags(frame) {
+                    debug!("ignoring frame from another VLAN");
+                    return;
+                }
+                let (segment, headers) = match TcpSegment::parse_with_headers(frame) {
+                    Some((segment, headers)) => (Some(segment), Some(headers)),
+                    None => (None, None),
+                };
+                let reply = match module.classify(frame, segment.as_ref(), &cookies) {
+                    Some(reply) => reply,
+                    None => return,
+                };
+                debug!("received reply in xdpscan rx loop: {:?}", reply);
+                if !sources.contains(reply.dst_ip, reply.dst_port) {
+                    debug!("ignoring reply to {}:{}", reply.dst_ip, reply.dst_port);
+                    return;
+                }
+                if let (Some(resetter), Some(seq)) = (&resetter, reply.reset_seq) {
+                    let probed = match &resetter.targets {
+                        Some(targets) => targets.contains(&reply.responder),
+                        None => true,
                     };
-
-                    match etherparse::PacketHeaders::from_ethernet_slice(&frame_ref) {
-                        Err(value) => println!("Err {:?}", value),
-                        Ok(value) => {
-                            eprintln!("received frame in xdpscan rx loop:");
-                            eprintln!("{:?}", value);
-                            if let Some(responder) = parse_response(&value) {
-                                responders.push(responder);
-                            }
-                        }
+                    if probed {
+                        let reset = Reset {
+                            target: reply.responder.clone(),
+                            src_ip: reply.dst_ip,
+                            src_port: reply.dst_port,
+                            seq,
+                        };
+                        // The sender only stops after we do.
+                        let _ = resetter.resets.send(reset);
                     }
                 }
-
-                // Add frames back to fill queue
-                while unsafe {
-                    fill_q
-                        .produce_and_wakeup(&frames[..frames_rcvd], rx_q.fd(), poll_ms_timeout)
-                        .unwrap()
-                } != frames_rcvd
-                {
-                    // Loop until frames added to the fill ring.
-                    eprintln!("fill_q.produce_and_wakeup() failed to allocate");
+                let info = headers.map(|headers| ReplyInfo {
+                    sent_at: probe_sent_at(
+                        send_times.sent_at(&reply.responder),
+                        headers
+                            .timestamps
+                            .and_then(|(_, tsecr)| clock.sent_at(tsecr)),
+                    ),
+                    os: fingerprints.as_ref().and_then(|db| db.classify(&headers)),
+                    headers,
+                    received_at,
+                    interface: interface.clone(),
+                });
+                if let Some(metrics) = &metrics {
+                    metrics.add_reply(reply.state);
                 }
+                responders.lock().unwrap().push(ScanResult {
+                    target: reply.responder,
+                    state: reply.state,
+                    reply: info,
+                    hop: reply.hop,
+                });
+            })
+            .expect("failed to receive frames");
 
-                eprintln!(
-                    "fill_q.produce_and_wakeup() submitted {} frames",
-                    frames_rcvd
-                );
-
-                total_frames_rcvd += frames_rcvd;
-                eprintln!("total frames received: {}", total_frames_rcvd);
+        total_frames_rcvd += frames_rcvd;
+        if let Some(metrics) = &metrics {
+            metrics.add_frames_received(frames_rcvd);
+            if stats_read_at.map_or(true, |read_at| read_at.elapsed() >= stats_interval) {
+                stats_read_at = Some(Instant::now());
+                match rx.xdp_stats() {
+                    Ok(Some(stats)) => metrics.set_xdp_stats(stats),
+                    Ok(None) => {}
+                    Err(e) => debug!("failed to read XDP statistics: {}", e),
+                }
             }
         }
     }
 
-    responders
+    debug!("total frames received: {}", total_frames_rcvd);
+    rx
 }
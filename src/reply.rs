@@ -0,0 +1,224 @@
+//! What replies say beyond the state of the port they came from, for telling operating systems
+//! apart and measuring latency.
+//!
+//! `ReplyInfo` is written as space separated `key=value` fields, which checkpoints and the
+//! command line scanner's results are stored with:
+//!
+//! - `ip`: the IP version, 4 or 6.
+//! - `ttl`, `ipid`, `df`, `win` and `flags`: header fields, with `ipid` left out for IPv6.
+//! - `opts`: the TCP options, comma separated as `TcpOption` writes them.
+//! - `ts`: TSval and TSecr, comma separated.
+//! - `recv` and `sent`: when the reply was received and its probe sent, in seconds since the
+//!   Unix epoch.
+//! - `rtt`: the round trip time in seconds, which is only written to be read by people.
+//! - `if`: the interface, and queue, the reply was received on.
+//! - `os`: the operating system the reply was guessed to come from, as a p0f label with `%`
+//!   and whitespace escaped as `%` and the hex of their bytes, e.g. `7%20or%208`, followed by
+//!   `fuzzy=1` if the guess is fuzzy.
+use crate::backend::Interface;
+use crate::fingerprint::OsGuess;
+use crate::packet::{ReplyHeaders, TcpOption};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The headers of a reply, and when and where it was received.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ReplyInfo {
+    pub headers: ReplyHeaders,
+    pub received_at: SystemTime,
+    /// When the probe the reply answers was sent. The sender records it for every probe, and
+    /// it is read back from the TSecr of the reply, to the millisecond, for replies that come
+    /// after the sender has forgotten.
+    pub sent_at: Option<SystemTime>,
+    /// `None` for backends without interfaces.
+    pub interface: Option<Interface>,
+    /// `None` unless the scan fingerprints replies, see `ScanConfig::fingerprints`, and the
+    /// reply is a SYN-ACK matching a signature.
+    pub os: Option<OsGuess>,
+}
+
+impl ReplyInfo {
+    /// Time from sending the probe to receiving the reply.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.received_at.duration_since(self.sent_at?).ok()
+    }
+}
+
+fn write_time(f: &mut fmt::Formatter, time: SystemTime) -> fmt::Result {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    write!(
+        f,
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    )
+}
+
+fn parse_time(s: &str) -> Option<SystemTime> {
+    let (secs, nanos) = s.split_once('.')?;
+    if nanos.len() != 9 {
+        return None;
+    }
+    let since_epoch = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    Some(UNIX_EPOCH + since_epoch)
+}
+
+/// Escapes `%` and whitespace, which would end the field, see the module documentation.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '%' || c.is_whitespace() {
+            let mut utf8 = [0; 4];
+            for byte in c.encode_utf8(&mut utf8).bytes() {
+                escaped += &format!("%{:02X}", byte);
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Undoes `escape`, or `None` if an escape is not followed by two hex digits.
+fn unescape(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl fmt::Display for ReplyInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let headers = &self.headers;
+        write!(f, "ip={} ttl={}", headers.ip_version, headers.ttl)?;
+        if let Some(ip_id) = headers.ip_id {
+            write!(f, " ipid={}", ip_id)?;
+        }
+        write!(
+            f,
+            " df={} win={} flags=0x{:02x}",
+            u8::from(headers.dont_fragment),
+            headers.window,
+            headers.flags
+        )?;
+        if !headers.tcp_options.is_empty() {
+            let options: Vec<String> = headers.tcp_options.iter().map(|o| o.to_string()).collect();
+            write!(f, " opts={}", options.join(","))?;
+        }
+        if let Some((tsval, tsecr)) = headers.timestamps {
+            write!(f, " ts={},{}", tsval, tsecr)?;
+        }
+        f.write_str(" recv=")?;
+        write_time(f, self.received_at)?;
+        if let Some(sent_at) = self.sent_at {
+            f.write_str(" sent=")?;
+            write_time(f, sent_at)?;
+        }
+        if let Some(rtt) = self.rtt() {
+            write!(f, " rtt={}.{:09}", rtt.as_secs(), rtt.subsec_nanos())?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " if={}", interface)?;
+        }
+        if let Some(os) = &self.os {
+            write!(f, " os={}", escape(&os.label.to_string()))?;
+            if os.fuzzy {
+                f.write_str(" fuzzy=1")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ReplyInfo {
+    type Err = String;
+
+    /// Parses the fields `Display` writes. `rtt` is ignored, as it follows from `recv` and
+    /// `sent`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid reply info {}", s);
+        let (mut ttl, mut window, mut flags, mut received_at) = (None, None, None, None);
+        let mut ip_version = None;
+        let mut info = ReplyInfo {
+            headers: ReplyHeaders {
+                ip_version: 0,
+                ttl: 0,
+                ip_id: None,
+                dont_fragment: false,
+                window: 0,
+                flags: 0,
+                tcp_options: vec![],
+                timestamps: None,
+            },
+            received_at: UNIX_EPOCH,
+            sent_at: None,
+            interface: None,
+            os: None,
+        };
+        let mut fuzzy = false;
+        for field in s.split_whitespace() {
+            let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+            let headers = &mut info.headers;
+            match key {
+                "ip" => {
+                    ip_version = match value {
+                        "4" => Some(4),
+                        "6" => Some(6),
+                        _ => return Err(invalid()),
+                    }
+                }
+                "ttl" => ttl = Some(value.parse().map_err(|_| invalid())?),
+                "ipid" => headers.ip_id = Some(value.parse().map_err(|_| invalid())?),
+                "df" => headers.dont_fragment = value == "1",
+                "win" => window = Some(value.parse().map_err(|_| invalid())?),
+                "flags" => {
+                    let hex = value.strip_prefix("0x").ok_or_else(invalid)?;
+                    flags = Some(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                }
+                "opts" => {
+                    let options: Result<Vec<TcpOption>, String> =
+                        value.split(',').map(str::parse).collect();
+                    headers.tcp_options = options?;
+                }
+                "ts" => {
+                    let (tsval, tsecr) = value.split_once(',').ok_or_else(invalid)?;
+                    let tsval = tsval.parse().map_err(|_| invalid())?;
+                    let tsecr = tsecr.parse().map_err(|_| invalid())?;
+                    headers.timestamps = Some((tsval, tsecr));
+                }
+                "recv" => received_at = Some(parse_time(value).ok_or_else(invalid)?),
+                "sent" => info.sent_at = Some(parse_time(value).ok_or_else(invalid)?),
+                "rtt" => {}
+                "if" => info.interface = Some(value.parse()?),
+                "os" => {
+                    let label = unescape(value).ok_or_else(invalid)?.parse()?;
+                    info.os = Some(OsGuess {
+                        label,
+                        fuzzy: false,
+                    });
+                }
+                "fuzzy" => fuzzy = value == "1",
+                _ => return Err(invalid()),
+            }
+        }
+        info.headers.ip_version = ip_version.ok_or_else(invalid)?;
+        info.headers.ttl = ttl.ok_or_else(invalid)?;
+        info.headers.window = window.ok_or_else(invalid)?;
+        info.headers.flags = flags.ok_or_else(invalid)?;
+        info.received_at = received_at.ok_or_else(invalid)?;
+        if let Some(os) = &mut info.os {
+            os.fuzzy = fuzzy;
+        }
+        Ok(info)
+    }
+}
@@ -0,0 +1,119 @@
+//! Resolving hostname targets to addresses before a scan.
+//!
+//! Hostnames are resolved through the system resolver, to both their IPv4 (A) and IPv6 (AAAA)
+//! addresses. Probes and replies only carry addresses, so `Hostnames` remembers which names
+//! each address was resolved from, to give them back alongside the results.
+use crate::ScanResult;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+/// Which of the addresses a hostname resolves to are scanned.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AddrSelection {
+    #[default]
+    All,
+    /// The first IPv4 and the first IPv6 address, in the order the resolver returned them.
+    FirstPerFamily,
+}
+
+impl fmt::Display for AddrSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddrSelection::All => f.write_str("all"),
+            AddrSelection::FirstPerFamily => f.write_str("first-per-family"),
+        }
+    }
+}
+
+impl FromStr for AddrSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(AddrSelection::All),
+            "first-per-family" => Ok(AddrSelection::FirstPerFamily),
+            _ => Err(format!("invalid address selection {}", s)),
+        }
+    }
+}
+
+/// Picks the addresses to scan out of those a hostname resolved to, dropping duplicates.
+pub fn select_addrs(addrs: &[IpAddr], selection: AddrSelection) -> Vec<IpAddr> {
+    let mut selected: Vec<IpAddr> = vec![];
+    for &addr in addrs {
+        let taken = match selection {
+            AddrSelection::All => selected.contains(&addr),
+            AddrSelection::FirstPerFamily => selected
+                .iter()
+                .any(|other| other.is_ipv4() == addr.is_ipv4()),
+        };
+        if !taken {
+            selected.push(addr);
+        }
+    }
+    selected
+}
+
+/// Resolves `hostname` to its addresses, and picks those to scan according to `selection`.
+pub fn resolve(hostname: &str, selection: AddrSelection) -> io::Result<Vec<IpAddr>> {
+    let addrs: Vec<IpAddr> = (hostname, 0)
+        .to_socket_addrs()?
+        .map(|addr| addr.ip())
+        .collect();
+    Ok(select_addrs(&addrs, selection))
+}
+
+/// A result with the hostnames its target's address was resolved from, if any.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NamedResult {
+    pub result: ScanResult,
+    pub hostnames: Vec<String>,
+}
+
+/// The hostnames targets were resolved from, by address.
+#[derive(Clone, Debug, Default)]
+pub struct Hostnames {
+    names: HashMap<IpAddr, Vec<String>>,
+}
+
+impl Hostnames {
+    pub fn new() -> Self {
+        Hostnames::default()
+    }
+
+    /// Resolves `hostname`, see `resolve`, and remembers it as a name of the addresses picked.
+    pub fn resolve(&mut self, hostname: &str, selection: AddrSelection) -> io::Result<Vec<IpAddr>> {
+        let addrs = resolve(hostname, selection)?;
+        for &addr in &addrs {
+            self.insert(addr, hostname);
+        }
+        Ok(addrs)
+    }
+
+    /// Remembers `hostname` as a name of `addr`.
+    pub fn insert(&mut self, addr: IpAddr, hostname: &str) {
+        let names = self.names.entry(addr).or_default();
+        if !names.iter().any(|name| name == hostname) {
+            names.push(hostname.to_string());
+        }
+    }
+
+    /// The hostnames `addr` was resolved from, in the order they were resolved.
+    pub fn names(&self, addr: IpAddr) -> &[String] {
+        self.names.get(&addr).map_or(&[], |names| &names[..])
+    }
+
+    /// Pairs `results` with the hostnames of their addresses.
+    pub fn name_results(&self, results: Vec<ScanResult>) -> Vec<NamedResult> {
+        results
+            .into_iter()
+            .map(|result| NamedResult {
+                hostnames: self.names(result.target.ip).to_vec(),
+                result,
+            })
+            .collect()
+    }
+}
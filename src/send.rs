@@ -1,112 +1,331 @@
+use crate::backend::PacketTx;
+use crate::metrics::Metrics;
+use crate::packet::{flags, Frame, IpIds, ProbeOptions};
+use crate::probe::{Cookies, Probe, ProbeModule, Templates};
+use crate::rng::Rng;
+use crate::source::Sources;
 use crate::{SrcConfig, Target};
-use etherparse::PacketBuilder;
-use std::net::{IpAddr, Ipv4Addr};
-use xsk_rs::{CompQueue, FrameDesc, TxQueue, Umem};
-
-fn generate_eth_frame(
-    src_mac: [u8; 6],
-    dst_mac: [u8; 6],
-    src_ip: IpAddr,
-    src_port: u16,
-    dst_ip: IpAddr,
-    dst_port: u16,
-) -> Vec<u8> {
-    let builder = PacketBuilder::ethernet2(src_mac, dst_mac);
-
-    if src_ip.is_ipv6() || dst_ip.is_ipv6() {
-        let src_ip = match src_ip {
-            IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
-            IpAddr::V6(ipv6) => ipv6,
-        };
-        let dst_ip = match dst_ip {
-            IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
-            IpAddr::V6(ipv6) => ipv6,
-        };
+use log::debug;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A request to reset the half-open connection a SYN-ACK from `target` to `src_ip` and
+/// `src_port` started.
+#[derive(Clone, Debug)]
+pub struct Reset {
+    pub target: Target,
+    pub src_ip: IpAddr,
+    pub src_port: u16,
+    /// Acknowledgment number of the SYN-ACK, which is the sequence number the RST must carry.
+    pub seq: u32,
+}
+
+/// The clock of the TSvals of probes, which counts milliseconds from a random offset, as Linux
+/// does. Replies that echo a TSval tell when their probe was sent.
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeClock {
+    start: Instant,
+    start_time: SystemTime,
+    offset: u32,
+    /// Whether probes carry a TSval at all.
+    stamped: bool,
+}
+
+impl ProbeClock {
+    pub fn new(offset: u32, stamped: bool) -> Self {
+        ProbeClock {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            offset,
+            stamped,
+        }
+    }
+
+    /// The current time, which unlike the system clock never goes back during a scan.
+    pub fn now(&self) -> SystemTime {
+        self.start_time + self.start.elapsed()
+    }
+
+    fn timestamp(&self) -> u32 {
+        let elapsed_ms = self.start.elapsed().as_millis() as u32;
+        self.offset.wrapping_add(elapsed_ms)
+    }
 
-        let builder = builder
-            .ipv6(src_ip.octets(), dst_ip.octets(), 20)
-            .tcp(src_port, dst_port, 0, 4)
-            .syn();
-        let mut result = Vec::<u8>::with_capacity(builder.size(0));
-        builder.write(&mut result, &[]).unwrap();
-        result
-    } else {
-        let src_ip = match src_ip {
-            IpAddr::V4(ipv4) => ipv4,
-            IpAddr::V6(ipv6) => ipv6
-                .to_ipv4()
-                .expect("Failed to convert ipv6 to ipv4, this shouldn't happen"),
+    /// When the probe with the TSval `timestamp` was sent, or `None` if no probe could have
+    /// carried it.
+    pub fn sent_at(&self, timestamp: u32) -> Option<SystemTime> {
+        let elapsed_ms = timestamp.wrapping_sub(self.offset);
+        if !self.stamped || u128::from(elapsed_ms) > self.start.elapsed().as_millis() {
+            return None;
+        }
+        Some(self.start_time + Duration::from_millis(u64::from(elapsed_ms)))
+    }
+}
+
+/// When probes were last sent to each target, by the clock of the scan, shared with the receive
+/// thread to time replies whether they echo a TSval or not. Targets are forgotten `retention`
+/// after their probe was sent, so that streamed scans don't remember every target.
+#[derive(Clone, Debug)]
+pub struct SendTimes {
+    retention: Duration,
+    sent: Arc<Mutex<SentProbes>>,
+}
+
+#[derive(Debug, Default)]
+struct SentProbes {
+    by_target: HashMap<Target, SystemTime>,
+    // Targets in the order their probes were sent, to forget them in.
+    order: VecDeque<(SystemTime, Target)>,
+}
+
+impl SendTimes {
+    pub fn new(retention: Duration) -> Self {
+        SendTimes {
+            retention,
+            sent: Arc::new(Mutex::new(SentProbes::default())),
+        }
+    }
+
+    /// Records the probes of a batch, in the order they were sent, and forgets the old ones.
+    fn record(&self, batch: &mut Vec<(Target, SystemTime)>) {
+        let now = match batch.last() {
+            Some(&(_, now)) => now,
+            None => return,
         };
-        let dst_ip = match dst_ip {
-            IpAddr::V4(ipv4) => ipv4,
-            IpAddr::V6(ipv6) => ipv6
-                .to_ipv4()
-                .expect("Failed to convert ipv6 to ipv4, this shouldn't happen"),
+        let mut sent = self.sent.lock().unwrap();
+        let sent = &mut *sent;
+        for (target, sent_at) in batch.drain(..) {
+            sent.by_target.insert(target.clone(), sent_at);
+            sent.order.push_back((sent_at, target));
+        }
+        while let Some(&(sent_at, _)) = sent.order.front() {
+            if sent_at + self.retention > now {
+                break;
+            }
+            let (sent_at, target) = sent.order.pop_front().unwrap();
+            // Unless the target has been probed again since.
+            if sent.by_target.get(&target) == Some(&sent_at) {
+                sent.by_target.remove(&target);
+            }
+        }
+    }
+
+    /// When the last probe to `target` was sent, unless it was too long ago to remember.
+    pub fn sent_at(&self, target: &Target) -> Option<SystemTime> {
+        self.sent.lock().unwrap().by_target.get(target).copied()
+    }
+}
+
+/// Writes the frames of probes and resets.
+struct FrameWriter<'a> {
+    src_config: &'a SrcConfig,
+    module: &'a dyn ProbeModule,
+    cookies: Cookies,
+    probe_options: &'a ProbeOptions,
+    templates: Templates,
+    ip_ids: IpIds,
+    clock: ProbeClock,
+}
+
+impl<'a> FrameWriter<'a> {
+    fn new(
+        src_config: &'a SrcConfig,
+        module: &'a dyn ProbeModule,
+        cookies: Cookies,
+        probe_options: &'a ProbeOptions,
+        clock: ProbeClock,
+    ) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+        FrameWriter {
+            src_config,
+            module,
+            cookies,
+            probe_options,
+            templates: Templates::new(),
+            ip_ids: IpIds::new(probe_options.ip_id, Rng::new(seed).next_u64()),
+            clock,
+        }
+    }
+
+    fn write_probe(
+        &mut self,
+        buf: &mut [u8],
+        (src_ip, src_port): (IpAddr, u16),
+        target: &Target,
+        ttl: u8,
+    ) -> usize {
+        let probe = Probe {
+            src_mac: self.src_config.src_mac,
+            dst_mac: self.src_config.dst_mac,
+            vlan_tags: &self.src_config.vlan_tags,
+            src_ip,
+            src_port,
+            target,
+            cookie: self.cookies.cookie(src_ip, src_port, target),
+            ip_id: self.ip_ids.next_id(),
+            ttl,
+            timestamp: self.clock.timestamp(),
+            options: self.probe_options,
+            templates: &self.templates,
         };
-        let builder = builder
-            .ipv4(
-                src_ip.octets(), // src ip
-                dst_ip.octets(), // dst ip
-                20,              // time to live
+        self.module.write_probe(buf, &probe)
+    }
+
+    fn write_reset(&mut self, buf: &mut [u8], reset: &Reset) -> usize {
+        Frame {
+            src_mac: self.src_config.src_mac,
+            dst_mac: self.src_config.dst_mac,
+            vlan_tags: &self.src_config.vlan_tags,
+            src_ip: reset.src_ip,
+            dst_ip: reset.target.ip,
+            ttl: self.probe_options.ttl,
+            ip_id: self.ip_ids.next_id(),
+            src_port: reset.src_port,
+            dst_port: reset.target.port,
+            seq: reset.seq,
+            ack: 0,
+            flags: flags::RST,
+            window: 0,
+            options: &[],
+            timestamp: self.clock.timestamp(),
+        }
+        .write(buf)
+    }
+}
+
+fn send_resets<T: PacketTx>(
+    tx: &mut T,
+    writer: &mut FrameWriter,
+    resets: &mut Vec<Reset>,
+    max_batch_size: usize,
+) -> usize {
+    let mut total_frames_sent = 0;
+    while !resets.is_empty() {
+        let mut pending = resets.iter();
+        let frames_sent = tx
+            .send_batch(
+                max_batch_size.min(resets.len()),
+                &mut |buf| match pending.next() {
+                    Some(reset) => writer.write_reset(buf, reset),
+                    None => 0,
+                },
             )
-            .tcp(src_port, dst_port, 0, 4)
-            .syn();
-        let mut result = Vec::<u8>::with_capacity(builder.size(0));
-        builder.write(&mut result, &[]).unwrap();
-        result
+            .expect("failed to send resets");
+        resets.drain(..frames_sent);
+        total_frames_sent += frames_sent;
     }
+    total_frames_sent
 }
 
-pub fn send(
-    targets: Vec<Target>,
-    src_config: SrcConfig,
-    mut tx_q: TxQueue,
-    mut _comp_q: CompQueue,
-    mut frames: Vec<FrameDesc>,
-    mut umem: Umem,
-) {
-    eprintln!("--------------------------");
-    eprintln!(
-        "tx frames[0] = {}, tx frames[-1] = {}",
-        frames[0].addr(),
-        frames[frames.len() - 1].addr()
-    );
-    eprintln!("--------------------------");
-
-    let mut free_frames = frames.len();
-    for (i, target) in targets.iter().enumerate() {
-        // Copy over some bytes to devs umem to transmit
-        let eth_frame = generate_eth_frame(
-            src_config.src_mac,
-            src_config.dst_mac,
-            src_config.src_ip,
-            src_config.src_port,
-            target.ip,
-            target.port,
-        );
-
-        unsafe {
-            umem.write_to_umem_checked(&mut frames[i], &eth_frame)
-                .unwrap()
-        };
+/// What the send loop builds probes with, and where it records them, see `send`.
+pub struct SendContext<'a> {
+    /// Picks the source of every probe.
+    pub src_config: SrcConfig,
+    /// Builds the probes, with `cookies` and `probe_options`.
+    pub module: &'a dyn ProbeModule,
+    pub cookies: Cookies,
+    pub probe_options: ProbeOptions,
+    /// Gives the TSvals of probes.
+    pub clock: ProbeClock,
+    /// Where the time every probe is sent is recorded.
+    pub send_times: SendTimes,
+    pub metrics: Option<Arc<Metrics>>,
+}
 
-        assert_eq!(frames[0].len(), eth_frame.len());
+/// Sends a probe to each of `targets` in order, with the TTL given along with it.
+///
+/// The number of probes sent is added to `progress` after every batch, and `probes_sent` is
+/// signalled once they all are. Frames sent, and those in flight, are counted in the metrics
+/// of `context`.
+///
+/// Resets requested through `resets` are sent between batches of probes. They are also sent
+/// after the probes, until every sender of `resets` has been dropped. `tx` is handed back
+/// once they are.
+pub fn send<T: PacketTx, I: Iterator<Item = (Target, u8)>>(
+    mut targets: I,
+    mut tx: T,
+    context: SendContext,
+    progress: &AtomicU64,
+    probes_sent: Sender<()>,
+    resets: Receiver<Reset>,
+) -> T {
+    let SendContext {
+        src_config,
+        module,
+        cookies,
+        probe_options,
+        clock,
+        send_times,
+        metrics,
+    } = context;
+    let max_batch_size: usize = 64;
+    let sources = Sources::new(&src_config);
+    let mut writer = FrameWriter::new(&src_config, module, cookies, &probe_options, clock);
+    let mut index = 0;
+    let mut total_frames_sent = 0;
+    let mut pending_resets = vec![];
+    let mut batch = Vec::with_capacity(max_batch_size);
 
-        free_frames -= 1;
+    loop {
+        pending_resets.extend(resets.try_iter());
+        let resets_sent = send_resets(&mut tx, &mut writer, &mut pending_resets, max_batch_size);
+        total_frames_sent += resets_sent;
 
-        if free_frames == 0 {
-            unsafe {
-                tx_q.produce_and_wakeup(&frames[..])
-                    .expect("failed to produce targets")
-            };
-            let free_frames = frames.len();
+        let mut targets_done = false;
+        let frames_sent = tx
+            .send_batch(max_batch_size, &mut |buf| match targets.next() {
+                Some((target, ttl)) => {
+                    let source = sources.pick(&target, index);
+                    index += 1;
+                    let len = writer.write_probe(buf, source, &target, ttl);
+                    batch.push((target, clock.now()));
+                    len
+                }
+                None => {
+                    targets_done = true;
+                    0
+                }
+            })
+            .expect("failed to send probes");
+        send_times.record(&mut batch);
+
+        total_frames_sent += frames_sent;
+        progress.fetch_add(frames_sent as u64, Ordering::Relaxed);
+        if let Some(metrics) = &metrics {
+            metrics.add_resets(resets_sent);
+            metrics.add_probes(frames_sent);
+            metrics.set_in_flight(tx.in_flight());
+        }
+        if targets_done {
+            break;
         }
     }
+    // The receiver may have stopped waiting already.
+    let _ = probes_sent.send(());
 
-    if free_frames != frames.len() {
-        unsafe {
-            tx_q.produce_and_wakeup(&frames[..frames.len() - free_frames])
-                .expect("failed to produce targets")
-        };
+    let poll_timeout = Duration::from_millis(100);
+    loop {
+        match resets.recv_timeout(poll_timeout) {
+            Ok(reset) => pending_resets.push(reset),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        pending_resets.extend(resets.try_iter());
+        let resets_sent = send_resets(&mut tx, &mut writer, &mut pending_resets, max_batch_size);
+        total_frames_sent += resets_sent;
+        if let Some(metrics) = &metrics {
+            metrics.add_resets(resets_sent);
+            metrics.set_in_flight(tx.in_flight());
+        }
     }
+
+    debug!("total frames sent: {}", total_frames_sent);
+    tx
 }
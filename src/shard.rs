@@ -0,0 +1,190 @@
+//! Splitting a scan between several independent scanners.
+//!
+//! Every scanner walks the same seeded probe order, and shard `i` of `n` only probes the
+//! targets at positions `p` with `p % n == i`, so scanners started with the same targets and
+//! seed and each of the `n` shards probe every target exactly once between them.
+use crate::checkpoint::Checkpoint;
+use crate::resolve::Hostnames;
+use crate::{PortState, ScanResult, Target};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// One of `count` disjoint parts of a scan.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Shard {
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    /// Panics unless `index < count`.
+    pub fn new(index: u64, count: u64) -> Self {
+        assert!(index < count, "shard {} out of range 0..{}", index, count);
+        Shard { index, count }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Whether the target at `position` in the probe order belongs to this shard.
+    pub fn contains(&self, position: u64) -> bool {
+        position % self.count == self.index
+    }
+}
+
+impl Default for Shard {
+    /// The whole scan.
+    fn default() -> Self {
+        Shard::new(0, 1)
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    /// Parses `i/n`, with `i` counted from 0.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid shard {}, expected i/n with i < n", s);
+        let mut parts = s.splitn(2, '/');
+        let index: u64 = parts.next().unwrap().parse().map_err(|_| invalid())?;
+        let count: u64 = parts
+            .next()
+            .ok_or_else(invalid)?
+            .parse()
+            .map_err(|_| invalid())?;
+        if index >= count {
+            return Err(invalid());
+        }
+        Ok(Shard::new(index, count))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Combines the results of the shards of a scan.
+///
+/// Each file is either a checkpoint or a list of results with one `ip:port` per line,
+/// optionally followed by the port state, the hostnames the address was resolved from and the
+/// `key=value` fields of the reply, as printed by the command line scanner. Ports without a
+/// state are open. The merged results are sorted and free of duplicates, keeping the first
+/// result seen for a target, and returned with the hostnames of every file.
+///
+/// Checkpoints are checked to come from the same scan, and to be of different shards of the
+/// same count. Lists of results don't say which shard they are of, so only if every file is
+/// a checkpoint are they checked to cover all shards.
+pub fn merge_results<P: AsRef<Path>>(paths: &[P]) -> io::Result<(Vec<ScanResult>, Hostnames)> {
+    let mut results = BTreeMap::new();
+    let mut hostnames = Hostnames::new();
+    let mut scan: Option<(u64, u64)> = None;
+    let mut shards: Vec<(Shard, &Path)> = vec![];
+    let mut only_checkpoints = true;
+
+    for path in paths {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+
+        if Checkpoint::is_checkpoint(&contents) {
+            let checkpoint = Checkpoint::load(path)?;
+            let this_scan = (checkpoint.seed, checkpoint.config_hash);
+            if *scan.get_or_insert(this_scan) != this_scan {
+                return Err(invalid_data(format!(
+                    "checkpoint {} belongs to a different scan",
+                    path.display()
+                )));
+            }
+            let shard = checkpoint.shard;
+            if let Some((first, first_path)) = shards.first() {
+                if first.count != shard.count {
+                    return Err(invalid_data(format!(
+                        "checkpoint {} is of shard {}, but {} is of shard {}",
+                        path.display(),
+                        shard,
+                        first_path.display(),
+                        first
+                    )));
+                }
+            }
+            if let Some((_, other_path)) = shards.iter().find(|(other, _)| *other == shard) {
+                return Err(invalid_data(format!(
+                    "checkpoints {} and {} are both of shard {}",
+                    other_path.display(),
+                    path.display(),
+                    shard
+                )));
+            }
+            shards.push((shard, path));
+            for result in checkpoint.results {
+                let target = result.target;
+                results
+                    .entry((target.ip, target.port))
+                    .or_insert((result.state, result.reply));
+            }
+            continue;
+        }
+
+        only_checkpoints = false;
+        for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let invalid = || invalid_data(format!("invalid result {} in {}", line, path.display()));
+            let mut fields = line.split_whitespace().peekable();
+            let addr: SocketAddr = fields.next().unwrap().parse().map_err(|_| invalid())?;
+            let state = match fields.next() {
+                Some(state) => state.parse().map_err(|_| invalid())?,
+                None => PortState::Open,
+            };
+            // Hostnames come before the reply, whose fields are the ones holding a `=`.
+            if let Some(names) = fields.next_if(|field| !field.contains('=')) {
+                for name in names.split(',') {
+                    hostnames.insert(addr.ip(), name);
+                }
+            }
+            let reply: Vec<&str> = fields.collect();
+            let reply = if reply.is_empty() {
+                None
+            } else {
+                Some(reply.join(" ").parse().map_err(|_| invalid())?)
+            };
+            results
+                .entry((addr.ip(), addr.port()))
+                .or_insert((state, reply));
+        }
+    }
+
+    if let Some((first, _)) = shards.first() {
+        if only_checkpoints && shards.len() as u64 != first.count {
+            return Err(invalid_data(format!(
+                "only {} of the {} shards have a checkpoint",
+                shards.len(),
+                first.count
+            )));
+        }
+    }
+
+    let results = results
+        .into_iter()
+        .map(|((ip, port), (state, reply))| ScanResult {
+            target: Target { ip, port },
+            state,
+            reply,
+            hop: None,
+        })
+        .collect();
+    Ok((results, hostnames))
+}
@@ -0,0 +1,82 @@
+//! Picking the source address and port of each probe from those in a `SrcConfig`.
+use crate::packet::{self, VlanTag};
+use crate::rng::{fnv1a, Rng, FNV1A_OFFSET};
+use crate::{SrcConfig, SrcSelection, Target};
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Debug)]
+pub struct Sources {
+    ipv4: Vec<IpAddr>,
+    ipv6: Vec<IpAddr>,
+    ports: RangeInclusive<u16>,
+    selection: SrcSelection,
+    vlan_tags: Vec<VlanTag>,
+}
+
+impl Sources {
+    pub fn new(src_config: &SrcConfig) -> Self {
+        assert!(
+            !src_config.src_ips.is_empty(),
+            "at least one source address is required"
+        );
+        assert!(
+            !src_config.src_ports.is_empty(),
+            "at least one source port is required"
+        );
+
+        let (ipv4, ipv6) = src_config.src_ips.iter().partition(|ip| ip.is_ipv4());
+        Sources {
+            ipv4,
+            ipv6,
+            ports: src_config.src_ports.clone(),
+            selection: src_config.src_selection,
+            vlan_tags: src_config.vlan_tags.clone(),
+        }
+    }
+
+    /// Returns the source address and port to send the `index`th probe, to `target`, from.
+    pub fn pick(&self, target: &Target, index: u64) -> (IpAddr, u16) {
+        let (same_family, other_family) = match target.ip {
+            IpAddr::V4(_) => (&self.ipv4, &self.ipv6),
+            IpAddr::V6(_) => (&self.ipv6, &self.ipv4),
+        };
+        let ips = if same_family.is_empty() {
+            other_family
+        } else {
+            same_family
+        };
+
+        let ip_count = ips.len() as u64;
+        let port_count = u64::from(self.ports.end() - self.ports.start()) + 1;
+        let choice = match self.selection {
+            SrcSelection::RoundRobin => index,
+            SrcSelection::Hashed => {
+                let hash = fnv1a(FNV1A_OFFSET, &packet::ipv6_octets(target.ip));
+                let hash = fnv1a(hash, &target.port.to_be_bytes());
+                // FNV-1a leaves the low bits poorly mixed, and those pick the source.
+                Rng::new(hash).next_u64()
+            }
+        } % (ip_count * port_count);
+
+        let ip = ips[(choice % ip_count) as usize];
+        let port = self.ports.start() + (choice / ip_count) as u16;
+        (ip, port)
+    }
+
+    /// Returns whether probes may be sent from `ip` and `port`, i.e. whether a reply to them
+    /// can be a reply to a probe.
+    pub fn contains(&self, ip: IpAddr, port: u16) -> bool {
+        // Probes to IPv6 targets from an IPv4 source are sent from its IPv4-mapped address.
+        let ip = match ip {
+            IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        self.ports.contains(&port) && (self.ipv4.contains(&ip) || self.ipv6.contains(&ip))
+    }
+
+    /// Returns whether `frame` arrived on the VLANs probes are sent on.
+    pub fn has_vlan_tags(&self, frame: &[u8]) -> bool {
+        packet::has_vlan_tags(frame, &self.vlan_tags)
+    }
+}
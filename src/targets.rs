@@ -0,0 +1,202 @@
+//! Targets streamed from files or stdin, for lists too large to hold in memory.
+//!
+//! Each line of a target list holds one of:
+//!
+//! - an address, `192.0.2.1` or `2001:db8::1`, probed on every default port,
+//! - an address and port, `192.0.2.1:80` or `[2001:db8::1]:80`,
+//! - a CIDR block, `192.0.2.0/24` or `2001:db8::/120`, whose every address is probed on every
+//!   default port,
+//! - an inclusive range of addresses of the same family, `192.0.2.10-192.0.2.20`, probed the
+//!   same way.
+//!
+//! Blank lines are skipped, and `#` starts a comment running to the end of the line. Lines are
+//! only read, and blocks and ranges only expanded, as the scan needs their targets.
+use crate::Target;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::slice;
+
+/// A stream of targets, read as the scan needs them.
+pub trait TargetSource: Send {
+    /// Returns the next target, or `None` once there are no more.
+    fn next_target(&mut self) -> io::Result<Option<Target>>;
+}
+
+impl<I: Iterator<Item = Target> + Send> TargetSource for I {
+    fn next_target(&mut self) -> io::Result<Option<Target>> {
+        Ok(self.next())
+    }
+}
+
+fn ip_bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ipv4) => u128::from(u32::from(ipv4)),
+        IpAddr::V6(ipv6) => u128::from(ipv6),
+    }
+}
+
+/// The targets of one line: consecutive addresses, each probed on the same ports.
+#[derive(Clone, Debug)]
+struct Block {
+    next: Option<u128>,
+    last: u128,
+    ipv6: bool,
+    /// The port of the line, or `None` for the default ports.
+    port: Option<u16>,
+    port_index: usize,
+}
+
+impl Block {
+    fn new(first: IpAddr, last: IpAddr, port: Option<u16>) -> Self {
+        Block {
+            next: Some(ip_bits(first)),
+            last: ip_bits(last),
+            ipv6: first.is_ipv6(),
+            port,
+            port_index: 0,
+        }
+    }
+
+    fn next(&mut self, default_ports: &[u16]) -> Option<Target> {
+        let bits = self.next?;
+        let ports = match &self.port {
+            Some(port) => slice::from_ref(port),
+            None => default_ports,
+        };
+        let port = ports[self.port_index];
+        self.port_index += 1;
+        if self.port_index == ports.len() {
+            self.port_index = 0;
+            self.next = if bits == self.last {
+                None
+            } else {
+                Some(bits + 1)
+            };
+        }
+        let ip = if self.ipv6 {
+            IpAddr::V6(Ipv6Addr::from(bits))
+        } else {
+            IpAddr::V4(Ipv4Addr::from(bits as u32))
+        };
+        Some(Target { ip, port })
+    }
+}
+
+/// Parses a line holding a CIDR block, range, address or address and port, without comments.
+fn parse_block(line: &str, default_ports: &[u16]) -> Result<Block, String> {
+    let invalid = || format!("invalid target {}", line);
+    let no_ports = || format!("no port to probe {} on", line);
+
+    if let Some((ip, prefix_len)) = line.split_once('/') {
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let bits = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix_len: u32 = match prefix_len.parse() {
+            Ok(prefix_len) if prefix_len <= bits => prefix_len,
+            _ => return Err(invalid()),
+        };
+        // Host bits are masked off, so 192.0.2.7/24 is 192.0.2.0/24.
+        let host_mask = u128::MAX.checked_shr(128 - bits + prefix_len).unwrap_or(0);
+        let first = ip_bits(ip) & !host_mask;
+        let (first, last) = match ip {
+            IpAddr::V4(_) => (
+                IpAddr::V4(Ipv4Addr::from(first as u32)),
+                IpAddr::V4(Ipv4Addr::from((first | host_mask) as u32)),
+            ),
+            IpAddr::V6(_) => (
+                IpAddr::V6(Ipv6Addr::from(first)),
+                IpAddr::V6(Ipv6Addr::from(first | host_mask)),
+            ),
+        };
+        if default_ports.is_empty() {
+            return Err(no_ports());
+        }
+        Ok(Block::new(first, last, None))
+    } else if let Some((first, last)) = line.split_once('-') {
+        let first: IpAddr = first.trim().parse().map_err(|_| invalid())?;
+        let last: IpAddr = last.trim().parse().map_err(|_| invalid())?;
+        if first.is_ipv4() != last.is_ipv4() || ip_bits(first) > ip_bits(last) {
+            return Err(invalid());
+        }
+        if default_ports.is_empty() {
+            return Err(no_ports());
+        }
+        Ok(Block::new(first, last, None))
+    } else if let Ok(addr) = line.parse::<SocketAddr>() {
+        Ok(Block::new(addr.ip(), addr.ip(), Some(addr.port())))
+    } else {
+        let ip: IpAddr = line.parse().map_err(|_| invalid())?;
+        if default_ports.is_empty() {
+            return Err(no_ports());
+        }
+        Ok(Block::new(ip, ip, None))
+    }
+}
+
+/// Reads a target list, see the module documentation, a line at a time.
+pub struct TargetReader<R> {
+    reader: R,
+    /// Ports of the lines that don't give one.
+    default_ports: Vec<u16>,
+    line: String,
+    line_number: usize,
+    block: Option<Block>,
+}
+
+impl<R: BufRead> TargetReader<R> {
+    pub fn new(reader: R, default_ports: Vec<u16>) -> Self {
+        TargetReader {
+            reader,
+            default_ports,
+            line: String::new(),
+            line_number: 0,
+            block: None,
+        }
+    }
+}
+
+impl TargetReader<Box<dyn BufRead + Send>> {
+    /// Reads the target list at `path`, or stdin if `path` is `-`.
+    pub fn open(path: &str, default_ports: Vec<u16>) -> io::Result<Self> {
+        let reader: Box<dyn BufRead + Send> = if path == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+        Ok(TargetReader::new(reader, default_ports))
+    }
+}
+
+impl<R: BufRead + Send> TargetSource for TargetReader<R> {
+    fn next_target(&mut self) -> io::Result<Option<Target>> {
+        loop {
+            if let Some(block) = &mut self.block {
+                if let Some(target) = block.next(&self.default_ports) {
+                    return Ok(Some(target));
+                }
+                self.block = None;
+            }
+
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            let line = match self.line.split_once('#') {
+                Some((line, _comment)) => line,
+                None => &self.line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            let block = parse_block(line, &self.default_ports).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", self.line_number, e),
+                )
+            })?;
+            self.block = Some(block);
+        }
+    }
+}
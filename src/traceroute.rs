@@ -0,0 +1,431 @@
+//! Mapping the paths to many targets at once, by sending probes with every TTL up to a maximum
+//! and listening for the ICMP time exceeded errors of the routers where they run out.
+//!
+//! Classic traceroute tells its probes apart by their ports, so load balancers hashing the ports
+//! into their choice of path send each TTL down another one, and the hops it reports may not
+//! lie on any single path. Like paris-traceroute, every probe to a target is sent from the same
+//! source address and port, with the TTL carried in a field load balancers don't hash:
+//!
+//! - TCP probes are SYNs with the TTL added to the cookie as their sequence number, which the
+//!   target acknowledges and routers quote in their errors.
+//! - UDP probes carry a payload of 2 bytes more than the TTL, so it is read back from the length
+//!   routers quote. The first 2 bytes keep the UDP checksum at a value derived from the cookie.
+//!
+//! The probes of all targets for a TTL are sent before those of the next TTL.
+use crate::backend::{xdp::XdpIo, PacketIo};
+use crate::metrics::Metrics;
+use crate::packet::{
+    flags, IcmpError, IcmpErrorKind, ProbeOptions, TcpOption, TcpSegment, PROTOCOL_TCP,
+    PROTOCOL_UDP,
+};
+use crate::probe::{Cookies, Probe, ProbeModule, ProbeReply};
+use crate::recv::{recv, RecvContext, Resetter};
+use crate::send::{send, ProbeClock, SendContext, SendTimes};
+use crate::source::Sources;
+use crate::{cookie_key, PortState, ScanResult, SrcConfig, SrcSelection, Target};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The protocol of traceroute probes.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TraceProtocol {
+    /// SYNs, which get through firewalls letting connections to the target port in.
+    #[default]
+    Tcp,
+    /// UDP datagrams, which closed ports answer with an ICMP port unreachable error.
+    Udp,
+}
+
+impl fmt::Display for TraceProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TraceProtocol::Tcp => "tcp",
+            TraceProtocol::Udp => "udp",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for TraceProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(TraceProtocol::Tcp),
+            "udp" => Ok(TraceProtocol::Udp),
+            _ => Err(format!("invalid traceroute protocol {}", s)),
+        }
+    }
+}
+
+/// Probes and timing of a traceroute.
+#[derive(Clone, Debug)]
+pub struct TraceConfig {
+    pub protocol: TraceProtocol,
+    /// TTL of the first probe to each target. Must be at least 1.
+    pub first_ttl: u8,
+    /// TTL of the last probe to each target. Must be at least `first_ttl`.
+    pub max_ttl: u8,
+    /// How long to give the receive thread to start before the first probe is sent.
+    pub startup_delay: Duration,
+    /// How long to keep listening for replies after the last probe has been sent.
+    pub wait_time: Duration,
+    /// Window, IP ID and TCP options of TCP probes. The TTL is ignored.
+    pub probe_options: ProbeOptions,
+    /// Where to count probes and replies, see `metrics`. The progress is that of the probes to
+    /// all targets at all TTLs.
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        TraceConfig {
+            protocol: TraceProtocol::Tcp,
+            first_ttl: 1,
+            max_ttl: 30,
+            startup_delay: Duration::from_secs(1),
+            wait_time: Duration::from_secs(5),
+            probe_options: ProbeOptions::default(),
+            metrics: None,
+        }
+    }
+}
+
+/// A reply to a traceroute probe: the TTL of the probe, and the router or target that answered
+/// it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Hop {
+    pub ttl: u8,
+    pub addr: IpAddr,
+}
+
+/// The path to a target.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Trace {
+    pub target: Target,
+    /// TTL of the first hop in `hops`.
+    pub first_ttl: u8,
+    /// The address that answered each TTL, or `None` if none did, up to the first TTL the
+    /// target answered, or the highest one anything answered if the target never did.
+    pub hops: Vec<Option<IpAddr>>,
+    /// The state of the target port, if the target answered.
+    pub state: Option<PortState>,
+}
+
+impl Trace {
+    /// Whether the probes reached the target.
+    pub fn reached(&self) -> bool {
+        self.state.is_some()
+    }
+}
+
+impl fmt::Display for Trace {
+    /// Writes the target and its state, or `unreached`, followed by a line per TTL with the
+    /// address that answered it, or `*`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addr = SocketAddr::new(self.target.ip, self.target.port);
+        match self.state {
+            Some(state) => write!(f, "{} {}", addr, state)?,
+            None => write!(f, "{} unreached", addr)?,
+        }
+        for (ttl, hop) in (usize::from(self.first_ttl)..).zip(&self.hops) {
+            match hop {
+                Some(addr) => write!(f, "\n{:3} {}", ttl, addr)?,
+                None => write!(f, "\n{:3} *", ttl)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The UDP checksum of probes with `cookie`, which must be neither 0 nor 0xffff.
+fn udp_checksum(cookie: u32) -> u16 {
+    (cookie % 0xfffe) as u16 + 1
+}
+
+/// Bytes of UDP payload before the TTL's worth of padding.
+const UDP_FILLER_LEN: usize = 2;
+
+#[derive(Debug)]
+struct TraceModule {
+    protocol: TraceProtocol,
+}
+
+impl TraceModule {
+    /// What an ICMP error about one of the probes says.
+    fn classify_error(&self, error: &IcmpError, cookies: &Cookies) -> Option<ProbeReply> {
+        let quoted = &error.quoted;
+        let target = Target {
+            ip: quoted.dst_ip,
+            port: quoted.dst_port,
+        };
+        let cookie = cookies.cookie(quoted.src_ip, quoted.src_port, &target);
+        let ttl = match (self.protocol, quoted.protocol) {
+            (TraceProtocol::Tcp, PROTOCOL_TCP) => quoted.word.wrapping_sub(cookie),
+            (TraceProtocol::Udp, PROTOCOL_UDP) => {
+                if quoted.word as u16 != udp_checksum(cookie) {
+                    return None;
+                }
+                (quoted.word >> 16).wrapping_sub(8 + UDP_FILLER_LEN as u32)
+            }
+            _ => return None,
+        };
+        let ttl = probe_ttl(ttl)?;
+        let state = match error.kind {
+            // Port unreachable, which the target sends from closed UDP ports.
+            IcmpErrorKind::Unreachable(code)
+                if error.src_ip == target.ip && code == port_unreachable(target.ip) =>
+            {
+                PortState::Closed
+            }
+            // Errors from routers say nothing about the port, but unreachable errors from the
+            // target, such as administratively prohibited, mean it filtered the probe.
+            IcmpErrorKind::Unreachable(_) | IcmpErrorKind::TimeExceeded => PortState::Filtered,
+        };
+        Some(ProbeReply {
+            responder: target,
+            dst_ip: quoted.src_ip,
+            dst_port: quoted.src_port,
+            state,
+            reset_seq: None,
+            hop: Some(Hop {
+                ttl,
+                addr: error.src_ip,
+            }),
+        })
+    }
+}
+
+/// The code of port unreachable errors, which differs between ICMP and ICMPv6.
+fn port_unreachable(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 3,
+        IpAddr::V6(_) => 4,
+    }
+}
+
+/// The TTL read back from a reply, or `None` if no probe could have been sent with it.
+fn probe_ttl(ttl: u32) -> Option<u8> {
+    match ttl {
+        1..=255 => Some(ttl as u8),
+        _ => None,
+    }
+}
+
+impl ProbeModule for TraceModule {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        match self.protocol {
+            TraceProtocol::Tcp => {
+                let seq = probe.cookie.wrapping_add(u32::from(probe.ttl));
+                probe.write_tcp(buf, flags::SYN, seq, 0)
+            }
+            TraceProtocol::Udp => {
+                let payload = [0; UDP_FILLER_LEN + 255];
+                let len = UDP_FILLER_LEN + usize::from(probe.ttl);
+                probe
+                    .udp_frame(&payload[..len])
+                    .write_with_checksum(buf, udp_checksum(probe.cookie))
+            }
+        }
+    }
+
+    fn classify(
+        &self,
+        frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        if let Some(error) = IcmpError::parse(frame) {
+            return self.classify_error(&error, cookies);
+        }
+        if self.protocol != TraceProtocol::Tcp {
+            return None;
+        }
+        // The target answers the SYN like a SYN scan's, acknowledging its sequence number.
+        let segment = segment?;
+        if !segment.has(flags::ACK) {
+            return None;
+        }
+        let ttl = segment
+            .ack
+            .wrapping_sub(cookies.reply_cookie(segment))
+            .wrapping_sub(1);
+        let hop = Some(Hop {
+            ttl: probe_ttl(ttl)?,
+            addr: segment.src_ip,
+        });
+        let reply = if segment.has(flags::SYN) {
+            ProbeReply::new(segment, PortState::Open, Some(segment.ack))
+        } else if segment.has(flags::RST) {
+            ProbeReply::new(segment, PortState::Closed, None)
+        } else {
+            return None;
+        };
+        Some(ProbeReply { hop, ..reply })
+    }
+}
+
+/// Builds the trace of each of `targets` from the hops in `results`.
+fn build_traces(targets: &[Target], results: &[ScanResult], config: &TraceConfig) -> Vec<Trace> {
+    let mut answered: HashMap<&Target, BTreeMap<u8, (IpAddr, PortState)>> = HashMap::new();
+    for result in results {
+        let hop = match result.hop {
+            Some(hop) if (config.first_ttl..=config.max_ttl).contains(&hop.ttl) => hop,
+            _ => continue,
+        };
+        // Retransmitted and duplicated replies only count once, with the first of them.
+        answered
+            .entry(&result.target)
+            .or_default()
+            .entry(hop.ttl)
+            .or_insert((hop.addr, result.state));
+    }
+
+    targets
+        .iter()
+        .map(|target| {
+            let hops = answered.remove(target).unwrap_or_default();
+            // Probes with higher TTLs reach the target too, and only the first counts.
+            let end = hops
+                .iter()
+                .find(|(_, (addr, _))| *addr == target.ip)
+                .map(|(&ttl, &(_, state))| (ttl, state));
+            let last_ttl = match end {
+                Some((ttl, _)) => ttl,
+                None => hops
+                    .keys()
+                    .next_back()
+                    .copied()
+                    .unwrap_or(config.first_ttl - 1),
+            };
+            Trace {
+                target: target.clone(),
+                first_ttl: config.first_ttl,
+                hops: (config.first_ttl..=last_ttl)
+                    .map(|ttl| hops.get(&ttl).map(|&(addr, _)| addr))
+                    .collect(),
+                state: end.map(|(_, state)| state),
+            }
+        })
+        .collect()
+}
+
+/// Traces the paths to `targets` from queue 0 of `ifname` over AF_XDP, see `trace_with`.
+pub fn trace(
+    ifname: &str,
+    src_config: SrcConfig,
+    config: TraceConfig,
+    targets: Vec<Target>,
+) -> io::Result<Vec<Trace>> {
+    let queue_id = 0;
+    let io = XdpIo::new(ifname, queue_id)?;
+    Ok(trace_with(io, src_config, config, targets))
+}
+
+/// Traces the paths to `targets` over an arbitrary packet I/O backend, and returns a trace per
+/// target in the order they were given.
+///
+/// Sources are picked by hashing the target whatever `src_config` says, so that every probe to
+/// a target takes the same flow. SYN-ACKs from targets are answered with a RST.
+pub fn trace_with<I: PacketIo>(
+    io: I,
+    src_config: SrcConfig,
+    config: TraceConfig,
+    targets: Vec<Target>,
+) -> Vec<Trace> {
+    assert!(
+        config.first_ttl >= 1 && config.first_ttl <= config.max_ttl,
+        "invalid TTL range {}-{}",
+        config.first_ttl,
+        config.max_ttl
+    );
+    let src_config = SrcConfig {
+        src_selection: SrcSelection::Hashed,
+        ..src_config
+    };
+    let interface = io.interface();
+    let (tx, rx) = io.split();
+    if let Some(metrics) = &config.metrics {
+        let ttls = u64::from(config.max_ttl - config.first_ttl) + 1;
+        metrics.start_scan(Some(targets.len() as u64 * ttls), 0);
+    }
+
+    let module: Arc<dyn ProbeModule> = Arc::new(TraceModule {
+        protocol: config.protocol,
+    });
+    let send_module = module.clone();
+    let cookies = Cookies::new(cookie_key());
+    let probe_options = config.probe_options.clone();
+    let stamped = probe_options.tcp_options.contains(&TcpOption::Timestamp);
+    let clock = ProbeClock::new(cookie_key() as u32, stamped);
+    let send_times = SendTimes::new(config.wait_time);
+    let recv_send_times = send_times.clone();
+    let sources = Sources::new(&src_config);
+    let (reset_tx, reset_rx) = mpsc::channel();
+    let resetter = Resetter {
+        targets: Some(targets.iter().cloned().collect()),
+        resets: reset_tx,
+    };
+
+    let done = Arc::new(AtomicBool::new(false));
+    let rx_done = done.clone();
+    let responders = Arc::new(Mutex::new(vec![]));
+    let rx_responders = responders.clone();
+    let recv_metrics = config.metrics.clone();
+    let recv_handle = thread::spawn(move || {
+        let context = RecvContext {
+            module: &*module,
+            cookies,
+            sources,
+            responders: rx_responders,
+            resetter: Some(resetter),
+            clock,
+            send_times: recv_send_times,
+            interface,
+            fingerprints: None,
+            metrics: recv_metrics,
+        };
+        recv(rx, rx_done, context)
+    });
+    thread::sleep(config.startup_delay);
+
+    let probe_targets = targets.clone();
+    let probes = (config.first_ttl..=config.max_ttl).flat_map(move |ttl| {
+        probe_targets
+            .clone()
+            .into_iter()
+            .map(move |target| (target, ttl))
+    });
+    let (sent_tx, sent_rx) = mpsc::channel::<()>();
+    let send_metrics = config.metrics.clone();
+    // Keeps running until the receive thread stops requesting resets.
+    let send_handle = thread::spawn(move || {
+        let context = SendContext {
+            src_config,
+            module: &*send_module,
+            cookies,
+            probe_options,
+            clock,
+            send_times,
+            metrics: send_metrics,
+        };
+        send(probes, tx, context, &AtomicU64::new(0), sent_tx, reset_rx);
+    });
+
+    // Only fails if the send thread panicked, which joining it reports.
+    let _ = sent_rx.recv();
+    thread::sleep(config.wait_time);
+    done.store(true, Ordering::Relaxed);
+    recv_handle.join().unwrap();
+    send_handle.join().unwrap();
+
+    let results = responders.lock().unwrap();
+    build_traces(&targets, &results, &config)
+}
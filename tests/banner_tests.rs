@@ -0,0 +1,158 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xdpscan::banner::{self, BannerConfig, BannerProbe};
+use xdpscan::Target;
+
+fn listen() -> (TcpListener, Target) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let target = Target {
+        ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        port: listener.local_addr().unwrap().port(),
+    };
+    (listener, target)
+}
+
+fn config() -> BannerConfig {
+    BannerConfig {
+        connect_timeout: Duration::from_secs(1),
+        read_timeout: Duration::from_millis(500),
+        idle_timeout: Duration::from_millis(100),
+        ..BannerConfig::default()
+    }
+}
+
+#[test]
+fn picks_probe_by_port() {
+    assert_eq!(BannerProbe::for_port(80), BannerProbe::HttpHead);
+    assert_eq!(BannerProbe::for_port(443), BannerProbe::TlsClientHello);
+    assert_eq!(BannerProbe::for_port(22), BannerProbe::Passive);
+}
+
+#[test]
+fn waits_for_servers_that_speak_first() {
+    let (listener, target) = listen();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"SSH-2.0-OpenSSH_8.4\r\n").unwrap();
+        // Keep the connection open, as a real server waiting for the client would.
+        thread::sleep(Duration::from_millis(300));
+    });
+
+    let start = Instant::now();
+    let data = banner::grab_banner(&target, BannerProbe::Passive, &config()).unwrap();
+    assert_eq!(data, b"SSH-2.0-OpenSSH_8.4\r\n");
+    assert!(start.elapsed() < Duration::from_millis(300));
+    server.join().unwrap();
+}
+
+#[test]
+fn sends_http_head() {
+    let (listener, target) = listen();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = vec![0; 1024];
+        let len = stream.read(&mut request).unwrap();
+        stream
+            .write_all(b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\n")
+            .unwrap();
+        String::from_utf8(request[..len].to_vec()).unwrap()
+    });
+
+    let data = banner::grab_banner(&target, BannerProbe::HttpHead, &config()).unwrap();
+    assert_eq!(data, b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\n");
+
+    let request = server.join().unwrap();
+    assert!(request.starts_with("HEAD / HTTP/1.0\r\n"));
+    assert!(request.contains("\r\nHost: 127.0.0.1\r\n"));
+    assert!(request.ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn sends_well_formed_tls_client_hello() {
+    let (listener, target) = listen();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut header = [0; 5];
+        stream.read_exact(&mut header).unwrap();
+        let mut record = vec![0; u16::from_be_bytes([header[3], header[4]]) as usize];
+        stream.read_exact(&mut record).unwrap();
+        // An unexpected_message alert, as a server that does not like the hello would send.
+        stream
+            .write_all(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x0a])
+            .unwrap();
+        (header, record)
+    });
+
+    let data = banner::grab_banner(&target, BannerProbe::TlsClientHello, &config()).unwrap();
+    assert_eq!(data, [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x0a]);
+
+    let (header, record) = server.join().unwrap();
+    // Handshake record carrying a ClientHello whose length covers the rest of the record.
+    assert_eq!(header[..3], [0x16, 0x03, 0x01]);
+    assert_eq!(record[0], 0x01);
+    let hello_len = u32::from_be_bytes([0, record[1], record[2], record[3]]) as usize;
+    assert_eq!(hello_len, record.len() - 4);
+    // TLS 1.2 client version.
+    assert_eq!(record[4..6], [0x03, 0x03]);
+}
+
+#[test]
+fn times_out_on_silent_servers() {
+    let (listener, target) = listen();
+    let server = thread::spawn(move || {
+        let (_stream, _) = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+
+    let start = Instant::now();
+    let data = banner::grab_banner(&target, BannerProbe::Passive, &config()).unwrap();
+    assert!(data.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(1));
+    server.join().unwrap();
+}
+
+#[test]
+fn grabs_banners_in_target_order_and_reports_closed_ports() {
+    let mut targets = vec![];
+    let mut servers = vec![];
+    for i in 0..4u8 {
+        let (listener, target) = listen();
+        targets.push(target);
+        servers.push(thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[b'a' + i]).unwrap();
+        }));
+    }
+    // Nothing listens on a port whose listener has been dropped.
+    let (listener, closed) = listen();
+    drop(listener);
+    targets.insert(2, closed);
+
+    let banners = banner::grab_banners(&targets, &config());
+    for server in servers {
+        server.join().unwrap();
+    }
+
+    let data: Vec<Vec<u8>> = banners.iter().map(|banner| banner.data.clone()).collect();
+    assert_eq!(
+        data,
+        vec![
+            b"a".to_vec(),
+            b"b".to_vec(),
+            vec![],
+            b"c".to_vec(),
+            b"d".to_vec()
+        ]
+    );
+    let failed: Vec<bool> = banners
+        .iter()
+        .map(|banner| banner.error.is_some())
+        .collect();
+    assert_eq!(failed, vec![false, false, true, false, false]);
+    assert!(banners
+        .iter()
+        .all(|banner| banner.probe == BannerProbe::Passive));
+}
@@ -0,0 +1,309 @@
+mod common;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{host, hosts, scan_config, sim_network, src_config, targets};
+use xdpscan::checkpoint::{config_hash, Checkpoint, SettledPosition};
+use xdpscan::probe::ScanType;
+use xdpscan::shard::Shard;
+use xdpscan::{PortState, ScanConfig, ScanResult, Target};
+
+fn checkpoint_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("xdpscan-{}-{}.checkpoint", name, process::id()))
+}
+
+#[test]
+fn checkpoint_round_trips() {
+    let path = checkpoint_path("round-trip");
+    let checkpoint = Checkpoint {
+        scan_type: ScanType::Fin,
+        seed: 42,
+        shard: Shard::new(2, 3),
+        position: 1000,
+        config_hash: 0x0123_4567_89ab_cdef,
+        results: vec![
+            ScanResult {
+                target: Target {
+                    ip: host(1),
+                    port: 80,
+                },
+                state: PortState::Closed,
+                reply: None,
+                hop: None,
+            },
+            ScanResult {
+                target: Target {
+                    ip: "fd00::2".parse().unwrap(),
+                    port: 443,
+                },
+                state: PortState::OpenFiltered,
+                reply: Some(
+                    "ip=6 ttl=57 df=0 win=65535 flags=0x12 opts=mss=1440,nop,ws=8 ts=7,42 \
+                     recv=1700000000.250000000 sent=1700000000.125000000 if=eth0/3"
+                        .parse()
+                        .unwrap(),
+                ),
+                hop: None,
+            },
+        ],
+    };
+    checkpoint.save(&path).unwrap();
+    assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn rejects_checkpoints_missing_fields() {
+    let path = checkpoint_path("missing");
+    let contents = concat!(
+        "xdpscan-checkpoint 1\n",
+        "scan syn\n",
+        "seed 42\n",
+        "position 1000\n",
+        "config 0123456789abcdef\n",
+        "result 10.0.0.1 80 open\n",
+    );
+    fs::write(&path, contents).unwrap();
+
+    let err = Checkpoint::load(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "checkpoint is missing shard");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn probe_order_depends_on_seed() {
+    let probes = |seed| {
+        let network = sim_network(40, 3);
+        let log = network.log();
+        let scan_config = ScanConfig {
+            seed,
+            ..scan_config()
+        };
+        xdpscan::scan_with(
+            network,
+            src_config(),
+            scan_config,
+            targets(&hosts(40), &[80]),
+        );
+        log.probes()
+    };
+
+    assert_eq!(probes(1), probes(1));
+    assert_ne!(probes(1), probes(2));
+    let probed: HashSet<Target> = probes(2).into_iter().collect();
+    assert_eq!(probed, targets(&hosts(40), &[80]).into_iter().collect());
+}
+
+#[test]
+fn resume_skips_completed_targets() {
+    let path = checkpoint_path("resume");
+
+    // A finished scan leaves a checkpoint at the end of the probe order.
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config(),
+        ScanConfig {
+            seed: 7,
+            checkpoint_path: Some(path.clone()),
+            ..scan_config()
+        },
+        targets(&hosts(40), &[80]),
+    );
+    let probes = log.probes();
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    assert_eq!(checkpoint.position, targets(&hosts(40), &[80]).len() as u64);
+    assert_eq!(checkpoint.results, results);
+
+    // Rewind it to look like the scan was interrupted part way through.
+    let position = 15;
+    let completed: HashSet<&Target> = probes[..position].iter().collect();
+    Checkpoint {
+        position: position as u64,
+        results: results
+            .iter()
+            .filter(|result| completed.contains(&result.target))
+            .cloned()
+            .collect(),
+        ..checkpoint
+    }
+    .save(&path)
+    .unwrap();
+
+    // The seed in the checkpoint wins over the one in the config.
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let resumed = xdpscan::resume_results_with(
+        network,
+        src_config(),
+        ScanConfig {
+            seed: 8,
+            checkpoint_path: Some(path.clone()),
+            ..scan_config()
+        },
+        targets(&hosts(40), &[80]),
+    )
+    .unwrap();
+
+    assert_eq!(log.probes(), probes[position..].to_vec());
+    // Replies to the probes sent again came in at other times, those checkpointed are kept.
+    let states = |results: &[ScanResult]| -> Vec<(Target, PortState)> {
+        results
+            .iter()
+            .map(|result| (result.target.clone(), result.state))
+            .collect()
+    };
+    assert_eq!(states(&resumed), states(&results));
+    for (resumed, result) in resumed.iter().zip(&results) {
+        if completed.contains(&result.target) {
+            assert_eq!(resumed, result);
+        }
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn settled_position_lags_by_wait_time() {
+    let wait_time = Duration::from_millis(100);
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    let mut settled = SettledPosition::new(5, wait_time);
+
+    // Nothing sent has had `wait_time` yet, so the scan has settled where it started.
+    assert_eq!(settled.record(at(0), 10), 5);
+    assert_eq!(settled.record(at(50), 20), 5);
+    assert_eq!(settled.record(at(99), 25), 5);
+    // Probes up to 10 were all sent at least `wait_time` ago, those after 10 were not.
+    assert_eq!(settled.record(at(100), 30), 10);
+    assert_eq!(settled.record(at(160), 30), 20);
+    assert_eq!(settled.record(at(1000), 30), 30);
+    assert_eq!(settled.record(at(1000), 40), 30);
+}
+
+#[test]
+fn resumes_a_scan_killed_part_way() {
+    let path = checkpoint_path("killed");
+    let config = |path: &PathBuf| ScanConfig {
+        wait_time: Duration::from_millis(100),
+        checkpoint_path: Some(path.clone()),
+        checkpoint_interval: Duration::from_millis(10),
+        ..scan_config()
+    };
+    // Several batches of probes, so that checkpoints are written between them.
+    let targets: Vec<Target> = (80..88)
+        .flat_map(|port| targets(&hosts(40), &[port]))
+        .collect();
+    let answering: HashSet<Target> = xdpscan::scan_results_with(
+        sim_network(40, 3),
+        src_config(),
+        scan_config(),
+        targets.clone(),
+    )
+    .into_iter()
+    .map(|result| result.target)
+    .collect();
+
+    // Streams the targets slowly, and keeps the first checkpoint written part way through as
+    // the one a scan killed at that point would have left.
+    let done = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let (path, done, len) = (path.clone(), done.clone(), targets.len() as u64);
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                if let Ok(checkpoint) = Checkpoint::load(&path) {
+                    if checkpoint.position > 0 && checkpoint.position < len {
+                        return Some(checkpoint);
+                    }
+                }
+                thread::sleep(Duration::from_millis(2));
+            }
+            None
+        })
+    };
+    let slow_targets = targets.clone().into_iter().inspect(|_| {
+        thread::sleep(Duration::from_millis(1));
+    });
+    let results = xdpscan::scan_source_with(
+        sim_network(40, 3),
+        src_config(),
+        config(&path),
+        slow_targets,
+    )
+    .unwrap();
+    done.store(true, Ordering::Relaxed);
+    let killed = watcher
+        .join()
+        .unwrap()
+        .expect("no checkpoint part way through");
+    let position = killed.position as usize;
+
+    // Every probe before the saved position had time to be answered.
+    let answered: HashSet<&Target> = killed.results.iter().map(|result| &result.target).collect();
+    for target in &targets[..position] {
+        assert_eq!(answered.contains(target), answering.contains(target));
+    }
+
+    let killed_path = checkpoint_path("killed-resume");
+    killed.save(&killed_path).unwrap();
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let resumed = xdpscan::resume_source_with(
+        network,
+        src_config(),
+        config(&killed_path),
+        targets.clone().into_iter(),
+    )
+    .unwrap();
+
+    assert_eq!(log.probes(), targets[position..].to_vec());
+    let states = |results: &[ScanResult]| -> HashSet<(Target, PortState)> {
+        results
+            .iter()
+            .map(|result| (result.target.clone(), result.state))
+            .collect()
+    };
+    assert_eq!(states(&resumed), states(&results));
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&killed_path).unwrap();
+}
+
+#[test]
+fn resume_rejects_checkpoint_of_another_scan() {
+    let path = checkpoint_path("mismatch");
+    Checkpoint {
+        scan_type: ScanType::Syn,
+        seed: 0,
+        shard: Shard::default(),
+        position: 0,
+        config_hash: config_hash(&src_config(), &targets(&hosts(40), &[80])),
+        results: vec![],
+    }
+    .save(&path)
+    .unwrap();
+
+    let mut other_targets = targets(&hosts(40), &[80]);
+    other_targets.pop();
+    let err = xdpscan::resume_with(
+        sim_network(40, 3),
+        src_config(),
+        ScanConfig {
+            checkpoint_path: Some(path.clone()),
+            ..scan_config()
+        },
+        other_targets,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    fs::remove_file(&path).unwrap();
+}
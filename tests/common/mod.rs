@@ -0,0 +1,60 @@
+//! Fixtures shared by the integration tests, each of which uses only some of them.
+#![allow(dead_code)]
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork};
+use xdpscan::{ScanConfig, SrcConfig, SrcSelection, Target};
+
+/// Sources of a scanner at 192.168.69.1, sending from a single port.
+pub fn src_config() -> SrcConfig {
+    SrcConfig {
+        src_mac: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        dst_mac: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        vlan_tags: vec![],
+        src_ips: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1))],
+        src_ports: 4321..=4321,
+        src_selection: SrcSelection::RoundRobin,
+    }
+}
+
+/// The default scan config, with delays short enough for simulated networks.
+pub fn scan_config() -> ScanConfig {
+    ScanConfig {
+        startup_delay: Duration::from_millis(10),
+        wait_time: Duration::from_millis(200),
+        ..ScanConfig::default()
+    }
+}
+
+/// Host `last_octet` of 10.0.0.0/24, where the simulated hosts are.
+pub fn host(last_octet: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet))
+}
+
+/// The first `count` hosts of 10.0.0.0/24.
+pub fn hosts(count: u8) -> Vec<IpAddr> {
+    (1..=count).map(host).collect()
+}
+
+/// Every port of `ports` on every host of `hosts`, host by host.
+pub fn targets(hosts: &[IpAddr], ports: &[u16]) -> Vec<Target> {
+    let mut targets = vec![];
+    for &ip in hosts {
+        for &port in ports {
+            targets.push(Target { ip, port });
+        }
+    }
+    targets
+}
+
+/// A simulated network of every `step`th of the first `count` hosts of 10.0.0.0/24, starting
+/// with the first, with port 80 open.
+pub fn sim_network(count: u8, step: usize) -> SimNetwork {
+    let mut network = SimNetwork::new(SimConfig::default());
+    for i in (1..=count).step_by(step) {
+        network.add_host(SimHost::new(host(i)).open(80));
+    }
+    network
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{host, scan_config, src_config};
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork};
+use xdpscan::daemon::{Change, Daemon};
+use xdpscan::{PortState, ScanResult, Target};
+
+fn target(last_octet: u8, port: u16) -> Target {
+    Target {
+        ip: host(last_octet),
+        port,
+    }
+}
+
+fn states(results: &[ScanResult]) -> Vec<(Target, PortState)> {
+    results
+        .iter()
+        .map(|result| (result.target.clone(), result.state))
+        .collect()
+}
+
+#[test]
+fn rescans_over_the_same_backend() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(host(1)).open(22));
+    network.add_host(SimHost::new(host(2)).open(443));
+    let hosts = network.hosts();
+    let log = network.log();
+    let targets = vec![target(1, 22), target(1, 80), target(2, 443), target(3, 80)];
+    let mut daemon = Daemon::new(network, src_config(), scan_config(), targets.clone());
+
+    let first = daemon.run_once();
+    assert_eq!(first.number, 1);
+    assert_eq!(first.changes, None);
+    assert_eq!(
+        states(&first.results),
+        vec![
+            (target(1, 22), PortState::Open),
+            (target(1, 80), PortState::Closed),
+            (target(2, 443), PortState::Open),
+        ]
+    );
+
+    hosts.add(SimHost::new(host(1)).open(80));
+    hosts.remove(host(2));
+    let second = daemon.run_once();
+    assert_eq!(second.number, 2);
+    let changes = second.changes.unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change {
+                target: target(1, 22),
+                before: Some(PortState::Open),
+                after: Some(PortState::Closed),
+            },
+            Change {
+                target: target(1, 80),
+                before: Some(PortState::Closed),
+                after: Some(PortState::Open),
+            },
+            Change {
+                target: target(2, 443),
+                before: Some(PortState::Open),
+                after: None,
+            },
+        ]
+    );
+    assert_eq!(changes[2].to_string(), "10.0.0.2:443 open -> unanswered");
+    // Both runs went out over the one network.
+    assert_eq!(log.probes().len(), 2 * targets.len());
+}
+
+#[test]
+fn runs_on_a_schedule() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(host(1)).open(22));
+    let targets = vec![target(1, 22), target(1, 80)];
+    let mut daemon = Daemon::new(network, src_config(), scan_config(), targets);
+
+    let interval = Duration::from_millis(400);
+    let mut runs = vec![];
+    daemon.run(interval, |run| {
+        runs.push(run);
+        runs.len() < 3
+    });
+
+    assert_eq!(runs.len(), 3);
+    for (i, pair) in runs.windows(2).enumerate() {
+        assert_eq!(pair[1].number, i as u64 + 2);
+        let gap = pair[1].started.duration_since(pair[0].started);
+        assert!(gap >= interval, "{:?}", gap);
+        // Nothing changed on the network.
+        assert_eq!(pair[1].changes, Some(vec![]));
+        assert_eq!(states(&pair[1].results), states(&runs[0].results));
+    }
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::src_config;
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork};
+use xdpscan::fingerprint::{OsGuess, SignatureDb};
+use xdpscan::packet::{flags, ReplyHeaders, TcpOption};
+use xdpscan::reply::ReplyInfo;
+use xdpscan::{PortState, ScanConfig, Target};
+
+const DB: &str = "
+; Requests are not replies, so their signatures are skipped.
+[tcp:request]
+label = s:unix:Linux:3.11 and newer
+sig   = *:64:0:*:mss*20,10:mss,sok,ts,nop,ws:df,id+:0
+
+[tcp:response]
+label = s:unix:Linux:3.x
+sys   = Linux
+sig   = *:64:0:*:mss*10,7:mss,sok,ts,nop,ws:df:0
+
+label = s:win:Windows:7 or 8
+sig   = *:128:0:*:8192,8:mss,nop,ws,sok,ts:df,id+:0
+
+label = g:unix:Linux:2.4-2.6
+sig   = *:64:0:*:*,*:mss,sok,ts,nop,ws:df:0
+sig   = *:64:0:*:65535,0:::0
+";
+
+fn linux_syn_ack() -> ReplyHeaders {
+    ReplyHeaders {
+        ip_version: 4,
+        ttl: 57,
+        ip_id: Some(0),
+        dont_fragment: true,
+        window: 14600,
+        flags: flags::SYN | flags::ACK,
+        tcp_options: vec![
+            TcpOption::MaxSegmentSize(1460),
+            TcpOption::SackPermitted,
+            TcpOption::Timestamp,
+            TcpOption::Nop,
+            TcpOption::WindowScale(7),
+        ],
+        timestamps: Some((1000, 42)),
+    }
+}
+
+fn label(guess: Option<OsGuess>) -> Option<(String, bool)> {
+    guess.map(|guess| (guess.label.to_string(), guess.fuzzy))
+}
+
+#[test]
+fn guesses_the_os_of_syn_acks() {
+    let db: SignatureDb = DB.parse().unwrap();
+    assert_eq!(db.len(), 4);
+
+    let linux = linux_syn_ack();
+    assert_eq!(
+        label(db.classify(&linux)),
+        Some(("s:unix:Linux:3.x".to_string(), false))
+    );
+
+    // Another window only matches the generic signature.
+    let generic = ReplyHeaders {
+        window: 5840,
+        ..linux.clone()
+    };
+    assert_eq!(
+        label(db.classify(&generic)),
+        Some(("g:unix:Linux:2.4-2.6".to_string(), false))
+    );
+
+    // Windows' initial TTL is 128, and its options come in another order.
+    let windows = ReplyHeaders {
+        ttl: 113,
+        ip_id: Some(0x1234),
+        window: 8192,
+        tcp_options: vec![
+            TcpOption::MaxSegmentSize(1440),
+            TcpOption::Nop,
+            TcpOption::WindowScale(8),
+            TcpOption::SackPermitted,
+            TcpOption::Timestamp,
+        ],
+        ..linux.clone()
+    };
+    assert_eq!(
+        label(db.classify(&windows)),
+        Some(("s:win:Windows:7 or 8".to_string(), false))
+    );
+
+    // Without the don't fragment bit, quirks only match fuzzily.
+    let fragmentable = ReplyHeaders {
+        dont_fragment: false,
+        ip_id: Some(7),
+        ..linux.clone()
+    };
+    assert_eq!(
+        label(db.classify(&fragmentable)),
+        Some(("s:unix:Linux:3.x".to_string(), true))
+    );
+
+    // Too far from any initial TTL.
+    let distant = ReplyHeaders {
+        ttl: 20,
+        ..linux.clone()
+    };
+    assert_eq!(db.classify(&distant), None);
+
+    // Only SYN-ACKs are fingerprinted.
+    let rst = ReplyHeaders {
+        flags: flags::RST | flags::ACK,
+        ..linux
+    };
+    assert_eq!(db.classify(&rst), None);
+}
+
+#[test]
+fn matches_the_ip_version_of_signatures() {
+    let db: SignatureDb =
+        "[tcp:response]\nlabel = s:unix:Linux:3.x\nsig = 6:64:0:*:*,7:mss,sok,ts,nop,ws::0\n"
+            .parse()
+            .unwrap();
+    let ipv6 = ReplyHeaders {
+        ip_version: 6,
+        ip_id: None,
+        dont_fragment: false,
+        ..linux_syn_ack()
+    };
+    assert_eq!(
+        label(db.classify(&ipv6)),
+        Some(("s:unix:Linux:3.x".to_string(), false))
+    );
+    assert_eq!(db.classify(&linux_syn_ack()), None);
+}
+
+#[test]
+fn reports_the_line_of_invalid_signatures() {
+    let db = "[tcp:response]\nlabel = s:unix:Linux:3.x\nsig = *:64:0:*:mss*10:mss:df:0\n";
+    assert_eq!(
+        db.parse::<SignatureDb>().unwrap_err(),
+        "line 3: invalid signature sig = *:64:0:*:mss*10:mss:df:0"
+    );
+    assert!("[tcp:response]\nsig = *:64:0:*:*,*::df:0\n"
+        .parse::<SignatureDb>()
+        .is_err());
+    assert!("[tcp:response]\nlabel = x:unix:Linux:3.x\n"
+        .parse::<SignatureDb>()
+        .is_err());
+}
+
+#[test]
+fn open_ports_carry_an_os_guess() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    let host = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    network.add_host(SimHost::new(host).open(80));
+    let src_config = src_config();
+    let scan_config = ScanConfig {
+        startup_delay: Duration::from_millis(10),
+        wait_time: Duration::from_millis(200),
+        fingerprints: Some(Arc::new(DB.parse().unwrap())),
+        ..ScanConfig::default()
+    };
+    let targets = vec![Target { ip: host, port: 22 }, Target { ip: host, port: 80 }];
+
+    let results = xdpscan::scan_results_with(network, src_config, scan_config, targets);
+    assert_eq!(results.len(), 2);
+    for result in results {
+        let reply = result.reply.unwrap();
+        if result.state == PortState::Open {
+            let os = reply.os.clone().unwrap();
+            assert_eq!(os.label.to_string(), "g:unix:Linux:2.4-2.6");
+        } else {
+            assert_eq!(reply.os, None);
+        }
+        assert_eq!(reply.to_string().parse::<ReplyInfo>(), Ok(reply));
+    }
+}
+
+#[test]
+fn escapes_os_labels_in_reply_info() {
+    let written = "ip=4 ttl=64 df=1 win=29200 flags=0x12 recv=1.500000000 \
+                   os=s:win:Windows:7%20or%208%25_sp1 fuzzy=1";
+    let reply: ReplyInfo = written.parse().unwrap();
+    let os = reply.os.clone().unwrap();
+    assert_eq!(os.label.to_string(), "s:win:Windows:7 or 8%_sp1");
+    assert!(os.fuzzy);
+    assert_eq!(reply.to_string(), written);
+    assert!(
+        "ip=4 ttl=64 df=1 win=0 flags=0x12 recv=1.500000000 os=s:win:Windows:7%2"
+            .parse::<ReplyInfo>()
+            .is_err()
+    );
+}
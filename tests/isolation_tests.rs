@@ -0,0 +1,102 @@
+extern crate utilities;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener};
+use utilities::netns::NetNs;
+
+use xdpscan::isolation::{PortReservation, QueueSteering};
+
+const RESERVED_PORTS: &str = "/proc/sys/net/ipv4/ip_local_reserved_ports";
+
+#[test]
+fn reserves_ports_until_dropped() -> io::Result<()> {
+    // The reserved ports sysctl is per network namespace, so this leaves the host alone.
+    let netns = NetNs::new()?;
+    let _netns = netns.enter()?;
+    let before = fs::read_to_string(RESERVED_PORTS)?;
+
+    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let reservation = PortReservation::new(&[ip], 40000..=40002, true)?;
+
+    for port in 40000..=40002 {
+        let err = TcpListener::bind((ip, port)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+    assert!(TcpListener::bind((ip, 40003)).is_ok());
+    assert_eq!(fs::read_to_string(RESERVED_PORTS)?.trim(), "40000-40002");
+
+    drop(reservation);
+    assert!(TcpListener::bind((ip, 40000)).is_ok());
+    assert_eq!(fs::read_to_string(RESERVED_PORTS)?, before);
+    Ok(())
+}
+
+#[test]
+fn reserves_ipv6_ports_without_touching_sysctl() -> io::Result<()> {
+    let netns = NetNs::new()?;
+    let _netns = netns.enter()?;
+    let before = fs::read_to_string(RESERVED_PORTS)?;
+
+    let ip = IpAddr::V6(Ipv6Addr::LOCALHOST);
+    let _reservation = PortReservation::new(&[ip], 40000..=40000, false)?;
+
+    let err = TcpListener::bind((ip, 40000)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert_eq!(fs::read_to_string(RESERVED_PORTS)?, before);
+    Ok(())
+}
+
+#[test]
+fn reserves_wide_ranges_through_sysctl_only() -> io::Result<()> {
+    let netns = NetNs::new()?;
+    let _netns = netns.enter()?;
+
+    let ips = [
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+    ];
+    // More placeholder sockets than allowed, and no sysctl to fall back on.
+    let err = PortReservation::new(&ips, 40000..=40199, false)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let _reservation = PortReservation::new(&ips, 40000..=40199, true)?;
+    assert!(TcpListener::bind((ips[0], 40000)).is_ok());
+    assert_eq!(fs::read_to_string(RESERVED_PORTS)?.trim(), "40000-40199");
+    Ok(())
+}
+
+#[test]
+fn steers_port_ranges_with_masked_rules() {
+    let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let rules = |ports| QueueSteering::rules("eth0", ip, &ports, 3);
+    let ethtool = |ports: &str| -> Vec<String> {
+        format!(
+            "-N eth0 flow-type tcp4 dst-ip 192.0.2.1 dst-port {} action 3",
+            ports
+        )
+        .split(' ')
+        .map(String::from)
+        .collect()
+    };
+
+    assert_eq!(rules(40000..=40000).unwrap(), vec![ethtool("40000")]);
+    assert_eq!(
+        rules(40960..=45055).unwrap(),
+        vec![ethtool("40960 m 0xfff")]
+    );
+    assert_eq!(
+        rules(40001..=40007).unwrap(),
+        vec![
+            ethtool("40001"),
+            ethtool("40002 m 0x1"),
+            ethtool("40004 m 0x3")
+        ]
+    );
+    let ipv6 = QueueSteering::rules("eth0", IpAddr::V6(Ipv6Addr::LOCALHOST), &(80..=80), 0);
+    assert_eq!(ipv6.unwrap()[0][3], "tcp6");
+
+    let err = rules(1..=65535).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
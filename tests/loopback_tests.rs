@@ -0,0 +1,126 @@
+mod common;
+
+use etherparse::PacketBuilder;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::src_config;
+use xdpscan::backend::{loopback::LoopbackIo, PacketIo, PacketRx, PacketTx};
+use xdpscan::{ScanConfig, Target};
+
+fn generate_synack_frame_resp(frame: &[u8]) -> Option<Vec<u8>> {
+    synack(frame, |port| port, |seq| seq.wrapping_add(1))
+}
+
+// Answers probes to port 80 on a different port than the probe was sent from.
+fn generate_misdirected_synack_frame_resp(frame: &[u8]) -> Option<Vec<u8>> {
+    synack(frame, |port| port + 1, |seq| seq.wrapping_add(1))
+}
+
+// Answers probes to port 80 without acknowledging the SYN, as a spoofed SYN-ACK would.
+fn generate_unacknowledging_synack_frame_resp(frame: &[u8]) -> Option<Vec<u8>> {
+    synack(frame, |port| port, |seq| seq)
+}
+
+/// Answers a probe with a SYN-ACK, sent to `misdirect` of its source port and acknowledging
+/// `acknowledge` of its sequence number if it was sent to port 80.
+fn synack(frame: &[u8], misdirect: fn(u16) -> u16, acknowledge: fn(u32) -> u32) -> Option<Vec<u8>> {
+    let value = etherparse::PacketHeaders::from_ethernet_slice(frame).ok()?;
+    let link_layer = value.link?;
+    let ipv4 = match value.ip? {
+        etherparse::IpHeader::Version4(ipv4) => ipv4,
+        _ => return None,
+    };
+    let tcp = value.transport?.tcp()?;
+    let (dst_port, ack) = if tcp.destination_port == 80 {
+        (misdirect(tcp.source_port), acknowledge(tcp.sequence_number))
+    } else {
+        (tcp.source_port, tcp.sequence_number.wrapping_add(1))
+    };
+
+    let builder = PacketBuilder::ethernet2(link_layer.destination, link_layer.source)
+        .ipv4(ipv4.destination, ipv4.source, 64)
+        .tcp(tcp.destination_port, dst_port, 0, 1024)
+        .syn()
+        .ack(ack);
+    let mut result = Vec::<u8>::with_capacity(builder.size(0));
+    builder.write(&mut result, &[]).unwrap();
+    Some(result)
+}
+
+fn respond(io: LoopbackIo, done: Arc<AtomicBool>, reply: fn(&[u8]) -> Option<Vec<u8>>) {
+    let (mut tx, mut rx) = io.split();
+    while !done.load(Ordering::Relaxed) {
+        let mut resp_frames = vec![];
+        rx.recv_batch(100, &mut |frame| {
+            resp_frames.extend(reply(frame));
+        })
+        .unwrap();
+
+        let mut resp_frames = resp_frames.into_iter();
+        while resp_frames.len() > 0 {
+            tx.send_batch(resp_frames.len(), &mut |buf| match resp_frames.next() {
+                Some(resp_frame) => {
+                    buf[..resp_frame.len()].copy_from_slice(&resp_frame);
+                    resp_frame.len()
+                }
+                None => 0,
+            })
+            .unwrap();
+        }
+    }
+}
+
+fn scan(reply: fn(&[u8]) -> Option<Vec<u8>>, targets: Vec<Target>) -> Vec<Target> {
+    let (scanner_io, responder_io) = LoopbackIo::pair();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let responder_done = done.clone();
+    let responder_handle = thread::spawn(move || respond(responder_io, responder_done, reply));
+
+    let src_config = src_config();
+    let scan_config = ScanConfig {
+        startup_delay: Duration::from_millis(100),
+        wait_time: Duration::from_millis(500),
+        ..ScanConfig::default()
+    };
+    let responders = xdpscan::scan_with(scanner_io, src_config, scan_config, targets);
+
+    done.store(true, Ordering::Relaxed);
+    responder_handle.join().unwrap();
+    responders
+}
+
+fn targets() -> Vec<Target> {
+    vec![
+        Target {
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2)),
+            port: 1234,
+        },
+        Target {
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 69, 3)),
+            port: 80,
+        },
+    ]
+}
+
+#[test]
+fn loopback_test() {
+    let responders = scan(generate_synack_frame_resp, targets());
+    assert_eq!(responders, targets());
+}
+
+#[test]
+fn ignores_replies_to_other_source_ports() {
+    let responders = scan(generate_misdirected_synack_frame_resp, targets());
+    assert_eq!(responders, targets()[..1]);
+}
+
+#[test]
+fn ignores_replies_that_do_not_acknowledge_the_probe() {
+    let responders = scan(generate_unacknowledging_synack_frame_resp, targets());
+    assert_eq!(responders, targets()[..1]);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::src_config;
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork};
+use xdpscan::metrics::Metrics;
+use xdpscan::{ScanConfig, Target};
+
+/// The value of the sample of `metric`, labels included, in `text`.
+fn sample<'a>(text: &'a str, metric: &str) -> Option<&'a str> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(metric)?.strip_prefix(' '))
+}
+
+#[test]
+fn counts_probes_replies_and_progress() {
+    let host = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let metrics = Arc::new(Metrics::new());
+    let scan_config = ScanConfig {
+        startup_delay: Duration::from_millis(10),
+        wait_time: Duration::from_millis(200),
+        reset_open: true,
+        metrics: Some(metrics.clone()),
+        ..ScanConfig::default()
+    };
+    let targets = vec![
+        Target { ip: host, port: 22 },
+        Target { ip: host, port: 80 },
+        Target {
+            ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            port: 80,
+        },
+    ];
+    let network = || {
+        let mut network = SimNetwork::new(SimConfig::default());
+        network.add_host(SimHost::new(host).open(80));
+        network
+    };
+
+    xdpscan::scan_with(
+        network(),
+        src_config(),
+        scan_config.clone(),
+        targets.clone(),
+    );
+    let text = metrics.render();
+    assert_eq!(sample(&text, "xdpscan_probes_sent_total"), Some("3"));
+    assert_eq!(sample(&text, "xdpscan_resets_sent_total"), Some("1"));
+    assert_eq!(sample(&text, "xdpscan_frames_received_total"), Some("2"));
+    let replies = |state: &str| format!("xdpscan_replies_total{{state=\"{}\"}}", state);
+    assert_eq!(sample(&text, &replies("open")), Some("1"));
+    assert_eq!(sample(&text, &replies("closed")), Some("1"));
+    assert_eq!(sample(&text, &replies("filtered")), Some("0"));
+    assert_eq!(sample(&text, "xdpscan_scan_targets"), Some("3"));
+    assert_eq!(sample(&text, "xdpscan_scan_position"), Some("3"));
+    assert_eq!(sample(&text, "xdpscan_tx_frames_in_flight"), Some("0"));
+    // Only AF_XDP sockets have drop counters.
+    assert!(!text.contains("xdpscan_xdp_"));
+    assert!(text.contains("# TYPE xdpscan_probes_sent_total counter\n"));
+    assert!(text.contains("# TYPE xdpscan_probe_rate gauge\n"));
+
+    // Counters add up across scans, while the progress is that of the last one.
+    xdpscan::scan_with(network(), src_config(), scan_config, targets[..2].to_vec());
+    let text = metrics.render();
+    assert_eq!(sample(&text, "xdpscan_scans_total"), Some("2"));
+    assert_eq!(sample(&text, "xdpscan_probes_sent_total"), Some("5"));
+    assert_eq!(sample(&text, &replies("open")), Some("2"));
+    assert_eq!(sample(&text, "xdpscan_scan_targets"), Some("2"));
+    assert_eq!(sample(&text, "xdpscan_scan_position"), Some("2"));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn serves_metrics_over_http() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use xdpscan::metrics::MetricsServer;
+
+    let server = MetricsServer::serve("127.0.0.1:0", Arc::new(Metrics::new())).unwrap();
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = get("/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert_eq!(sample(body, "xdpscan_probes_sent_total"), Some("0"));
+    assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let addr = server.local_addr();
+    drop(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
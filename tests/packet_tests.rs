@@ -0,0 +1,513 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::src_config;
+use xdpscan::backend::{loopback::LoopbackIo, PacketIo, PacketRx};
+use xdpscan::packet::{
+    self, flags, Frame, FrameTemplate, IcmpError, IcmpErrorKind, IpIdStrategy, OsPreset,
+    ProbeOptions, QuotedPacket, ReplyHeaders, TcpOption, TcpSegment, UdpFrame, VlanTag,
+};
+use xdpscan::{ScanConfig, Target};
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn tcp_checksum(pseudo_header: &[u8], segment: &[u8]) -> u16 {
+    checksum(&[pseudo_header, segment].concat())
+}
+
+fn frame(src_ip: IpAddr, dst_ip: IpAddr, options: &[TcpOption]) -> Frame<'_> {
+    Frame {
+        src_mac: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        dst_mac: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        vlan_tags: &[],
+        src_ip,
+        dst_ip,
+        ttl: 64,
+        ip_id: 0x1234,
+        src_port: 4321,
+        dst_port: 443,
+        seq: 0xdead_beef,
+        ack: 0,
+        flags: flags::SYN,
+        window: 64240,
+        options,
+        timestamp: 0x0102_0304,
+    }
+}
+
+#[test]
+fn writes_ipv4_syn_with_options() {
+    let src_ip = Ipv4Addr::new(192, 168, 69, 1);
+    let dst_ip = Ipv4Addr::new(192, 168, 69, 2);
+    let options = OsPreset::Linux.probe_options().tcp_options;
+    let frame = frame(IpAddr::V4(src_ip), IpAddr::V4(dst_ip), &options);
+
+    let mut buf = [0xff; 128];
+    let len = frame.write(&mut buf);
+    assert_eq!(len, frame.size());
+    assert_eq!(len, 14 + 20 + 40);
+    let buf = &buf[..len];
+
+    let headers = etherparse::PacketHeaders::from_ethernet_slice(buf).unwrap();
+    let ipv4 = match headers.ip.unwrap() {
+        etherparse::IpHeader::Version4(ipv4) => ipv4,
+        _ => panic!("not IPv4"),
+    };
+    assert_eq!(ipv4.time_to_live, 64);
+    assert_eq!(ipv4.identification, 0x1234);
+    assert_eq!(ipv4.source, src_ip.octets());
+    assert_eq!(ipv4.destination, dst_ip.octets());
+    assert_eq!(checksum(&buf[14..34]), 0);
+
+    let tcp = headers.transport.unwrap().tcp().unwrap();
+    assert_eq!((tcp.source_port, tcp.destination_port), (4321, 443));
+    assert_eq!(tcp.sequence_number, 0xdead_beef);
+    assert!(tcp.syn && !tcp.ack && !tcp.rst);
+    assert_eq!(tcp.window_size, 64240);
+
+    // MSS 1460, SACK permitted, timestamps, NOP, window scale 7.
+    assert_eq!(
+        buf[54..74],
+        [2, 4, 0x05, 0xb4, 4, 2, 8, 10, 1, 2, 3, 4, 0, 0, 0, 0, 1, 3, 3, 7]
+    );
+
+    let mut pseudo_header = vec![];
+    pseudo_header.extend_from_slice(&src_ip.octets());
+    pseudo_header.extend_from_slice(&dst_ip.octets());
+    pseudo_header.extend_from_slice(&[0, 6, 0, 40]);
+    assert_eq!(tcp_checksum(&pseudo_header, &buf[34..]), 0);
+}
+
+#[test]
+fn writes_ipv6_syn_and_maps_ipv4_addresses() {
+    let src_ip = Ipv4Addr::new(192, 168, 69, 1);
+    let dst_ip = Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 2);
+    let options = OsPreset::MacOs.probe_options().tcp_options;
+    let frame = frame(IpAddr::V4(src_ip), IpAddr::V6(dst_ip), &options);
+
+    let mut buf = [0; 128];
+    let len = frame.write(&mut buf);
+    // 22 bytes of options padded to 24.
+    assert_eq!(len, 14 + 40 + 44);
+    let buf = &buf[..len];
+
+    let headers = etherparse::PacketHeaders::from_ethernet_slice(buf).unwrap();
+    let ipv6 = match headers.ip.unwrap() {
+        etherparse::IpHeader::Version6(ipv6) => ipv6,
+        _ => panic!("not IPv6"),
+    };
+    assert_eq!(ipv6.hop_limit, 64);
+    assert_eq!(ipv6.source, src_ip.to_ipv6_mapped().octets());
+    assert_eq!(ipv6.destination, dst_ip.octets());
+    assert_eq!(ipv6.payload_length, 44);
+
+    let mut pseudo_header = vec![];
+    pseudo_header.extend_from_slice(&src_ip.to_ipv6_mapped().octets());
+    pseudo_header.extend_from_slice(&dst_ip.octets());
+    pseudo_header.extend_from_slice(&[0, 0, 0, 44, 0, 0, 0, 6]);
+    assert_eq!(tcp_checksum(&pseudo_header, &buf[54..]), 0);
+    // Padded with end of option list.
+    assert_eq!(buf[96..98], [0, 0]);
+}
+
+#[test]
+fn parses_and_prints_tcp_options() {
+    for preset in [OsPreset::Linux, OsPreset::Windows, OsPreset::MacOs] {
+        for option in preset.probe_options().tcp_options {
+            assert_eq!(option.to_string().parse::<TcpOption>(), Ok(option));
+        }
+    }
+    assert!("ws=15".parse::<TcpOption>().is_err());
+    assert!("mss=".parse::<TcpOption>().is_err());
+    assert!("md5".parse::<TcpOption>().is_err());
+}
+
+#[test]
+fn templates_write_the_same_frames() {
+    let ipv4 = |last_octet| IpAddr::V4(Ipv4Addr::new(192, 168, 69, last_octet));
+    let ipv6 = |last_segment| IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, last_segment));
+    let option_lists = [
+        OsPreset::Linux.probe_options().tcp_options,
+        OsPreset::MacOs.probe_options().tcp_options,
+        // A timestamp at an odd offset.
+        vec![TcpOption::Nop, TcpOption::Timestamp],
+        vec![],
+    ];
+    let addresses = [
+        (ipv4(1), ipv4(2), ipv4(3)),
+        (ipv4(1), ipv6(2), ipv6(0xfffe)),
+        (ipv6(1), ipv6(2), ipv6(3)),
+    ];
+
+    for options in &option_lists {
+        for &(src_ip, dst_ip, other_dst_ip) in &addresses {
+            let template = FrameTemplate::new(&frame(src_ip, dst_ip, options));
+            for (i, &dst_ip) in [dst_ip, other_dst_ip].iter().enumerate() {
+                let frame = Frame {
+                    ip_id: 0xfffe - i as u16,
+                    src_port: 40000 + i as u16,
+                    dst_port: 0xffff - i as u16,
+                    seq: 0xffff_ff00 + i as u32,
+                    ack: 0x8000_0001 * i as u32,
+                    timestamp: 0xfedc_ba98 + i as u32,
+                    ..frame(src_ip, dst_ip, options)
+                };
+                let mut expected = [0; 128];
+                let len = frame.write(&mut expected);
+                let mut buf = [0xff; 128];
+                assert_eq!(template.write(&mut buf, &frame), len);
+                assert_eq!(buf[..len], expected[..len], "{:?}", frame);
+            }
+        }
+    }
+}
+
+fn segment(frame: &Frame) -> TcpSegment {
+    TcpSegment {
+        src_ip: frame.src_ip,
+        dst_ip: frame.dst_ip,
+        src_port: frame.src_port,
+        dst_port: frame.dst_port,
+        seq: frame.seq,
+        ack: frame.ack,
+        flags: frame.flags,
+        window: frame.window,
+    }
+}
+
+#[test]
+fn parses_tcp_segments_in_place() {
+    let options = OsPreset::Linux.probe_options().tcp_options;
+    let src_ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1));
+    let dst_ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 2));
+    let ipv6_frame = Frame {
+        flags: flags::SYN | flags::ACK,
+        ack: 0x1234_5678,
+        ..frame(src_ip, dst_ip, &options)
+    };
+    let mut buf = [0; 128];
+    let len = ipv6_frame.write(&mut buf);
+    assert_eq!(TcpSegment::parse(&buf[..len]), Some(segment(&ipv6_frame)));
+
+    let src_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2));
+    let dst_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let ipv4_frame = Frame {
+        flags: flags::RST | flags::ACK,
+        ..frame(src_ip, dst_ip, &[])
+    };
+    let len = ipv4_frame.write(&mut buf);
+    let ipv4 = buf[..len].to_vec();
+    assert_eq!(TcpSegment::parse(&ipv4), Some(segment(&ipv4_frame)));
+
+    // Padded to the minimum Ethernet frame size.
+    let mut padded = ipv4.clone();
+    padded.resize(60, 0);
+    assert_eq!(TcpSegment::parse(&padded), Some(segment(&ipv4_frame)));
+
+    // Tagged with an 802.1ad and an 802.1Q tag.
+    let tagged = [
+        &ipv4[..12],
+        &[0x88, 0xa8, 0, 10, 0x81, 0, 0, 20],
+        &ipv4[12..],
+    ]
+    .concat();
+    assert_eq!(TcpSegment::parse(&tagged), Some(segment(&ipv4_frame)));
+
+    for truncated_len in [0, 13, 33, len - 1] {
+        assert_eq!(TcpSegment::parse(&ipv4[..truncated_len]), None);
+    }
+    let mut udp = ipv4.clone();
+    udp[14 + 9] = 17;
+    assert_eq!(TcpSegment::parse(&udp), None);
+    let mut fragment = ipv4.clone();
+    fragment[14 + 7] = 8;
+    assert_eq!(TcpSegment::parse(&fragment), None);
+}
+
+#[test]
+fn writes_and_matches_vlan_tags() {
+    let tags = [VlanTag::dot1ad(100), VlanTag::dot1q(200)];
+    let src_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let dst_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2));
+    let tagged_frame = Frame {
+        vlan_tags: &tags,
+        ..frame(src_ip, dst_ip, &[])
+    };
+
+    let mut buf = [0; 128];
+    let len = tagged_frame.write(&mut buf);
+    assert_eq!(len, 14 + 8 + 20 + 20);
+    let tagged = buf[..len].to_vec();
+    assert_eq!(
+        tagged[12..22],
+        [0x88, 0xa8, 0, 100, 0x81, 0, 0, 200, 0x08, 0]
+    );
+    assert_eq!(TcpSegment::parse(&tagged), Some(segment(&tagged_frame)));
+
+    let template = FrameTemplate::new(&tagged_frame);
+    let mut buf = [0; 128];
+    assert_eq!(template.write(&mut buf, &tagged_frame), len);
+    assert_eq!(buf[..len], tagged[..]);
+
+    let len = frame(src_ip, dst_ip, &[]).write(&mut buf);
+    let untagged = &buf[..len];
+    assert!(packet::has_vlan_tags(&tagged, &tags));
+    assert!(packet::has_vlan_tags(untagged, &[]));
+    assert!(!packet::has_vlan_tags(&tagged, &tags[..1]));
+    assert!(!packet::has_vlan_tags(&tagged, &[]));
+    assert!(!packet::has_vlan_tags(untagged, &tags));
+    assert!(!packet::has_vlan_tags(
+        &tagged,
+        &[VlanTag::dot1ad(100), VlanTag::dot1q(201)]
+    ));
+}
+
+#[test]
+fn parses_reply_headers() {
+    let src_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2));
+    let dst_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let options = OsPreset::Linux.probe_options().tcp_options;
+    let syn_ack = Frame {
+        flags: flags::SYN | flags::ACK | flags::ECE,
+        ..frame(src_ip, dst_ip, &options)
+    };
+    let mut buf = [0; 128];
+    let len = syn_ack.write(&mut buf);
+    // A TSecr echoing a probe's TSval.
+    buf[len - 8..len - 4].copy_from_slice(&[0, 0, 0, 42]);
+
+    let headers = ReplyHeaders::parse(&buf[..len]).unwrap();
+    assert_eq!(
+        headers,
+        ReplyHeaders {
+            ip_version: 4,
+            ttl: 64,
+            ip_id: Some(0x1234),
+            dont_fragment: true,
+            window: 64240,
+            flags: flags::SYN | flags::ACK | flags::ECE,
+            tcp_options: options,
+            timestamps: Some((0x0102_0304, 42)),
+        }
+    );
+    assert_eq!(headers.mss(), Some(1460));
+    assert_eq!(headers.window_scale(), Some(7));
+
+    let src_ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 2));
+    let dst_ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1));
+    let rst = Frame {
+        flags: flags::RST | flags::ACK,
+        window: 0,
+        ..frame(src_ip, dst_ip, &[])
+    };
+    let len = rst.write(&mut buf);
+    let headers = ReplyHeaders::parse(&buf[..len]).unwrap();
+    assert_eq!(
+        (
+            headers.ip_version,
+            headers.ttl,
+            headers.ip_id,
+            headers.dont_fragment
+        ),
+        (6, 64, None, false)
+    );
+    assert_eq!(
+        (headers.window, headers.flags),
+        (0, flags::RST | flags::ACK)
+    );
+    assert_eq!((headers.mss(), headers.window_scale()), (None, None));
+    assert_eq!((headers.tcp_options, headers.timestamps), (vec![], None));
+
+    let mut udp = buf[..len].to_vec();
+    udp[14 + 6] = 17;
+    assert_eq!(ReplyHeaders::parse(&udp), None);
+}
+
+#[test]
+fn writes_udp_with_a_fixed_checksum() {
+    let src_ip = Ipv4Addr::new(192, 168, 69, 1);
+    let dst_ip = Ipv4Addr::new(192, 168, 69, 2);
+    let payload = [0xab; 12];
+    let udp = |payload| UdpFrame {
+        src_mac: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        dst_mac: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        vlan_tags: &[],
+        src_ip: IpAddr::V4(src_ip),
+        dst_ip: IpAddr::V4(dst_ip),
+        ttl: 3,
+        ip_id: 0,
+        src_port: 4321,
+        dst_port: 33434,
+        payload,
+    };
+
+    let mut buf = [0; 128];
+    let len = udp(&payload).write(&mut buf);
+    assert_eq!(len, 14 + 20 + 8 + 12);
+    let pseudo_header = [&src_ip.octets()[..], &dst_ip.octets(), &[0, 17, 0, 20]].concat();
+    assert_eq!(tcp_checksum(&pseudo_header, &buf[34..len]), 0);
+    assert_eq!(&buf[42..len], &payload);
+
+    // Payloads of every length get the same checksum, which stays valid.
+    for payload_len in 2..payload.len() {
+        let len = udp(&payload[..payload_len]).write_with_checksum(&mut buf, 0x1234);
+        let udp_len = 8 + payload_len as u8;
+        assert_eq!(len, 14 + 20 + usize::from(udp_len));
+        assert_eq!(&buf[40..42], &[0x12, 0x34]);
+        assert_eq!(&buf[44..len], &payload[2..payload_len]);
+        let pseudo_header = [&pseudo_header[..8], &[0, 17, 0, udp_len]].concat();
+        assert_eq!(tcp_checksum(&pseudo_header, &buf[34..len]), 0);
+    }
+}
+
+/// An ICMPv4 error with `icmp_type` from `router`, about the IPv4 packet in `probe`.
+fn icmp_error(router: Ipv4Addr, icmp_type: u8, code: u8, probe: &[u8]) -> Vec<u8> {
+    let quoted = &probe[14..14 + 20 + 8];
+    let mut frame = probe[..14].to_vec();
+    let mut ip = [0; 20];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(20 + 8 + quoted.len() as u16).to_be_bytes());
+    ip[8] = 250;
+    ip[9] = 1;
+    ip[12..16].copy_from_slice(&router.octets());
+    ip[16..20].copy_from_slice(&probe[26..30]);
+    frame.extend_from_slice(&ip);
+    frame.extend_from_slice(&[icmp_type, code, 0, 0, 0, 0, 0, 0]);
+    frame.extend_from_slice(quoted);
+    frame
+}
+
+#[test]
+fn parses_icmp_errors() {
+    let src_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let dst_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 70, 2));
+    let router = Ipv4Addr::new(10, 0, 0, 1);
+    let mut buf = [0; 128];
+    let len = frame(src_ip, dst_ip, &[]).write(&mut buf);
+    let probe = &buf[..len];
+
+    let quoted = QuotedPacket {
+        protocol: packet::PROTOCOL_TCP,
+        src_ip,
+        dst_ip,
+        src_port: 4321,
+        dst_port: 443,
+        word: 0xdead_beef,
+    };
+    assert_eq!(
+        IcmpError::parse(&icmp_error(router, 11, 0, probe)),
+        Some(IcmpError {
+            src_ip: IpAddr::V4(router),
+            dst_ip: src_ip,
+            kind: IcmpErrorKind::TimeExceeded,
+            quoted,
+        })
+    );
+    let unreachable = IcmpError::parse(&icmp_error(router, 3, 13, probe)).unwrap();
+    assert_eq!(unreachable.kind, IcmpErrorKind::Unreachable(13));
+
+    // Echo replies are not errors, and errors must quote 8 bytes past the IP header.
+    assert_eq!(IcmpError::parse(&icmp_error(router, 0, 0, probe)), None);
+    let mut truncated = icmp_error(router, 11, 0, probe);
+    truncated.truncate(truncated.len() - 1);
+    truncated[14 + 3] -= 1;
+    assert_eq!(IcmpError::parse(&truncated), None);
+    // TCP segments are not ICMP errors.
+    assert_eq!(IcmpError::parse(probe), None);
+}
+
+/// Scans `targets` over a loopback backend and returns the frames the scanner sent.
+fn capture_probes(probe_options: ProbeOptions, targets: Vec<Target>) -> Vec<Vec<u8>> {
+    let (scanner_io, capture_io) = LoopbackIo::pair();
+    let (_capture_tx, mut capture_rx) = capture_io.split();
+
+    let frames = Arc::new(Mutex::new(vec![]));
+    let capture_frames = frames.clone();
+    let probe_count = targets.len();
+    let capture = thread::spawn(move || {
+        while capture_frames.lock().unwrap().len() < probe_count {
+            capture_rx
+                .recv_batch(100, &mut |frame| {
+                    capture_frames.lock().unwrap().push(frame.to_vec())
+                })
+                .unwrap();
+        }
+    });
+
+    let src_config = src_config();
+    let scan_config = ScanConfig {
+        startup_delay: Duration::from_millis(10),
+        wait_time: Duration::from_millis(100),
+        probe_options,
+        ..ScanConfig::default()
+    };
+    xdpscan::scan_with(scanner_io, src_config, scan_config, targets);
+
+    capture.join().unwrap();
+    let frames = frames.lock().unwrap().clone();
+    frames
+}
+
+fn ip_id(frame: &[u8]) -> u16 {
+    u16::from_be_bytes([frame[18], frame[19]])
+}
+
+#[test]
+fn probes_use_configured_options() {
+    let targets: Vec<Target> = (1..=4)
+        .map(|i| Target {
+            ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)),
+            port: 80,
+        })
+        .collect();
+    let probe_options = ProbeOptions {
+        ttl: 128,
+        window: 8192,
+        ip_id: IpIdStrategy::Incrementing,
+        tcp_options: vec![TcpOption::MaxSegmentSize(1400), TcpOption::SackPermitted],
+    };
+    let frames = capture_probes(probe_options, targets);
+    assert_eq!(frames.len(), 4);
+
+    for frame in &frames {
+        let headers = etherparse::PacketHeaders::from_ethernet_slice(frame).unwrap();
+        match headers.ip.unwrap() {
+            etherparse::IpHeader::Version4(ipv4) => assert_eq!(ipv4.time_to_live, 128),
+            _ => panic!("not IPv4"),
+        }
+        let tcp = headers.transport.unwrap().tcp().unwrap();
+        assert_eq!(tcp.window_size, 8192);
+        assert_eq!(frame[54..62], [2, 4, 0x05, 0x78, 4, 2, 0, 0]);
+    }
+    for pair in frames.windows(2) {
+        assert_eq!(ip_id(&pair[1]), ip_id(&pair[0]).wrapping_add(1));
+    }
+}
+
+#[test]
+fn default_probes_are_bare_syns() {
+    let targets = vec![Target {
+        ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        port: 80,
+    }];
+    let frames = capture_probes(ProbeOptions::default(), targets);
+
+    assert_eq!(frames[0].len(), 14 + 20 + 20);
+    assert_eq!(ip_id(&frames[0]), 0);
+    let headers = etherparse::PacketHeaders::from_ethernet_slice(&frames[0]).unwrap();
+    let tcp = headers.transport.unwrap().tcp().unwrap();
+    assert_eq!(tcp.window_size, 4);
+}
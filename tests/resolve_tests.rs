@@ -0,0 +1,78 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use xdpscan::resolve::{self, AddrSelection, Hostnames, NamedResult};
+use xdpscan::{PortState, ScanResult, Target};
+
+fn ips(ips: &[&str]) -> Vec<IpAddr> {
+    ips.iter().map(|ip| ip.parse().unwrap()).collect()
+}
+
+#[test]
+fn selects_all_or_first_addresses_per_family() {
+    let resolved = ips(&[
+        "2001:db8::1",
+        "192.0.2.1",
+        "192.0.2.2",
+        "2001:db8::1",
+        "2001:db8::2",
+        "192.0.2.1",
+    ]);
+    assert_eq!(
+        resolve::select_addrs(&resolved, AddrSelection::All),
+        ips(&["2001:db8::1", "192.0.2.1", "192.0.2.2", "2001:db8::2"])
+    );
+    assert_eq!(
+        resolve::select_addrs(&resolved, AddrSelection::FirstPerFamily),
+        ips(&["2001:db8::1", "192.0.2.1"])
+    );
+    assert_eq!(
+        "first-per-family".parse::<AddrSelection>().unwrap(),
+        AddrSelection::FirstPerFamily
+    );
+}
+
+#[test]
+fn resolves_hostnames_through_the_system_resolver() {
+    let mut hostnames = Hostnames::new();
+    let addrs = hostnames
+        .resolve("localhost", AddrSelection::FirstPerFamily)
+        .unwrap();
+    assert!(!addrs.is_empty());
+    for addr in addrs {
+        assert!(addr.is_loopback());
+        assert_eq!(hostnames.names(addr), ["localhost".to_string()]);
+    }
+    assert!(hostnames
+        .resolve("no-such-host.invalid", AddrSelection::All)
+        .is_err());
+}
+
+#[test]
+fn results_keep_the_hostnames_of_their_address() {
+    let shared = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let unnamed = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+    let mut hostnames = Hostnames::new();
+    hostnames.insert(shared, "example.com");
+    hostnames.insert(shared, "www.example.com");
+    hostnames.insert(shared, "example.com");
+
+    let result = |ip| ScanResult {
+        target: Target { ip, port: 443 },
+        state: PortState::Open,
+        reply: None,
+        hop: None,
+    };
+    assert_eq!(
+        hostnames.name_results(vec![result(shared), result(unnamed)]),
+        vec![
+            NamedResult {
+                result: result(shared),
+                hostnames: vec!["example.com".to_string(), "www.example.com".to_string()],
+            },
+            NamedResult {
+                result: result(unnamed),
+                hostnames: vec![],
+            },
+        ]
+    );
+}
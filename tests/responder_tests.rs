@@ -0,0 +1,121 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use utilities::responder::{respond, PortPolicy, PortReply, ResponderConfig};
+
+use xdpscan::packet::{checksum_add, checksum_fold, flags, Frame, TcpSegment, VlanTag};
+
+const SCANNER_MAC: [u8; 6] = [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a];
+const TARGET_MAC: [u8; 6] = [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31];
+
+fn config(ips: Vec<IpAddr>) -> ResponderConfig {
+    ResponderConfig {
+        mac: TARGET_MAC,
+        ips,
+        policy: PortPolicy::open(&[80]).with(81, PortReply::Drop),
+    }
+}
+
+/// A frame from the scanner to `dst_ip:dst_port` with TCP `flags`.
+fn probe(
+    vlan_tags: &[VlanTag],
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    dst_port: u16,
+    flags: u8,
+) -> Vec<u8> {
+    let frame = Frame {
+        src_mac: SCANNER_MAC,
+        dst_mac: TARGET_MAC,
+        vlan_tags,
+        src_ip,
+        dst_ip,
+        ttl: 64,
+        ip_id: 0x1234,
+        src_port: 4321,
+        dst_port,
+        seq: 0xdead_beef,
+        ack: 0,
+        flags,
+        window: 64240,
+        options: &[],
+        timestamp: 0,
+    };
+    let mut buf = vec![0; frame.size()];
+    frame.write(&mut buf);
+    buf
+}
+
+/// The sum of the TCP pseudo header of a segment of `len` bytes between `src` and `dst`.
+fn pseudo_header_sum(src: IpAddr, dst: IpAddr, len: usize) -> u32 {
+    let octets = |ip: IpAddr| match ip {
+        IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+        IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+    };
+    let sum = checksum_add(checksum_add(0, &octets(src)), &octets(dst));
+    sum + len as u32 + 6
+}
+
+#[test]
+fn answers_syns_to_open_ports_with_syn_acks() {
+    let scanner = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let target = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2));
+    let reply = respond(
+        &config(vec![target]),
+        &probe(&[], scanner, target, 80, flags::SYN),
+    )
+    .unwrap();
+
+    assert_eq!(reply[..6], SCANNER_MAC);
+    assert_eq!(reply[6..12], TARGET_MAC);
+    let segment = TcpSegment::parse(&reply).unwrap();
+    assert_eq!((segment.src_ip, segment.dst_ip), (target, scanner));
+    assert_eq!((segment.src_port, segment.dst_port), (80, 4321));
+    assert_eq!(segment.flags, flags::SYN | flags::ACK);
+    assert_eq!(segment.ack, 0xdead_bef0);
+
+    let header_len = usize::from(reply[14] & 0x0f) * 4;
+    assert_eq!(
+        checksum_fold(checksum_add(0, &reply[14..14 + header_len])),
+        0
+    );
+    let tcp = &reply[14 + header_len..];
+    let sum = pseudo_header_sum(target, scanner, tcp.len());
+    assert_eq!(checksum_fold(checksum_add(sum, tcp)), 0);
+}
+
+#[test]
+fn answers_syns_to_closed_ports_with_rsts() {
+    let scanner = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1));
+    let target = IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 2));
+    let tags = [VlanTag::dot1q(10)];
+    let reply = respond(
+        &config(vec![target]),
+        &probe(&tags, scanner, target, 22, flags::SYN),
+    )
+    .unwrap();
+
+    // Tagged like the probe.
+    assert_eq!(reply[12..16], [0x81, 0x00, 0x00, 0x0a]);
+    let segment = TcpSegment::parse(&reply).unwrap();
+    assert_eq!((segment.src_ip, segment.dst_ip), (target, scanner));
+    assert_eq!((segment.src_port, segment.dst_port), (22, 4321));
+    assert_eq!(segment.flags, flags::RST | flags::ACK);
+    assert_eq!(segment.ack, 0xdead_bef0);
+
+    let tcp = &reply[18 + 40..];
+    let sum = pseudo_header_sum(target, scanner, tcp.len());
+    assert_eq!(checksum_fold(checksum_add(sum, tcp)), 0);
+}
+
+#[test]
+fn ignores_dropped_ports_other_hosts_and_non_syns() {
+    let scanner = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1));
+    let target = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 2));
+    let other = IpAddr::V4(Ipv4Addr::new(192, 168, 69, 3));
+    let config = config(vec![target]);
+
+    assert!(respond(&config, &probe(&[], scanner, target, 81, flags::SYN)).is_none());
+    assert!(respond(&config, &probe(&[], scanner, other, 80, flags::SYN)).is_none());
+    let syn_ack = flags::SYN | flags::ACK;
+    assert!(respond(&config, &probe(&[], scanner, target, 80, syn_ack)).is_none());
+    assert!(respond(&config, &probe(&[], scanner, target, 80, flags::RST)).is_none());
+}
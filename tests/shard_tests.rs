@@ -0,0 +1,198 @@
+mod common;
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use common::{host, hosts, scan_config, sim_network, src_config, targets};
+use xdpscan::checkpoint::Checkpoint;
+use xdpscan::probe::ScanType;
+use xdpscan::shard::{self, Shard};
+use xdpscan::{PortState, ScanConfig, Target};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("xdpscan-shard-{}-{}", name, process::id()))
+}
+
+#[test]
+fn parses_shards() {
+    assert_eq!("2/5".parse::<Shard>(), Ok(Shard::new(2, 5)));
+    assert_eq!(Shard::new(2, 5).to_string(), "2/5");
+    assert!("5/5".parse::<Shard>().is_err());
+    assert!("1/0".parse::<Shard>().is_err());
+    assert!("1".parse::<Shard>().is_err());
+}
+
+#[test]
+fn shards_cover_targets_exactly_once() {
+    let count = 3;
+    let mut probed = vec![];
+    let mut results = vec![];
+    for index in 0..count {
+        let network = sim_network(30, 4);
+        let log = network.log();
+        let shard = Shard::new(index, count);
+        results.extend(xdpscan::scan_with(
+            network,
+            src_config(),
+            ScanConfig {
+                shard,
+                ..scan_config()
+            },
+            targets(&hosts(30), &[22, 80]),
+        ));
+        let probes = log.probes();
+        assert!(!probes.is_empty());
+        probed.extend(probes);
+    }
+
+    assert_eq!(probed.len(), targets(&hosts(30), &[22, 80]).len());
+    let probed: HashSet<Target> = probed.into_iter().collect();
+    assert_eq!(probed, targets(&hosts(30), &[22, 80]).into_iter().collect());
+
+    let whole = xdpscan::scan_with(
+        sim_network(30, 4),
+        src_config(),
+        scan_config(),
+        targets(&hosts(30), &[22, 80]),
+    );
+    let results: HashSet<Target> = results.into_iter().collect();
+    assert_eq!(results, whole.into_iter().collect());
+}
+
+#[test]
+fn merges_checkpoints_and_result_files() {
+    let checkpoint_paths: Vec<PathBuf> = (0..2)
+        .map(|index| temp_path(&format!("checkpoint-{}", index)))
+        .collect();
+    for (index, path) in checkpoint_paths.iter().enumerate() {
+        let shard = Shard::new(index as u64, 2);
+        xdpscan::scan_with(
+            sim_network(30, 4),
+            src_config(),
+            ScanConfig {
+                shard,
+                checkpoint_path: Some(path.clone()),
+                ..scan_config()
+            },
+            targets(&hosts(30), &[22, 80]),
+        );
+        assert_eq!(Checkpoint::load(path).unwrap().shard, shard);
+    }
+
+    let results_path = temp_path("results");
+    fs::write(
+        &results_path,
+        concat!(
+            "10.0.0.1:80\n",
+            "[fd00::2]:443 open example.com,www.example.com\n",
+            "\n",
+            "10.0.0.1:80\n",
+            "10.0.0.2:80 closed ip=4 ttl=64 df=0 win=0 flags=0x14 recv=1.500000000\n",
+        ),
+    )
+    .unwrap();
+
+    let mut paths = checkpoint_paths.clone();
+    paths.push(results_path.clone());
+    let (merged, hostnames) = shard::merge_results(&paths).unwrap();
+
+    let result = |ip, port, state| (Target { ip, port }, state);
+    // Checkpoints keep the closed ports SYN scans find too.
+    let mut expected = vec![];
+    for i in (1..=30).step_by(4) {
+        expected.push(result(host(i), 22, PortState::Closed));
+        expected.push(result(host(i), 80, PortState::Open));
+    }
+    expected.insert(2, result(host(2), 80, PortState::Closed));
+    expected.push(result("fd00::2".parse().unwrap(), 443, PortState::Open));
+    let states: Vec<_> = merged
+        .iter()
+        .map(|result| (result.target.clone(), result.state))
+        .collect();
+    assert_eq!(states, expected);
+
+    // Replies come from checkpoints, and from the fields after the state of results.
+    let reply = merged[2].reply.as_ref().unwrap();
+    assert_eq!((reply.headers.ttl, reply.headers.flags), (64, 0x14));
+    assert!(merged
+        .iter()
+        .filter(|result| result.target.port == 22)
+        .all(|result| result.reply.is_some()));
+    assert_eq!(merged.last().unwrap().reply, None);
+
+    // Hostnames come from the field after the state of results.
+    let ipv6 = "fd00::2".parse().unwrap();
+    assert_eq!(hostnames.names(ipv6), ["example.com", "www.example.com"]);
+    assert!(hostnames.names(host(1)).is_empty());
+
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn merge_rejects_checkpoints_of_different_scans() {
+    let paths = [temp_path("seed-1"), temp_path("seed-2")];
+    for (seed, path) in [1, 2].iter().zip(&paths) {
+        xdpscan::scan_with(
+            sim_network(30, 4),
+            src_config(),
+            ScanConfig {
+                seed: *seed,
+                checkpoint_path: Some(path.clone()),
+                ..scan_config()
+            },
+            targets(&hosts(30), &[22, 80]),
+        );
+    }
+
+    assert!(shard::merge_results(&paths).is_err());
+    for path in &paths {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn merge_rejects_duplicate_mismatched_and_missing_shards() {
+    let save = |name: &str, shard: Shard| {
+        let path = temp_path(name);
+        let checkpoint = Checkpoint {
+            scan_type: ScanType::Syn,
+            seed: 7,
+            shard,
+            position: 0,
+            config_hash: 0x1234,
+            results: vec![],
+        };
+        checkpoint.save(&path).unwrap();
+        path
+    };
+    let half_0 = save("half-0", Shard::new(0, 2));
+    let half_0_copy = save("half-0-copy", Shard::new(0, 2));
+    let half_1 = save("half-1", Shard::new(1, 2));
+    let third_1 = save("third-1", Shard::new(1, 3));
+    let results_path = temp_path("other-half");
+    fs::write(&results_path, "10.0.0.1:80\n").unwrap();
+    let merge = |paths: &[&PathBuf]| shard::merge_results(paths).map(|_| ());
+
+    assert!(merge(&[&half_0, &half_1]).is_ok());
+    let err = merge(&[&half_0, &half_0_copy, &half_1]).unwrap_err();
+    assert!(err.to_string().contains("are both of shard 0/2"), "{}", err);
+    let err = merge(&[&half_0, &third_1]).unwrap_err();
+    assert!(err.to_string().contains("is of shard 1/3"), "{}", err);
+    let err = merge(&[&half_0]).unwrap_err();
+    assert!(
+        err.to_string().contains("only 1 of the 2 shards"),
+        "{}",
+        err
+    );
+    // The results may be those of the other shard.
+    assert!(merge(&[&half_0, &results_path]).is_ok());
+
+    for path in &[half_0, half_0_copy, half_1, third_1, results_path] {
+        fs::remove_file(path).unwrap();
+    }
+}
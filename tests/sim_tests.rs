@@ -0,0 +1,473 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use common::{host, hosts, scan_config, src_config, targets};
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork, SimPort};
+use xdpscan::packet::{flags, TcpSegment, VlanTag};
+use xdpscan::probe::{Cookies, Probe, ProbeModule, ProbeReply, ScanType};
+use xdpscan::reply::ReplyInfo;
+use xdpscan::{PortState, ScanConfig, SrcConfig, SrcSelection, Target};
+
+fn sorted(mut targets: Vec<Target>) -> Vec<Target> {
+    targets.sort_by_key(|target| (target.ip, target.port));
+    targets
+}
+
+#[test]
+fn classifies_open_closed_and_filtered_ports() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(
+        SimHost::new(host(1))
+            .open(22)
+            .open(80)
+            .port(443, SimPort::Filtered),
+    );
+    network.add_host(
+        SimHost::new(host(2))
+            .default_port(SimPort::Filtered)
+            .open(443),
+    );
+
+    let targets = targets(&[host(1), host(2), host(3)], &[22, 80, 443, 8080]);
+    let responders = xdpscan::scan_with(network, src_config(), scan_config(), targets);
+
+    let expected = vec![
+        Target {
+            ip: host(1),
+            port: 22,
+        },
+        Target {
+            ip: host(1),
+            port: 80,
+        },
+        Target {
+            ip: host(2),
+            port: 443,
+        },
+    ];
+    assert_eq!(sorted(responders), expected);
+}
+
+#[test]
+fn probes_every_target_once() {
+    let network = SimNetwork::new(SimConfig::default());
+    let log = network.log();
+
+    let hosts = hosts(50);
+    let targets = targets(&hosts, &[80, 443]);
+    xdpscan::scan_with(network, src_config(), scan_config(), targets.clone());
+
+    let probes = log.probes();
+    assert_eq!(probes.len(), targets.len());
+    let probes: HashSet<Target> = probes.into_iter().collect();
+    let targets: HashSet<Target> = targets.into_iter().collect();
+    assert_eq!(probes, targets);
+}
+
+#[test]
+fn ignores_duplicate_and_spoofed_replies() {
+    let mut network = SimNetwork::new(SimConfig {
+        seed: 7,
+        duplicate: 1.0,
+        spoof: 1.0,
+        ..SimConfig::default()
+    });
+    for i in 1..=20 {
+        network.add_host(SimHost::new(host(i)).open(80));
+    }
+
+    let hosts = hosts(20);
+    let targets = targets(&hosts, &[80]);
+    let responders = xdpscan::scan_with(network, src_config(), scan_config(), targets.clone());
+
+    assert_eq!(sorted(responders), sorted(targets));
+}
+
+#[test]
+fn loss_is_deterministic() {
+    let run = |seed| {
+        let mut network = SimNetwork::new(SimConfig {
+            seed,
+            loss: 0.5,
+            jitter: Duration::from_millis(5),
+            ..SimConfig::default()
+        });
+        for i in 1..=100 {
+            network.add_host(SimHost::new(host(i)).open(80));
+        }
+        let hosts = hosts(100);
+        sorted(xdpscan::scan_with(
+            network,
+            src_config(),
+            scan_config(),
+            targets(&hosts, &[80]),
+        ))
+    };
+
+    let first = run(42);
+    assert!(!first.is_empty());
+    assert!(first.len() < 100);
+    assert_eq!(first, run(42));
+}
+
+#[test]
+fn scans_ipv6_targets() {
+    let ip = IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2));
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(ip).open(80));
+
+    let targets = targets(&[ip], &[22, 80]);
+    let responders = xdpscan::scan_with(network, src_config(), scan_config(), targets);
+
+    assert_eq!(responders, vec![Target { ip, port: 80 }]);
+}
+
+#[test]
+fn resets_half_open_connections_when_asked() {
+    let mut network = SimNetwork::new(SimConfig {
+        seed: 3,
+        spoof: 1.0,
+        ..SimConfig::default()
+    });
+    for i in 1..=10 {
+        network.add_host(SimHost::new(host(i)).open(80));
+    }
+    let log = network.log();
+
+    let hosts = hosts(10);
+    let scan_config = ScanConfig {
+        reset_open: true,
+        ..scan_config()
+    };
+    let responders = xdpscan::scan_with(
+        network,
+        src_config(),
+        scan_config,
+        targets(&hosts, &[22, 80]),
+    );
+
+    let open = targets(&hosts, &[80]);
+    assert_eq!(sorted(responders), open);
+    // Spoofed SYN-ACKs are not answered, and every RST carries the right sequence number.
+    assert_eq!(sorted(log.resets()), open);
+    assert!(log.half_open().is_empty());
+}
+
+#[test]
+fn leaves_connections_half_open_by_default() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    for i in 1..=10 {
+        network.add_host(SimHost::new(host(i)).open(80));
+    }
+    let log = network.log();
+
+    let hosts = hosts(10);
+    xdpscan::scan_with(network, src_config(), scan_config(), targets(&hosts, &[80]));
+
+    assert!(log.resets().is_empty());
+    assert_eq!(sorted(log.half_open()), targets(&hosts, &[80]));
+}
+
+fn multi_src_config(src_selection: SrcSelection) -> SrcConfig {
+    SrcConfig {
+        src_ips: vec![
+            IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1)),
+            IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 69, 3)),
+        ],
+        src_ports: 40000..=40001,
+        src_selection,
+        ..src_config()
+    }
+}
+
+#[test]
+fn round_robin_spreads_probes_over_sources() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    for i in 1..=8 {
+        network.add_host(SimHost::new(host(i)).open(80));
+    }
+    let log = network.log();
+
+    let hosts = hosts(8);
+    let responders = xdpscan::scan_with(
+        network,
+        multi_src_config(SrcSelection::RoundRobin),
+        scan_config(),
+        targets(&hosts, &[80]),
+    );
+    assert_eq!(sorted(responders), targets(&hosts, &[80]));
+
+    // IPv4 targets are only probed from the IPv4 sources, each of them as often as the others.
+    let mut uses: HashMap<SocketAddr, usize> = HashMap::new();
+    for source in log.sources() {
+        *uses.entry(source).or_default() += 1;
+    }
+    let mut expected = HashMap::new();
+    for ip in [[192, 168, 69, 1], [192, 168, 69, 3]] {
+        for port in 40000..=40001 {
+            expected.insert(SocketAddr::new(IpAddr::from(ip), port), 2);
+        }
+    }
+    assert_eq!(uses, expected);
+}
+
+#[test]
+fn hashed_selection_keeps_sources_across_probe_orders() {
+    let run = |seed| {
+        let network = SimNetwork::new(SimConfig::default());
+        let log = network.log();
+        let hosts = hosts(20);
+        let scan_config = ScanConfig {
+            seed,
+            ..scan_config()
+        };
+        xdpscan::scan_with(
+            network,
+            multi_src_config(SrcSelection::Hashed),
+            scan_config,
+            targets(&hosts, &[22, 80]),
+        );
+        log.probes()
+            .into_iter()
+            .zip(log.sources())
+            .collect::<HashMap<Target, SocketAddr>>()
+    };
+
+    let first = run(1);
+    assert_eq!(first.len(), 40);
+    assert!(first.values().collect::<HashSet<_>>().len() > 1);
+    assert_eq!(first, run(2));
+}
+
+/// Scans ports 22 (closed), 80 (open) and 443 (filtered) of two hosts, the second of which
+/// gives open ports away by the window of its RSTs.
+fn scan_states(scan_type: ScanType) -> Vec<(Target, PortState)> {
+    let mut network = SimNetwork::new(SimConfig::default());
+    for (i, open_rst_window) in [(1, 0), (2, 512)] {
+        network.add_host(
+            SimHost::new(host(i))
+                .open(80)
+                .port(443, SimPort::Filtered)
+                .open_rst_window(open_rst_window),
+        );
+    }
+    let scan_config = ScanConfig {
+        scan_type,
+        ..scan_config()
+    };
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config(),
+        scan_config,
+        targets(&[host(1), host(2)], &[22, 80, 443]),
+    );
+    results
+        .into_iter()
+        .map(|result| (result.target, result.state))
+        .collect()
+}
+
+fn states(expected: &[(u8, u16, PortState)]) -> Vec<(Target, PortState)> {
+    expected
+        .iter()
+        .map(|&(i, port, state)| (Target { ip: host(i), port }, state))
+        .collect()
+}
+
+#[test]
+fn scans_through_vlan_tags() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(host(1)).open(80));
+    let src_config = SrcConfig {
+        vlan_tags: vec![VlanTag::dot1ad(100), VlanTag::dot1q(10)],
+        ..src_config()
+    };
+
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config,
+        scan_config(),
+        targets(&[host(1)], &[22, 80]),
+    );
+    let states_found: Vec<(Target, PortState)> = results
+        .into_iter()
+        .map(|result| (result.target, result.state))
+        .collect();
+
+    assert_eq!(
+        states_found,
+        states(&[(1, 22, PortState::Closed), (1, 80, PortState::Open)])
+    );
+}
+
+#[test]
+fn syn_scans_tell_open_from_closed_ports() {
+    assert_eq!(
+        scan_states(ScanType::Syn),
+        states(&[
+            (1, 22, PortState::Closed),
+            (1, 80, PortState::Open),
+            (2, 22, PortState::Closed),
+            (2, 80, PortState::Open),
+        ])
+    );
+}
+
+#[test]
+fn ack_scans_find_unfiltered_ports() {
+    assert_eq!(
+        scan_states(ScanType::Ack),
+        states(&[
+            (1, 22, PortState::Unfiltered),
+            (1, 80, PortState::Unfiltered),
+            (2, 22, PortState::Unfiltered),
+            (2, 80, PortState::Unfiltered),
+        ])
+    );
+    assert_eq!(ScanType::Ack.unanswered_state(), PortState::Filtered);
+}
+
+#[test]
+fn fin_null_and_xmas_scans_find_closed_ports() {
+    for scan_type in [ScanType::Fin, ScanType::Null, ScanType::Xmas] {
+        assert_eq!(
+            scan_states(scan_type),
+            states(&[(1, 22, PortState::Closed), (2, 22, PortState::Closed)])
+        );
+        assert_eq!(scan_type.unanswered_state(), PortState::OpenFiltered);
+    }
+}
+
+#[test]
+fn window_scans_read_the_rst_window() {
+    assert_eq!(
+        scan_states(ScanType::Window),
+        states(&[
+            (1, 22, PortState::Closed),
+            (1, 80, PortState::Closed),
+            (2, 22, PortState::Closed),
+            (2, 80, PortState::Open),
+        ])
+    );
+}
+
+/// A scan type defined outside the crate: a SYN scan that counts its probes and ignores RSTs.
+#[derive(Debug, Default)]
+struct SynAckOnly {
+    probes: AtomicUsize,
+}
+
+impl ProbeModule for SynAckOnly {
+    fn write_probe(&self, buf: &mut [u8], probe: &Probe) -> usize {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        ScanType::Syn.module().write_probe(buf, probe)
+    }
+
+    fn classify(
+        &self,
+        frame: &[u8],
+        segment: Option<&TcpSegment>,
+        cookies: &Cookies,
+    ) -> Option<ProbeReply> {
+        ScanType::Syn
+            .module()
+            .classify(frame, segment, cookies)
+            .filter(|reply| reply.state == PortState::Open)
+    }
+}
+
+#[test]
+fn scans_with_probe_modules_of_other_crates() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(host(1)).open(80));
+    let module = Arc::new(SynAckOnly::default());
+    let scan_config = ScanConfig {
+        probe_module: Some(module.clone()),
+        ..scan_config()
+    };
+
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config(),
+        scan_config,
+        targets(&[host(1)], &[22, 80]),
+    );
+    let open = Target {
+        ip: host(1),
+        port: 80,
+    };
+    assert_eq!(
+        results
+            .into_iter()
+            .map(|result| (result.target, result.state))
+            .collect::<Vec<_>>(),
+        vec![(open, PortState::Open)]
+    );
+    assert_eq!(module.probes.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn results_carry_their_reply() {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(SimHost::new(host(1)).open(80));
+    let before = SystemTime::now();
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config(),
+        scan_config(),
+        targets(&[host(1)], &[22, 80]),
+    );
+    let after = SystemTime::now();
+
+    let replies: Vec<(u16, ReplyInfo)> = results
+        .into_iter()
+        .map(|result| (result.target.port, result.reply.unwrap()))
+        .collect();
+    assert_eq!(replies.len(), 2);
+    for (port, reply) in replies {
+        let expected_flags = if port == 80 {
+            flags::SYN | flags::ACK
+        } else {
+            flags::RST | flags::ACK
+        };
+        assert_eq!(reply.headers.flags, expected_flags);
+        assert_eq!(reply.headers.ttl, 64);
+        assert_eq!(reply.headers.window, if port == 80 { 65535 } else { 0 });
+        assert!(reply.received_at >= before && reply.received_at <= after);
+        let sent_at = reply.sent_at.unwrap();
+        assert!(sent_at >= before && sent_at <= reply.received_at);
+        assert_eq!(reply.interface, None);
+        assert_eq!(reply.to_string().parse(), Ok(reply));
+    }
+}
+
+#[test]
+fn times_replies_to_probes_without_timestamps() {
+    let latency = Duration::from_millis(20);
+    let mut network = SimNetwork::new(SimConfig {
+        latency,
+        ..SimConfig::default()
+    });
+    network.add_host(SimHost::new(host(1)).open(80));
+    let results = xdpscan::scan_results_with(
+        network,
+        src_config(),
+        scan_config(),
+        targets(&[host(1)], &[22, 80]),
+    );
+
+    let states: Vec<PortState> = results.iter().map(|result| result.state).collect();
+    assert_eq!(states, vec![PortState::Closed, PortState::Open]);
+    for result in results {
+        let reply = result.reply.unwrap();
+        assert_eq!(reply.headers.timestamps, None);
+        let rtt = reply.rtt().unwrap();
+        assert!(rtt >= latency && rtt < 10 * latency, "{:?}", rtt);
+    }
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+use std::process;
+
+use common::{scan_config, sim_network, src_config};
+use xdpscan::checkpoint::Checkpoint;
+use xdpscan::shard::Shard;
+use xdpscan::targets::{TargetReader, TargetSource};
+use xdpscan::{PortState, ScanConfig, ScanResult, Target};
+
+fn target(addr: &str) -> Target {
+    let addr: SocketAddr = addr.parse().unwrap();
+    Target {
+        ip: addr.ip(),
+        port: addr.port(),
+    }
+}
+
+fn reader(list: &str, default_ports: &[u16]) -> TargetReader<Cursor<String>> {
+    TargetReader::new(Cursor::new(list.to_string()), default_ports.to_vec())
+}
+
+fn read_all<S: TargetSource>(mut source: S) -> io::Result<Vec<Target>> {
+    let mut targets = vec![];
+    while let Some(target) = source.next_target()? {
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+#[test]
+fn reads_addresses_blocks_and_ranges() {
+    let list = "\
+# Scanned weekly
+10.0.0.1
+10.0.0.2:8080  # just the proxy
+
+[fd00::1]:443
+10.0.1.7/31
+10.0.2.254-10.0.3.1
+fd00::ff-fd00::100
+";
+    let expected: Vec<Target> = [
+        "10.0.0.1:22",
+        "10.0.0.1:80",
+        "10.0.0.2:8080",
+        "[fd00::1]:443",
+        "10.0.1.6:22",
+        "10.0.1.6:80",
+        "10.0.1.7:22",
+        "10.0.1.7:80",
+        "10.0.2.254:22",
+        "10.0.2.254:80",
+        "10.0.2.255:22",
+        "10.0.2.255:80",
+        "10.0.3.0:22",
+        "10.0.3.0:80",
+        "10.0.3.1:22",
+        "10.0.3.1:80",
+        "[fd00::ff]:22",
+        "[fd00::ff]:80",
+        "[fd00::100]:22",
+        "[fd00::100]:80",
+    ]
+    .iter()
+    .map(|addr| target(addr))
+    .collect();
+    assert_eq!(read_all(reader(list, &[22, 80])).unwrap(), expected);
+}
+
+#[test]
+fn expands_blocks_as_they_are_read() {
+    let mut source = reader("::/0\n", &[80]);
+    let first: Vec<Target> = (0..3)
+        .map(|_| source.next_target().unwrap().unwrap())
+        .collect();
+    assert_eq!(
+        first,
+        vec![target("[::]:80"), target("[::1]:80"), target("[::2]:80")]
+    );
+
+    let last = read_all(reader("255.255.255.254/31\n0.0.0.0/32", &[1])).unwrap();
+    assert_eq!(
+        last,
+        vec![
+            target("255.255.255.254:1"),
+            target("255.255.255.255:1"),
+            target("0.0.0.0:1")
+        ]
+    );
+}
+
+#[test]
+fn reports_the_line_of_invalid_targets() {
+    let invalid = |list: &str, default_ports: &[u16]| {
+        let err = read_all(reader(list, default_ports)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    };
+    assert_eq!(
+        invalid("10.0.0.1\n\n10.0.0.256\n", &[80]),
+        "line 3: invalid target 10.0.0.256"
+    );
+    assert_eq!(
+        invalid("10.0.0.0/33", &[80]),
+        "line 1: invalid target 10.0.0.0/33"
+    );
+    assert_eq!(
+        invalid("10.0.0.2-10.0.0.1", &[80]),
+        "line 1: invalid target 10.0.0.2-10.0.0.1"
+    );
+    assert_eq!(
+        invalid("10.0.0.1-fd00::1", &[80]),
+        "line 1: invalid target 10.0.0.1-fd00::1"
+    );
+    assert_eq!(
+        invalid("10.0.0.1:80\n10.0.0.1", &[]),
+        "line 2: no port to probe 10.0.0.1 on"
+    );
+}
+
+fn listed_targets() -> Vec<Target> {
+    read_all(reader("10.0.0.1-10.0.0.40", &[80])).unwrap()
+}
+
+#[test]
+fn streamed_targets_are_probed_in_order() {
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let results = xdpscan::scan_source_with(
+        network,
+        src_config(),
+        scan_config(),
+        reader("10.0.0.1-10.0.0.40", &[80]),
+    )
+    .unwrap();
+
+    assert_eq!(log.probes(), listed_targets());
+    let mut open: Vec<Target> = results
+        .into_iter()
+        .filter(|result| result.state == PortState::Open)
+        .map(|result| result.target)
+        .collect();
+    open.sort_by_key(|target| target.ip);
+    let expected: Vec<Target> = listed_targets().into_iter().step_by(3).collect();
+    assert_eq!(open, expected);
+}
+
+#[test]
+fn streamed_scans_are_sharded_by_position() {
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let scan_config = ScanConfig {
+        shard: Shard::new(1, 4),
+        ..scan_config()
+    };
+    xdpscan::scan_source_with(
+        network,
+        src_config(),
+        scan_config,
+        reader("10.0.0.1-10.0.0.40", &[80]),
+    )
+    .unwrap();
+
+    let expected: Vec<Target> = listed_targets()
+        .into_iter()
+        .enumerate()
+        .filter(|&(position, _)| Shard::new(1, 4).contains(position as u64))
+        .map(|(_, target)| target)
+        .collect();
+    assert_eq!(log.probes(), expected);
+}
+
+#[test]
+fn streamed_scans_resume_after_the_checkpoint() {
+    let path = env::temp_dir().join(format!("xdpscan-stream-{}.checkpoint", process::id()));
+    let scan_config = ScanConfig {
+        checkpoint_path: Some(path.clone()),
+        ..scan_config()
+    };
+
+    let results = xdpscan::scan_source_with(
+        sim_network(40, 3),
+        src_config(),
+        scan_config.clone(),
+        reader("10.0.0.1-10.0.0.40", &[80]),
+    )
+    .unwrap();
+    let checkpoint = Checkpoint::load(&path).unwrap();
+    assert_eq!(checkpoint.position, 40);
+    assert_eq!(checkpoint.results, results);
+
+    let position = 25;
+    let completed = &listed_targets()[..position];
+    Checkpoint {
+        position: position as u64,
+        results: results
+            .iter()
+            .filter(|result| completed.contains(&result.target))
+            .cloned()
+            .collect(),
+        ..checkpoint
+    }
+    .save(&path)
+    .unwrap();
+
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let mut resumed = xdpscan::resume_source_with(
+        network,
+        src_config(),
+        scan_config,
+        reader("10.0.0.1-10.0.0.40", &[80]),
+    )
+    .unwrap();
+    assert_eq!(log.probes(), listed_targets()[position..].to_vec());
+    // Streamed results come in the order they answered.
+    let mut results = results;
+    resumed.sort_by_key(|result| result.target.ip);
+    results.sort_by_key(|result| result.target.ip);
+    // Replies to the probes sent again came in at other times.
+    let states = |results: &[ScanResult]| -> Vec<(Target, PortState)> {
+        results
+            .iter()
+            .map(|result| (result.target.clone(), result.state))
+            .collect()
+    };
+    assert_eq!(states(&resumed), states(&results));
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn streamed_scans_stop_at_unreadable_targets() {
+    let network = sim_network(40, 3);
+    let log = network.log();
+    let err = xdpscan::scan_source_with(
+        network,
+        src_config(),
+        scan_config(),
+        reader("10.0.0.1\n10.0.0.2\nnot an address\n10.0.0.3\n", &[80]),
+    )
+    .unwrap_err();
+
+    assert_eq!(err.to_string(), "line 3: invalid target not an address");
+    assert_eq!(
+        log.probes(),
+        vec![target("10.0.0.1:80"), target("10.0.0.2:80")]
+    );
+}
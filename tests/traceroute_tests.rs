@@ -0,0 +1,207 @@
+mod common;
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use xdpscan::backend::sim::{SimConfig, SimHost, SimNetwork, SimPort};
+use xdpscan::metrics::Metrics;
+use xdpscan::traceroute::{self, TraceConfig, TraceProtocol};
+use xdpscan::{PortState, SrcConfig, SrcSelection, Target};
+
+fn router(last_octet: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(172, 16, 0, last_octet))
+}
+
+fn src_config() -> SrcConfig {
+    SrcConfig {
+        src_ips: vec![
+            IpAddr::V4(Ipv4Addr::new(192, 168, 69, 1)),
+            IpAddr::V6(Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1)),
+        ],
+        src_ports: 4321..=4330,
+        // Traceroutes hash the target whatever this says.
+        src_selection: SrcSelection::RoundRobin,
+        ..common::src_config()
+    }
+}
+
+fn trace_config(protocol: TraceProtocol) -> TraceConfig {
+    TraceConfig {
+        protocol,
+        max_ttl: 8,
+        startup_delay: Duration::from_millis(10),
+        wait_time: Duration::from_millis(200),
+        ..TraceConfig::default()
+    }
+}
+
+/// Two hosts behind a shared first router, and an IPv6 host, with a port of each state.
+fn network() -> SimNetwork {
+    let mut network = SimNetwork::new(SimConfig::default());
+    network.add_host(
+        SimHost::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+            .open(80)
+            .port(81, SimPort::Filtered)
+            .route(vec![router(1), router(2), router(3)]),
+    );
+    network.add_host(
+        SimHost::new(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1))).route(vec![router(1), router(4)]),
+    );
+    network.add_host(
+        SimHost::new("fd00::2".parse().unwrap())
+            .open(443)
+            .route(vec!["fd00:ff::1".parse().unwrap()]),
+    );
+    network
+}
+
+fn target(ip: &str, port: u16) -> Target {
+    Target {
+        ip: ip.parse().unwrap(),
+        port,
+    }
+}
+
+#[test]
+fn traces_paths_with_tcp() {
+    let network = network();
+    let log = network.log();
+    let targets = vec![
+        target("10.0.0.1", 80),
+        target("10.0.0.1", 81),
+        target("10.0.1.1", 22),
+        target("fd00::2", 443),
+    ];
+    let config = trace_config(TraceProtocol::Tcp);
+    let traces = traceroute::trace_with(network, src_config(), config, targets.clone());
+
+    let found: Vec<(Target, Option<PortState>)> = traces
+        .iter()
+        .map(|trace| (trace.target.clone(), trace.state))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (targets[0].clone(), Some(PortState::Open)),
+            (targets[1].clone(), None),
+            (targets[2].clone(), Some(PortState::Closed)),
+            (targets[3].clone(), Some(PortState::Open)),
+        ]
+    );
+    let host = Some(targets[0].ip);
+    assert_eq!(
+        traces[0].hops,
+        vec![Some(router(1)), Some(router(2)), Some(router(3)), host]
+    );
+    // Filtered ports only show the routers.
+    assert_eq!(
+        traces[1].hops,
+        vec![Some(router(1)), Some(router(2)), Some(router(3))]
+    );
+    assert!(!traces[1].reached());
+    assert_eq!(
+        traces[2].hops,
+        vec![Some(router(1)), Some(router(4)), Some(targets[2].ip)]
+    );
+    assert_eq!(
+        traces[3].hops,
+        vec![Some("fd00:ff::1".parse().unwrap()), Some(targets[3].ip)]
+    );
+
+    // Every TTL is probed, all targets at a time, and each target from a single source.
+    let probes = log.probes();
+    assert_eq!(probes.len(), 8 * targets.len());
+    assert_eq!(&probes[..targets.len()], &targets[..]);
+    for target in &targets {
+        let sources: HashSet<_> = probes
+            .iter()
+            .zip(log.sources())
+            .filter(|(probe, _)| *probe == target)
+            .map(|(_, source)| source)
+            .collect();
+        assert_eq!(sources.len(), 1);
+    }
+    // Open ports are left with no half-open connection.
+    assert!(log.half_open().is_empty());
+}
+
+#[test]
+fn traces_paths_with_udp() {
+    let targets = vec![target("10.0.0.1", 33434), target("10.0.0.1", 80)];
+    let config = trace_config(TraceProtocol::Udp);
+    let traces = traceroute::trace_with(network(), src_config(), config, targets.clone());
+
+    // Closed ports answer with port unreachable errors, open ones not at all.
+    assert_eq!(traces[0].state, Some(PortState::Closed));
+    assert_eq!(
+        traces[0].hops,
+        vec![
+            Some(router(1)),
+            Some(router(2)),
+            Some(router(3)),
+            Some(targets[0].ip)
+        ]
+    );
+    assert_eq!(traces[1].state, None);
+    assert_eq!(traces[1].hops.len(), 3);
+    assert_eq!(
+        traces[0].to_string(),
+        "10.0.0.1:33434 closed\n  1 172.16.0.1\n  2 172.16.0.2\n  3 172.16.0.3\n  4 10.0.0.1"
+    );
+}
+
+#[test]
+fn silent_hops_are_gaps() {
+    let mut network = SimNetwork::new(SimConfig {
+        loss: 0.3,
+        seed: 7,
+        ..SimConfig::default()
+    });
+    let host = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let routers = (1..=6).map(router).collect();
+    network.add_host(SimHost::new(host).open(80).route(routers));
+    let config = TraceConfig {
+        first_ttl: 2,
+        ..trace_config(TraceProtocol::Tcp)
+    };
+    let targets = vec![Target { ip: host, port: 80 }];
+    let trace = &traceroute::trace_with(network, src_config(), config, targets)[0];
+
+    assert_eq!(trace.first_ttl, 2);
+    assert!(trace.reached());
+    for (ttl, hop) in (2..).zip(&trace.hops) {
+        let expected = if ttl <= 6 { router(ttl) } else { host };
+        assert!(hop.map_or(true, |addr| addr == expected), "{}", trace);
+    }
+    // Lost probes and replies leave gaps, printed as `*`.
+    let gaps = trace.hops.iter().filter(|hop| hop.is_none()).count();
+    assert!(gaps > 0);
+    let stars = trace
+        .to_string()
+        .lines()
+        .filter(|line| line.ends_with(" *"))
+        .count();
+    assert_eq!(stars, gaps);
+}
+
+#[test]
+fn counts_probes_into_metrics() {
+    let metrics = Arc::new(Metrics::new());
+    let config = TraceConfig {
+        metrics: Some(metrics.clone()),
+        ..trace_config(TraceProtocol::Tcp)
+    };
+    let targets = vec![target("10.0.0.1", 80), target("10.0.1.1", 22)];
+    traceroute::trace_with(network(), src_config(), config, targets);
+
+    let text = metrics.render();
+    for sample in &[
+        "xdpscan_probes_sent_total 16",
+        "xdpscan_scan_targets 16",
+        "xdpscan_scan_position 16",
+    ] {
+        assert!(text.lines().any(|line| line == *sample), "{}", text);
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashSet;
+use std::thread;
+
+use xdpscan::backend::umem::{FrameAllocator, Owner};
+
+const FRAME_SIZE: usize = 2048;
+
+#[test]
+fn halves_never_share_frames() {
+    let allocator = FrameAllocator::new(64, FRAME_SIZE);
+    let tx: HashSet<usize> = allocator.alloc(Owner::Tx, 32).into_iter().collect();
+    let rx: HashSet<usize> = allocator.alloc(Owner::Rx, 64).into_iter().collect();
+
+    assert_eq!(tx.len(), 32);
+    assert_eq!(rx.len(), 32);
+    assert!(tx.is_disjoint(&rx));
+    assert!(allocator.alloc(Owner::Tx, 1).is_empty());
+    for &addr in &tx {
+        assert_eq!(addr % FRAME_SIZE, 0);
+        assert_eq!(allocator.owner(addr), Some(Owner::Tx));
+    }
+    for &addr in &rx {
+        // Received frames start past the headroom.
+        assert_eq!(allocator.owner(addr + 256), Some(Owner::Rx));
+    }
+}
+
+#[test]
+fn concurrent_allocations_are_disjoint() {
+    let allocator = FrameAllocator::new(4096, FRAME_SIZE);
+    let handles: Vec<_> = [Owner::Tx, Owner::Rx]
+        .iter()
+        .map(|&owner| {
+            let allocator = allocator.clone();
+            thread::spawn(move || {
+                (0..64)
+                    .flat_map(|_| allocator.alloc(owner, 32))
+                    .collect::<Vec<usize>>()
+            })
+        })
+        .collect();
+    let allocated: Vec<Vec<usize>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    let tx: HashSet<usize> = allocated[0].iter().cloned().collect();
+    let rx: HashSet<usize> = allocated[1].iter().cloned().collect();
+    assert_eq!(tx.len() + rx.len(), 4096);
+    assert!(tx.is_disjoint(&rx));
+}
+
+#[test]
+#[should_panic(expected = "RX used frame 0, which is owned by Some(Tx)")]
+fn using_a_frame_of_the_other_half_panics() {
+    let allocator = FrameAllocator::new(4, FRAME_SIZE);
+    let addr = allocator.alloc(Owner::Tx, 1)[0];
+    allocator.check(addr, Owner::Rx);
+}
+
+#[test]
+#[should_panic(expected = "outside the UMEM")]
+fn addresses_past_the_umem_panic() {
+    let allocator = FrameAllocator::new(4, FRAME_SIZE);
+    allocator.owner(4 * FRAME_SIZE);
+}
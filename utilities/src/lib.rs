@@ -1 +1,3 @@
+pub mod netns;
+pub mod responder;
 pub mod veth_setup;
@@ -0,0 +1,375 @@
+//! Veth test topologies isolated in their own network namespaces.
+//!
+//! Every `Topology` lives in freshly created namespaces with uniquely named links, so tests
+//! can run in parallel without `#[serial]`, and everything is torn down when it is dropped,
+//! including when a test panics.
+//!
+//! Namespaces are managed with iproute2, since the rtnetlink version used by `veth_setup`
+//! cannot create namespaces, set queue counts or add routes.
+use std::fs::{self, File};
+use std::io;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::AsRawFd;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn unique_name(prefix: &str) -> String {
+    // Link names are limited to 15 bytes.
+    format!(
+        "{}{}x{}",
+        prefix,
+        process::id() % 100_000,
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn ip(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("ip").args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "ip {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    }
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn setns(file: &File) -> io::Result<()> {
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// A named network namespace, deleted on drop.
+pub struct NetNs {
+    name: String,
+}
+
+impl NetNs {
+    pub fn new() -> io::Result<Self> {
+        let name = unique_name("xdpscan");
+        ip(&["netns", "add", &name])?;
+        let netns = NetNs { name };
+        netns.ip(&["link", "set", "lo", "up"])?;
+        Ok(netns)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn ip(&self, args: &[&str]) -> io::Result<String> {
+        let mut ns_args = vec!["-n", self.name.as_str()];
+        ns_args.extend_from_slice(args);
+        ip(&ns_args)
+    }
+
+    /// Writes `value` to `/proc/sys/<key>` inside the namespace.
+    pub fn sysctl(&self, key: &str, value: &str) -> io::Result<()> {
+        let _guard = self.enter()?;
+        fs::write(format!("/proc/sys/{}", key), value)
+    }
+
+    /// Moves the current thread into the namespace until the returned guard is dropped.
+    ///
+    /// Threads spawned while the guard is alive start in the namespace too, and sockets
+    /// created in it stay bound to it after the guard is dropped.
+    pub fn enter(&self) -> io::Result<NetNsGuard> {
+        let previous = File::open("/proc/thread-self/ns/net")?;
+        setns(&File::open(format!("/run/netns/{}", self.name))?)?;
+        Ok(NetNsGuard {
+            previous,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for NetNs {
+    fn drop(&mut self) {
+        if let Err(e) = ip(&["netns", "del", &self.name]) {
+            eprintln!(
+                "{}. May need to remove manually: 'sudo ip netns del {}'",
+                e, self.name
+            );
+        }
+    }
+}
+
+/// Returns the current thread to its previous namespace when dropped.
+pub struct NetNsGuard {
+    previous: File,
+    // Namespaces are per thread, so the guard must be dropped on the thread that made it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for NetNsGuard {
+    fn drop(&mut self) {
+        setns(&self.previous).expect("failed to return to previous network namespace");
+    }
+}
+
+/// One end of a topology.
+pub struct Endpoint {
+    pub netns: NetNs,
+    pub if_name: String,
+    pub mac: [u8; 6],
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+    /// MAC address of the next hop towards the other endpoint, either the other endpoint
+    /// itself or the router.
+    pub next_hop_mac: [u8; 6],
+}
+
+#[derive(Clone, Debug)]
+pub struct TopologyConfig {
+    /// Number of RX and TX queues on every veth.
+    pub queues: u32,
+    /// Whether to route between the endpoints through a third namespace instead of
+    /// connecting them directly.
+    pub router: bool,
+}
+
+impl Default for TopologyConfig {
+    fn default() -> Self {
+        TopologyConfig {
+            queues: 1,
+            router: false,
+        }
+    }
+}
+
+struct LinkAddrs<'a> {
+    if_name: &'a str,
+    mac: [u8; 6],
+    ipv4: Ipv4Addr,
+    ipv6: Ipv6Addr,
+}
+
+fn configure_link(netns: &NetNs, link: &LinkAddrs) -> io::Result<()> {
+    netns.ip(&[
+        "link",
+        "set",
+        link.if_name,
+        "address",
+        &format_mac(&link.mac),
+    ])?;
+    netns.ip(&[
+        "addr",
+        "add",
+        &format!("{}/24", link.ipv4),
+        "dev",
+        link.if_name,
+    ])?;
+    netns.ip(&[
+        "-6",
+        "addr",
+        "add",
+        &format!("{}/64", link.ipv6),
+        "dev",
+        link.if_name,
+        "nodad",
+    ])?;
+    netns.ip(&["link", "set", link.if_name, "up"])?;
+    Ok(())
+}
+
+// A veth only gets a carrier once both ends are up, and frames sent before then are dropped,
+// which would cost the first ARP or neighbour solicitation a retransmit.
+fn wait_for_carrier(netns: &NetNs, if_name: &str) -> io::Result<()> {
+    for _ in 0..100 {
+        if netns
+            .ip(&["link", "show", "dev", if_name])?
+            .contains("state UP")
+        {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("{} has no carrier", if_name),
+    ))
+}
+
+// Without this, IPv6 addresses (including the link-local ones routers need for neighbour
+// discovery) stay tentative for a couple of seconds after the links come up.
+fn disable_dad(netns: &NetNs) -> io::Result<()> {
+    netns.sysctl("net/ipv6/conf/all/accept_dad", "0")?;
+    netns.sysctl("net/ipv6/conf/default/accept_dad", "0")
+}
+
+fn add_veth(
+    config: &TopologyConfig,
+    a: (&NetNs, &LinkAddrs),
+    b: (&NetNs, &LinkAddrs),
+) -> io::Result<()> {
+    let queues = config.queues.to_string();
+    a.0.ip(&[
+        "link",
+        "add",
+        a.1.if_name,
+        "numtxqueues",
+        &queues,
+        "numrxqueues",
+        &queues,
+        "type",
+        "veth",
+        "peer",
+        "name",
+        b.1.if_name,
+        "netns",
+        b.0.name(),
+        "numtxqueues",
+        &queues,
+        "numrxqueues",
+        &queues,
+    ])?;
+    configure_link(a.0, a.1)?;
+    configure_link(b.0, b.1)?;
+    wait_for_carrier(a.0, a.1.if_name)?;
+    wait_for_carrier(b.0, b.1.if_name)
+}
+
+/// A scanner namespace and a target namespace connected by veth pairs.
+///
+/// Without a router the endpoints share 192.168.69.0/24 and fd00:69::/64. With a router the
+/// scanner is on those subnets, the target on 192.168.70.0/24 and fd00:70::/64, and the router
+/// namespace forwards between them.
+pub struct Topology {
+    pub scanner: Endpoint,
+    pub target: Endpoint,
+    pub router: Option<NetNs>,
+}
+
+impl Topology {
+    pub fn new(config: &TopologyConfig) -> io::Result<Self> {
+        let scanner_ns = NetNs::new()?;
+        let target_ns = NetNs::new()?;
+        disable_dad(&scanner_ns)?;
+        disable_dad(&target_ns)?;
+
+        let scanner = LinkAddrs {
+            if_name: &unique_name("xs"),
+            mac: [0x02, 0, 0, 0, 0, 0x01],
+            ipv4: Ipv4Addr::new(192, 168, 69, 1),
+            ipv6: Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 1),
+        };
+
+        if !config.router {
+            let target = LinkAddrs {
+                if_name: &unique_name("xt"),
+                mac: [0x02, 0, 0, 0, 0, 0x02],
+                ipv4: Ipv4Addr::new(192, 168, 69, 2),
+                ipv6: Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 2),
+            };
+            add_veth(config, (&scanner_ns, &scanner), (&target_ns, &target))?;
+
+            return Ok(Topology {
+                scanner: Endpoint {
+                    netns: scanner_ns,
+                    if_name: scanner.if_name.into(),
+                    mac: scanner.mac,
+                    ipv4: scanner.ipv4,
+                    ipv6: scanner.ipv6,
+                    next_hop_mac: target.mac,
+                },
+                target: Endpoint {
+                    netns: target_ns,
+                    if_name: target.if_name.into(),
+                    mac: target.mac,
+                    ipv4: target.ipv4,
+                    ipv6: target.ipv6,
+                    next_hop_mac: scanner.mac,
+                },
+                router: None,
+            });
+        }
+
+        let router_ns = NetNs::new()?;
+        disable_dad(&router_ns)?;
+        router_ns.sysctl("net/ipv4/ip_forward", "1")?;
+        router_ns.sysctl("net/ipv6/conf/all/forwarding", "1")?;
+        let router_scanner_side = LinkAddrs {
+            if_name: &unique_name("xr"),
+            mac: [0x02, 0, 0, 0, 0, 0xfe],
+            ipv4: Ipv4Addr::new(192, 168, 69, 254),
+            ipv6: Ipv6Addr::new(0xfd00, 0x69, 0, 0, 0, 0, 0, 0xfe),
+        };
+        let router_target_side = LinkAddrs {
+            if_name: &unique_name("xr"),
+            mac: [0x02, 0, 0, 0, 0x01, 0xfe],
+            ipv4: Ipv4Addr::new(192, 168, 70, 254),
+            ipv6: Ipv6Addr::new(0xfd00, 0x70, 0, 0, 0, 0, 0, 0xfe),
+        };
+        let target = LinkAddrs {
+            if_name: &unique_name("xt"),
+            mac: [0x02, 0, 0, 0, 0, 0x02],
+            ipv4: Ipv4Addr::new(192, 168, 70, 2),
+            ipv6: Ipv6Addr::new(0xfd00, 0x70, 0, 0, 0, 0, 0, 2),
+        };
+        add_veth(
+            config,
+            (&scanner_ns, &scanner),
+            (&router_ns, &router_scanner_side),
+        )?;
+        add_veth(
+            config,
+            (&router_ns, &router_target_side),
+            (&target_ns, &target),
+        )?;
+
+        for (netns, gateway) in &[
+            (&scanner_ns, &router_scanner_side),
+            (&target_ns, &router_target_side),
+        ] {
+            netns.ip(&["route", "add", "default", "via", &gateway.ipv4.to_string()])?;
+            netns.ip(&[
+                "-6",
+                "route",
+                "add",
+                "default",
+                "via",
+                &gateway.ipv6.to_string(),
+            ])?;
+        }
+
+        Ok(Topology {
+            scanner: Endpoint {
+                netns: scanner_ns,
+                if_name: scanner.if_name.into(),
+                mac: scanner.mac,
+                ipv4: scanner.ipv4,
+                ipv6: scanner.ipv6,
+                next_hop_mac: router_scanner_side.mac,
+            },
+            target: Endpoint {
+                netns: target_ns,
+                if_name: target.if_name.into(),
+                mac: target.mac,
+                ipv4: target.ipv4,
+                ipv6: target.ipv6,
+                next_hop_mac: router_target_side.mac,
+            },
+            router: Some(router_ns),
+        })
+    }
+}
@@ -0,0 +1,503 @@
+//! A fake host that answers scanner probes over AF_XDP.
+//!
+//! The responder answers TCP SYNs with a SYN-ACK or RST according to a `PortPolicy`, ICMP and
+//! ICMPv6 echo requests with echo replies, and ARP requests and IPv6 neighbor solicitations for
+//! its own addresses, which is enough for a scanner on the other end of a veth pair to treat
+//! it as a real host. Frames tagged with 802.1Q or 802.1ad VLAN tags are answered with the same
+//! tags.
+use etherparse::PacketBuilder;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use xdpscan::packet::{checksum_add, checksum_fold};
+use xsk_rs::{
+    socket::{Config as SocketConfig, *},
+    umem::{Config as UmemConfig, *},
+    BindFlags, LibbpfFlags, XdpFlags,
+};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLANS: [u16; 2] = [0x8100, 0x88a8];
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_ICMPV6: u8 = 58;
+
+/// How the responder answers a SYN to a port.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PortReply {
+    SynAck,
+    Rst,
+    /// Never answer, as if a firewall dropped the probe.
+    Drop,
+}
+
+/// Per port replies for TCP SYNs.
+#[derive(Clone, Debug)]
+pub struct PortPolicy {
+    ports: HashMap<u16, PortReply>,
+    default: PortReply,
+}
+
+impl PortPolicy {
+    pub fn all_open() -> Self {
+        PortPolicy {
+            ports: HashMap::new(),
+            default: PortReply::SynAck,
+        }
+    }
+
+    pub fn all_closed() -> Self {
+        PortPolicy {
+            ports: HashMap::new(),
+            default: PortReply::Rst,
+        }
+    }
+
+    /// Answers SYN-ACK on `ports` and RST everywhere else.
+    pub fn open(ports: &[u16]) -> Self {
+        let mut policy = PortPolicy::all_closed();
+        for &port in ports {
+            policy = policy.with(port, PortReply::SynAck);
+        }
+        policy
+    }
+
+    pub fn with(mut self, port: u16, reply: PortReply) -> Self {
+        self.ports.insert(port, reply);
+        self
+    }
+
+    pub fn reply(&self, port: u16) -> PortReply {
+        *self.ports.get(&port).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ResponderConfig {
+    /// MAC address used in ARP replies and neighbor advertisements.
+    pub mac: [u8; 6],
+    /// Addresses the responder owns. If empty, it answers for every address.
+    pub ips: Vec<IpAddr>,
+    pub policy: PortPolicy,
+}
+
+impl ResponderConfig {
+    fn owns(&self, ip: IpAddr) -> bool {
+        self.ips.is_empty() || self.ips.contains(&ip)
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn ipv6_pseudo_header_sum(src: &[u8], dst: &[u8], len: usize, next_header: u8) -> u32 {
+    let sum = checksum_add(0, src);
+    let sum = checksum_add(sum, dst);
+    sum + len as u32 + next_header as u32
+}
+
+fn swap_macs(frame: &mut [u8]) {
+    let (dst, rest) = frame.split_at_mut(6);
+    dst.swap_with_slice(&mut rest[..6]);
+}
+
+/// Computes the reply to `frame`, if the responder should answer it.
+pub fn respond(config: &ResponderConfig, frame: &[u8]) -> Option<Vec<u8>> {
+    if frame.len() < 14 {
+        return None;
+    }
+    let mut tags_len = 0;
+    while frame.len() >= 18 + tags_len && ETHERTYPE_VLANS.contains(&be16(frame, 12 + tags_len)) {
+        tags_len += 4;
+    }
+    if tags_len > 0 {
+        let untagged = [&frame[..12], &frame[12 + tags_len..]].concat();
+        let mut reply = respond(config, &untagged)?;
+        reply.splice(12..12, frame[12..12 + tags_len].iter().copied());
+        return Some(reply);
+    }
+    match be16(frame, 12) {
+        ETHERTYPE_ARP => respond_arp(config, frame),
+        ETHERTYPE_IPV4 => respond_ipv4(config, frame),
+        ETHERTYPE_IPV6 => respond_ipv6(config, frame),
+        _ => None,
+    }
+}
+
+fn respond_arp(config: &ResponderConfig, frame: &[u8]) -> Option<Vec<u8>> {
+    let arp = frame.get(14..42)?;
+    // Ethernet/IPv4 requests only.
+    if be16(arp, 0) != 1 || be16(arp, 2) != ETHERTYPE_IPV4 || be16(arp, 6) != 1 {
+        return None;
+    }
+    let target_ip = Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]);
+    if !config.owns(IpAddr::V4(target_ip)) {
+        return None;
+    }
+
+    let mut reply = frame[..42].to_vec();
+    reply[..6].copy_from_slice(&arp[8..14]);
+    reply[6..12].copy_from_slice(&config.mac);
+    let arp_reply = &mut reply[14..];
+    arp_reply[6..8].copy_from_slice(&2u16.to_be_bytes());
+    arp_reply[8..14].copy_from_slice(&config.mac);
+    arp_reply[14..18].copy_from_slice(&arp[24..28]);
+    arp_reply[18..24].copy_from_slice(&arp[8..14]);
+    arp_reply[24..28].copy_from_slice(&arp[14..18]);
+    Some(reply)
+}
+
+fn respond_ipv4(config: &ResponderConfig, frame: &[u8]) -> Option<Vec<u8>> {
+    let ip = frame.get(14..34)?;
+    let header_len = ((ip[0] & 0x0f) as usize) * 4;
+    let total_len = be16(ip, 2) as usize;
+    let src = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let dst = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+    if !config.owns(IpAddr::V4(dst)) || header_len < 20 || total_len < header_len {
+        return None;
+    }
+    let payload = frame.get(14 + header_len..14 + total_len)?;
+
+    match ip[9] {
+        IPPROTO_ICMP => {
+            // Echo request
+            if payload.len() < 8 || payload[0] != 8 {
+                return None;
+            }
+            let mut reply = frame[..14 + total_len].to_vec();
+            swap_macs(&mut reply);
+            let ip_reply = &mut reply[14..];
+            ip_reply[8] = 64;
+            ip_reply[12..16].copy_from_slice(&dst.octets());
+            ip_reply[16..20].copy_from_slice(&src.octets());
+            ip_reply[10..12].copy_from_slice(&[0, 0]);
+            let ip_checksum = checksum_fold(checksum_add(0, &ip_reply[..header_len]));
+            ip_reply[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+            let icmp = &mut ip_reply[header_len..];
+            icmp[0] = 0;
+            icmp[2..4].copy_from_slice(&[0, 0]);
+            let icmp_checksum = checksum_fold(checksum_add(0, icmp));
+            icmp[2..4].copy_from_slice(&icmp_checksum.to_be_bytes());
+            Some(reply)
+        }
+        IPPROTO_TCP => respond_tcp(config, frame, IpAddr::V4(src), IpAddr::V4(dst), payload),
+        _ => None,
+    }
+}
+
+fn respond_ipv6(config: &ResponderConfig, frame: &[u8]) -> Option<Vec<u8>> {
+    let ip = frame.get(14..54)?;
+    let payload_len = be16(ip, 4) as usize;
+    let mut src_octets = [0; 16];
+    src_octets.copy_from_slice(&ip[8..24]);
+    let mut dst_octets = [0; 16];
+    dst_octets.copy_from_slice(&ip[24..40]);
+    let src = Ipv6Addr::from(src_octets);
+    let dst = Ipv6Addr::from(dst_octets);
+    let payload = frame.get(54..54 + payload_len)?;
+
+    match ip[6] {
+        IPPROTO_ICMPV6 if payload.len() >= 8 && payload[0] == 128 => {
+            // Echo request
+            if !config.owns(IpAddr::V6(dst)) {
+                return None;
+            }
+            let mut reply = frame[..54 + payload_len].to_vec();
+            swap_macs(&mut reply);
+            let ip_reply = &mut reply[14..];
+            ip_reply[7] = 64;
+            ip_reply[8..24].copy_from_slice(&dst_octets);
+            ip_reply[24..40].copy_from_slice(&src_octets);
+
+            let icmp = &mut ip_reply[40..];
+            icmp[0] = 129;
+            icmp[2..4].copy_from_slice(&[0, 0]);
+            let sum = ipv6_pseudo_header_sum(&dst_octets, &src_octets, icmp.len(), IPPROTO_ICMPV6);
+            let icmp_checksum = checksum_fold(checksum_add(sum, icmp));
+            icmp[2..4].copy_from_slice(&icmp_checksum.to_be_bytes());
+            Some(reply)
+        }
+        IPPROTO_ICMPV6 if payload.len() >= 24 && payload[0] == 135 => {
+            // Neighbor solicitation, answered with a solicited neighbor advertisement that
+            // carries our link-layer address.
+            let target = &payload[8..24];
+            let mut target_octets = [0; 16];
+            target_octets.copy_from_slice(target);
+            if !config.owns(IpAddr::V6(Ipv6Addr::from(target_octets))) {
+                return None;
+            }
+
+            let mut reply = Vec::with_capacity(14 + 40 + 32);
+            reply.extend_from_slice(&frame[6..12]);
+            reply.extend_from_slice(&config.mac);
+            reply.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+
+            reply.extend_from_slice(&[0x60, 0, 0, 0]);
+            reply.extend_from_slice(&32u16.to_be_bytes());
+            reply.push(IPPROTO_ICMPV6);
+            reply.push(255);
+            reply.extend_from_slice(&target_octets);
+            reply.extend_from_slice(&src_octets);
+
+            let mut icmp = vec![136, 0, 0, 0, 0x60, 0, 0, 0];
+            icmp.extend_from_slice(&target_octets);
+            icmp.extend_from_slice(&[2, 1]);
+            icmp.extend_from_slice(&config.mac);
+            let sum =
+                ipv6_pseudo_header_sum(&target_octets, &src_octets, icmp.len(), IPPROTO_ICMPV6);
+            let icmp_checksum = checksum_fold(checksum_add(sum, &icmp));
+            icmp[2..4].copy_from_slice(&icmp_checksum.to_be_bytes());
+            reply.extend_from_slice(&icmp);
+            Some(reply)
+        }
+        IPPROTO_TCP if config.owns(IpAddr::V6(dst)) => {
+            respond_tcp(config, frame, IpAddr::V6(src), IpAddr::V6(dst), payload)
+        }
+        _ => None,
+    }
+}
+
+fn respond_tcp(
+    config: &ResponderConfig,
+    frame: &[u8],
+    src: IpAddr,
+    dst: IpAddr,
+    tcp: &[u8],
+) -> Option<Vec<u8>> {
+    if tcp.len() < 20 {
+        return None;
+    }
+    let src_port = be16(tcp, 0);
+    let dst_port = be16(tcp, 2);
+    let seq = be32(tcp, 4);
+    let flags = tcp[13];
+    let (syn, ack) = (flags & 0x02 != 0, flags & 0x10 != 0);
+    if !syn || ack {
+        return None;
+    }
+
+    let mut dst_mac = [0; 6];
+    dst_mac.copy_from_slice(&frame[6..12]);
+    let mut src_mac = [0; 6];
+    src_mac.copy_from_slice(&frame[..6]);
+    let builder = PacketBuilder::ethernet2(src_mac, dst_mac);
+    let builder = match (dst, src) {
+        (IpAddr::V4(reply_src), IpAddr::V4(reply_dst)) => {
+            builder.ipv4(reply_src.octets(), reply_dst.octets(), 64)
+        }
+        (IpAddr::V6(reply_src), IpAddr::V6(reply_dst)) => {
+            builder.ipv6(reply_src.octets(), reply_dst.octets(), 64)
+        }
+        _ => return None,
+    };
+    let builder = builder.tcp(dst_port, src_port, 0, 65535);
+    let builder = match config.policy.reply(dst_port) {
+        PortReply::SynAck => builder.syn().ack(seq.wrapping_add(1)),
+        PortReply::Rst => builder.rst().ack(seq.wrapping_add(1)),
+        PortReply::Drop => return None,
+    };
+
+    let mut result = Vec::<u8>::with_capacity(builder.size(0));
+    builder.write(&mut result, &[]).unwrap();
+    Some(result)
+}
+
+/// An AF_XDP socket that answers frames arriving on one queue of an interface.
+pub struct Responder {
+    config: ResponderConfig,
+    umem: Umem<'static>,
+    fill_q: FillQueue<'static>,
+    comp_q: CompQueue<'static>,
+    tx_q: TxQueue<'static>,
+    rx_q: RxQueue<'static>,
+    rx_frames: Vec<FrameDesc<'static>>,
+    tx_frames: Vec<FrameDesc<'static>>,
+    completed: Vec<FrameDesc<'static>>,
+    next_tx: usize,
+    outstanding: usize,
+}
+
+impl Responder {
+    pub fn new(ifname: &str, queue_id: u32, config: ResponderConfig) -> Self {
+        let rx_q_size: u32 = 4096;
+        let tx_q_size: u32 = 4096;
+        let comp_q_size: u32 = 4096;
+        let fill_q_size: u32 = 4096;
+        let frame_size: u32 = 2048;
+        let frame_count = 2048;
+
+        let umem_config = UmemConfig::new(
+            NonZeroU32::new(frame_count).unwrap(),
+            NonZeroU32::new(frame_size).unwrap(),
+            fill_q_size,
+            comp_q_size,
+            0,
+            false,
+        )
+        .unwrap();
+
+        let (mut umem, mut fill_q, comp_q, mut frames) = Umem::builder(umem_config)
+            .create_mmap()
+            .expect("failed to create mmap area")
+            .create_umem()
+            .expect("failed to create umem");
+
+        let socket_config = SocketConfig::new(
+            rx_q_size,
+            tx_q_size,
+            LibbpfFlags::empty(),
+            XdpFlags::empty(),
+            BindFlags::XDP_USE_NEED_WAKEUP,
+        )
+        .unwrap();
+
+        let (tx_q, rx_q) = Socket::new(socket_config, &mut umem, ifname, queue_id)
+            .expect("failed to build socket");
+
+        // Frames given to the fill queue and frames used for replies must never overlap.
+        let rx_frames = frames.split_off(frames.len() / 2);
+        let tx_frames = frames;
+        assert_eq!(unsafe { fill_q.produce(&rx_frames[..]) }, rx_frames.len());
+
+        Responder {
+            config,
+            umem,
+            fill_q,
+            comp_q,
+            tx_q,
+            rx_q,
+            rx_frames,
+            completed: tx_frames.clone(),
+            tx_frames,
+            next_tx: 0,
+            outstanding: 0,
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) {
+        while self.outstanding == self.tx_frames.len() {
+            let completed = self.comp_q.consume(&mut self.completed[..self.outstanding]);
+            self.outstanding -= completed;
+            if completed == 0 && self.tx_q.needs_wakeup() {
+                self.tx_q.wakeup().unwrap();
+            }
+        }
+
+        let next_tx = self.next_tx;
+        unsafe {
+            self.umem
+                .write_to_umem_checked(&mut self.tx_frames[next_tx], frame)
+                .unwrap();
+        };
+        while unsafe {
+            self.tx_q
+                .produce_and_wakeup(&self.tx_frames[next_tx..next_tx + 1])
+                .unwrap()
+        } != 1
+        {}
+
+        self.outstanding += 1;
+        self.next_tx = (next_tx + 1) % self.tx_frames.len();
+
+        let completed = self.comp_q.consume(&mut self.completed[..self.outstanding]);
+        self.outstanding -= completed;
+    }
+
+    /// Answers frames until `max_replies` replies have been sent, if given, or until `stop`
+    /// is set. Returns the number of replies sent.
+    pub fn run(&mut self, max_replies: Option<usize>, stop: &AtomicBool) -> usize {
+        let poll_ms_timeout: i32 = 100;
+        let mut replies_sent = 0;
+
+        while !stop.load(Ordering::Relaxed)
+            && max_replies.map_or(true, |max_replies| replies_sent < max_replies)
+        {
+            match self
+                .rx_q
+                .poll_and_consume(&mut self.rx_frames[..], poll_ms_timeout)
+                .unwrap()
+            {
+                0 => {
+                    // No packets consumed, wake up fill queue if required
+                    if self.fill_q.needs_wakeup() {
+                        self.fill_q.wakeup(self.rx_q.fd(), poll_ms_timeout).unwrap();
+                    }
+                }
+                frames_rcvd => {
+                    let mut replies = vec![];
+                    for recv_frame in self.rx_frames.iter().take(frames_rcvd) {
+                        let frame_ref = unsafe {
+                            self.umem
+                                .read_from_umem_checked(&recv_frame.addr(), &recv_frame.len())
+                                .unwrap()
+                        };
+                        replies.extend(respond(&self.config, frame_ref));
+                    }
+
+                    for reply in replies {
+                        self.transmit(&reply);
+                        replies_sent += 1;
+                    }
+
+                    // Add frames back to fill queue
+                    while unsafe {
+                        self.fill_q
+                            .produce_and_wakeup(
+                                &self.rx_frames[..frames_rcvd],
+                                self.rx_q.fd(),
+                                poll_ms_timeout,
+                            )
+                            .unwrap()
+                    } != frames_rcvd
+                    {
+                        // Loop until frames added to the fill ring.
+                    }
+                }
+            }
+        }
+
+        replies_sent
+    }
+
+    /// Runs the responder on its own thread.
+    pub fn spawn(mut self, max_replies: Option<usize>) -> ResponderHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || self.run(max_replies, &thread_stop));
+        ResponderHandle { stop, handle }
+    }
+}
+
+pub struct ResponderHandle {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<usize>,
+}
+
+impl ResponderHandle {
+    /// Waits for the responder to send its maximum number of replies.
+    pub fn join(self) -> usize {
+        self.handle.join().expect("responder thread panicked")
+    }
+
+    /// Stops the responder and returns the number of replies it sent.
+    pub fn stop(self) -> usize {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join()
+    }
+}